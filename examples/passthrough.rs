@@ -0,0 +1,93 @@
+#[cfg(target_os = "linux")]
+use clap::{crate_version, App, Arg};
+#[cfg(target_os = "linux")]
+use fuser::passthrough::PassthroughFs;
+#[cfg(target_os = "linux")]
+use fuser::MountOption;
+#[cfg(target_os = "linux")]
+use log::LevelFilter;
+#[cfg(target_os = "linux")]
+use std::fs::File;
+#[cfg(target_os = "linux")]
+use std::io::{self, BufRead, BufReader};
+
+#[cfg(target_os = "linux")]
+fn fuse_allow_other_enabled() -> io::Result<bool> {
+    let file = File::open("/etc/fuse.conf")?;
+    for line in BufReader::new(file).lines() {
+        if line?.trim_start().starts_with("user_allow_other") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    let matches = App::new("Fuser passthrough")
+        .version(crate_version!())
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .value_name("DIR")
+                .required(true)
+                .help("Set the local directory to mirror")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mount-point")
+                .long("mount-point")
+                .value_name("MOUNT_POINT")
+                .required(true)
+                .help("Mount FUSE at given path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("direct-io")
+                .long("direct-io")
+                .help("Mount FUSE with direct IO"),
+        )
+        .arg(
+            Arg::with_name("v")
+                .short("v")
+                .multiple(true)
+                .help("Sets the level of verbosity"),
+        )
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("v") {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    env_logger::builder()
+        .format_timestamp_nanos()
+        .filter_level(log_level)
+        .init();
+
+    // The kernel applies the umask of the calling process already
+    unsafe { libc::umask(0) };
+
+    let data_dir = matches.value_of("data-dir").unwrap();
+    let mountpoint = matches.value_of("mount-point").unwrap();
+    let mut options = vec![
+        MountOption::FSName("fuser".to_string()),
+        MountOption::AutoUnmount,
+        MountOption::DefaultPermissions,
+    ];
+    if matches.is_present("direct-io") {
+        options.push(MountOption::DirectIO);
+    }
+    if unsafe { libc::geteuid() } == 0 || fuse_allow_other_enabled().unwrap_or(false) {
+        options.push(MountOption::AllowOther);
+    }
+    let filesystem = PassthroughFs::new(data_dir).unwrap();
+    fuser::mount2(filesystem, mountpoint, &options).unwrap();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("The passthrough filesystem is only supported on Linux");
+}
@@ -2,8 +2,9 @@
 
 use clap::{crate_version, App, Arg};
 use fuser::{
-    Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
-    ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, Request, FUSE_ROOT_ID,
+    Errno, Filesystem, KernelConfig, MountOption, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite, Request,
+    WriteFlags, FUSE_ROOT_ID,
 };
 use log::LevelFilter;
 use log::{debug, error, warn};
@@ -13,7 +14,6 @@ use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
-use std::os::raw::c_int;
 use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -155,14 +155,14 @@ impl SimpleFS {
             .join(inode.to_string())
     }
 
-    fn get_directory_content(&self, inode: Inode) -> Result<DirectoryDescriptor, c_int> {
+    fn get_directory_content(&self, inode: Inode) -> Result<DirectoryDescriptor, Errno> {
         let path = Path::new(&self.data_dir)
             .join("contents")
             .join(inode.to_string());
         if let Ok(file) = File::open(&path) {
             Ok(bincode::deserialize_from(file).unwrap())
         } else {
-            Err(libc::ENOENT)
+            Err(Errno::ENOENT)
         }
     }
 
@@ -179,14 +179,14 @@ impl SimpleFS {
         bincode::serialize_into(file, &entries).unwrap();
     }
 
-    fn get_inode(&self, inode: Inode) -> Result<InodeAttributes, c_int> {
+    fn get_inode(&self, inode: Inode) -> Result<InodeAttributes, Errno> {
         let path = Path::new(&self.data_dir)
             .join("inodes")
             .join(inode.to_string());
         if let Ok(file) = File::open(&path) {
             Ok(bincode::deserialize_from(file).unwrap())
         } else {
-            Err(libc::ENOENT)
+            Err(Errno::ENOENT)
         }
     }
 
@@ -228,9 +228,9 @@ impl SimpleFS {
         new_length: u64,
         uid: u32,
         gid: u32,
-    ) -> Result<InodeAttributes, c_int> {
+    ) -> Result<InodeAttributes, Errno> {
         if new_length > MAX_FILE_SIZE {
-            return Err(libc::EFBIG);
+            return Err(Errno::EFBIG);
         }
 
         let mut attrs = self.get_inode(inode)?;
@@ -243,7 +243,7 @@ impl SimpleFS {
             gid,
             libc::W_OK as u32,
         ) {
-            return Err(libc::EACCES);
+            return Err(Errno::EACCES);
         }
 
         let path = self.content_path(inode);
@@ -259,19 +259,19 @@ impl SimpleFS {
         Ok(attrs)
     }
 
-    fn lookup_name(&self, parent: u64, name: &OsStr) -> Result<InodeAttributes, c_int> {
+    fn lookup_name(&self, parent: u64, name: &OsStr) -> Result<InodeAttributes, Errno> {
         let name = if let Some(value) = name.to_str() {
             value
         } else {
             error!("Path component is not UTF-8");
-            return Err(libc::EINVAL);
+            return Err(Errno::EINVAL);
         };
 
         let entries = self.get_directory_content(parent)?;
         if let Some((inode, _)) = entries.get(name) {
             return self.get_inode(*inode);
         } else {
-            return Err(libc::ENOENT);
+            return Err(Errno::ENOENT);
         }
     }
 
@@ -282,16 +282,16 @@ impl SimpleFS {
         name: &OsStr,
         inode: u64,
         kind: FileKind,
-    ) -> Result<(), c_int> {
+    ) -> Result<(), Errno> {
         if self.lookup_name(parent, name).is_ok() {
-            return Err(libc::EEXIST);
+            return Err(Errno::EEXIST);
         }
 
         let name = if let Some(value) = name.to_str() {
             value
         } else {
             error!("Path component is not UTF-8");
-            return Err(libc::EINVAL);
+            return Err(Errno::EINVAL);
         };
 
         let mut parent_attrs = self.get_inode(parent)?;
@@ -304,7 +304,7 @@ impl SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            return Err(libc::EACCES);
+            return Err(Errno::EACCES);
         }
         parent_attrs.last_modified = SystemTime::now();
         parent_attrs.last_metadata_changed = SystemTime::now();
@@ -319,7 +319,7 @@ impl SimpleFS {
 }
 
 impl Filesystem for SimpleFS {
-    fn init(&mut self, _req: &Request) -> Result<(), c_int> {
+    fn init(&self, _req: &Request, _config: &mut KernelConfig) -> Result<(), Errno> {
         fs::create_dir_all(Path::new(&self.data_dir).join("inodes")).unwrap();
         fs::create_dir_all(Path::new(&self.data_dir).join("contents")).unwrap();
         if self.get_inode(FUSE_ROOT_ID).is_err() {
@@ -346,11 +346,11 @@ impl Filesystem for SimpleFS {
         Ok(())
     }
 
-    fn destroy(&mut self, _req: &Request) {}
+    fn destroy(&self, _req: &Request) {}
 
-    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+    fn lookup(&self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         if name.len() > MAX_NAME_LENGTH as usize {
-            reply.error(libc::ENAMETOOLONG);
+            reply.error(Errno::ENAMETOOLONG);
             return;
         }
         let parent_attrs = self.get_inode(parent).unwrap();
@@ -362,7 +362,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::X_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -372,9 +372,9 @@ impl Filesystem for SimpleFS {
         }
     }
 
-    fn forget(&mut self, _req: &Request, _ino: u64, _nlookup: u64) {}
+    fn forget(&self, _req: &Request, _ino: u64, _nlookup: u64) {}
 
-    fn getattr(&mut self, _req: &Request, inode: u64, reply: ReplyAttr) {
+    fn getattr(&self, _req: &Request, inode: u64, reply: ReplyAttr) {
         match self.get_inode(inode) {
             Ok(attrs) => reply.attr(&Duration::new(0, 0), &attrs.into()),
             Err(error_code) => reply.error(error_code),
@@ -382,7 +382,7 @@ impl Filesystem for SimpleFS {
     }
 
     fn setattr(
-        &mut self,
+        &self,
         req: &Request,
         inode: u64,
         mode: Option<u32>,
@@ -411,7 +411,7 @@ impl Filesystem for SimpleFS {
         if let Some(mode) = mode {
             debug!("chmod() called with {:?}, {:o}", inode, mode);
             if req.uid() != 0 && req.uid() != attrs.uid {
-                reply.error(libc::EPERM);
+                reply.error(Errno::EPERM);
                 return;
             }
             attrs.mode = mode as u16;
@@ -426,7 +426,7 @@ impl Filesystem for SimpleFS {
             if let Some(gid) = gid {
                 // Non-root users can only change gid to a group they're in
                 if req.uid() != 0 && !get_groups(req.pid()).contains(&gid) {
-                    reply.error(libc::EPERM);
+                    reply.error(Errno::EPERM);
                     return;
                 }
             }
@@ -435,13 +435,13 @@ impl Filesystem for SimpleFS {
                     // but no-op changes by the owner are not an error
                     && !(uid == attrs.uid && req.uid() == attrs.uid)
                 {
-                    reply.error(libc::EPERM);
+                    reply.error(Errno::EPERM);
                     return;
                 }
             }
             // Only owner may change the group
             if gid.is_some() && req.uid() != 0 && req.uid() != attrs.uid {
-                reply.error(libc::EPERM);
+                reply.error(Errno::EPERM);
                 return;
             }
 
@@ -470,7 +470,7 @@ impl Filesystem for SimpleFS {
                         return;
                     }
                 } else {
-                    reply.error(libc::EACCES);
+                    reply.error(Errno::EACCES);
                     return;
                 }
             } else if let Err(error_code) = self.truncate(inode, size, req.uid(), req.gid()) {
@@ -489,7 +489,7 @@ impl Filesystem for SimpleFS {
             let mtime = if mtime_now { Some(now) } else { mtime };
 
             if attrs.uid != req.uid() && req.uid() != 0 && (!atime_now || !mtime_now) {
-                reply.error(libc::EPERM);
+                reply.error(Errno::EPERM);
                 return;
             }
 
@@ -503,7 +503,7 @@ impl Filesystem for SimpleFS {
                     libc::W_OK as u32,
                 )
             {
-                reply.error(libc::EACCES);
+                reply.error(Errno::EACCES);
                 return;
             }
 
@@ -521,7 +521,7 @@ impl Filesystem for SimpleFS {
         return;
     }
 
-    fn readlink(&mut self, _req: &Request, inode: u64, reply: ReplyData) {
+    fn readlink(&self, _req: &Request, inode: u64, reply: ReplyData) {
         debug!("readlink() called on {:?}", inode);
         let path = self.content_path(inode);
         if let Ok(mut file) = File::open(&path) {
@@ -530,12 +530,12 @@ impl Filesystem for SimpleFS {
             file.read_exact(&mut buffer).unwrap();
             reply.data(&buffer);
         } else {
-            reply.error(libc::ENOENT);
+            reply.error(Errno::ENOENT);
         }
     }
 
     fn mknod(
-        &mut self,
+        &self,
         req: &Request,
         parent: u64,
         name: &OsStr,
@@ -551,12 +551,12 @@ impl Filesystem for SimpleFS {
         {
             // TODO
             warn!("mknod() implementation is incomplete. Only supports regular files, symlinks, and directories. Got {:o}", mode);
-            reply.error(libc::ENOSYS);
+            reply.error(Errno::ENOSYS);
             return;
         }
 
         if self.lookup_name(parent, name).is_ok() {
-            reply.error(libc::EEXIST);
+            reply.error(Errno::EEXIST);
             return;
         }
 
@@ -564,7 +564,7 @@ impl Filesystem for SimpleFS {
             value
         } else {
             error!("Path component is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
 
@@ -584,7 +584,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
         parent_attrs.last_modified = SystemTime::now();
@@ -625,10 +625,10 @@ impl Filesystem for SimpleFS {
         reply.entry(&Duration::new(0, 0), &attrs.into(), 0);
     }
 
-    fn mkdir(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
+    fn mkdir(&self, req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
         debug!("mkdir() called with {:?} {:?} {:o}", parent, name, mode);
         if self.lookup_name(parent, name).is_ok() {
-            reply.error(libc::EEXIST);
+            reply.error(Errno::EEXIST);
             return;
         }
 
@@ -636,7 +636,7 @@ impl Filesystem for SimpleFS {
             value
         } else {
             error!("Path component is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
 
@@ -656,7 +656,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
         parent_attrs.last_modified = SystemTime::now();
@@ -693,7 +693,7 @@ impl Filesystem for SimpleFS {
         reply.entry(&Duration::new(0, 0), &attrs.into(), 0);
     }
 
-    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+    fn unlink(&self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
         debug!("unlink() called with {:?} {:?}", parent, name);
         let mut attrs = match self.lookup_name(parent, name) {
             Ok(attrs) => attrs,
@@ -707,7 +707,7 @@ impl Filesystem for SimpleFS {
             value
         } else {
             error!("Path component is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
 
@@ -727,7 +727,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -738,7 +738,7 @@ impl Filesystem for SimpleFS {
             && uid != parent_attrs.uid
             && uid != attrs.uid
         {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -758,7 +758,7 @@ impl Filesystem for SimpleFS {
         reply.ok();
     }
 
-    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+    fn rmdir(&self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
         debug!("rmdir() called with {:?} {:?}", parent, name);
         let mut attrs = match self.lookup_name(parent, name) {
             Ok(attrs) => attrs,
@@ -772,7 +772,7 @@ impl Filesystem for SimpleFS {
             value
         } else {
             error!("Path component is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
 
@@ -786,7 +786,7 @@ impl Filesystem for SimpleFS {
 
         // Directories always have a self and parent link
         if self.get_directory_content(attrs.inode).unwrap().len() > 2 {
-            reply.error(libc::ENOTEMPTY);
+            reply.error(Errno::ENOTEMPTY);
             return;
         }
         if !check_access(
@@ -797,7 +797,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -807,7 +807,7 @@ impl Filesystem for SimpleFS {
             && req.uid() != parent_attrs.uid
             && req.uid() != attrs.uid
         {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -827,20 +827,13 @@ impl Filesystem for SimpleFS {
         reply.ok();
     }
 
-    fn symlink(
-        &mut self,
-        req: &Request,
-        parent: u64,
-        name: &OsStr,
-        link: &Path,
-        reply: ReplyEntry,
-    ) {
+    fn symlink(&self, req: &Request, parent: u64, name: &OsStr, link: &Path, reply: ReplyEntry) {
         debug!("symlink() called with {:?} {:?} {:?}", parent, name, link);
         let link = if let Some(value) = link.to_str() {
             value
         } else {
             error!("Link is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
 
@@ -879,26 +872,31 @@ impl Filesystem for SimpleFS {
     }
 
     fn rename(
-        &mut self,
+        &self,
         req: &Request,
         parent: u64,
         name: &OsStr,
         new_parent: u64,
         new_name: &OsStr,
+        flags: u32,
         reply: ReplyEmpty,
     ) {
+        if flags != 0 {
+            reply.error(Errno::EINVAL);
+            return;
+        }
         let name_str = if let Some(value) = name.to_str() {
             value
         } else {
             error!("Path component is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
         let new_name_str = if let Some(value) = new_name.to_str() {
             value
         } else {
             error!("Path component is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
 
@@ -926,7 +924,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -936,7 +934,7 @@ impl Filesystem for SimpleFS {
             && req.uid() != parent_attrs.uid
             && req.uid() != inode_attrs.uid
         {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -956,7 +954,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -967,7 +965,7 @@ impl Filesystem for SimpleFS {
                     && req.uid() != new_parent_attrs.uid
                     && req.uid() != existing_attrs.uid
                 {
-                    reply.error(libc::EACCES);
+                    reply.error(Errno::EACCES);
                     return;
                 }
             }
@@ -982,7 +980,7 @@ impl Filesystem for SimpleFS {
                     .len()
                     > 2
             {
-                reply.error(libc::ENOTEMPTY);
+                reply.error(Errno::ENOTEMPTY);
                 return;
             }
         }
@@ -1000,7 +998,7 @@ impl Filesystem for SimpleFS {
                 libc::W_OK as u32,
             )
         {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -1050,7 +1048,7 @@ impl Filesystem for SimpleFS {
     }
 
     fn link(
-        &mut self,
+        &self,
         req: &Request,
         inode: u64,
         new_parent: u64,
@@ -1078,13 +1076,13 @@ impl Filesystem for SimpleFS {
         }
     }
 
-    fn open(&mut self, req: &Request, inode: u64, flags: u32, reply: ReplyOpen) {
+    fn open(&self, req: &Request, inode: u64, flags: u32, reply: ReplyOpen) {
         debug!("open() called for {:?}", inode);
         let (access_mask, read, write) = match flags as i32 & libc::O_ACCMODE {
             libc::O_RDONLY => {
                 // Behavior is undefined, but most filesystems return EACCES
                 if flags as i32 & libc::O_TRUNC != 0 {
-                    reply.error(libc::EACCES);
+                    reply.error(Errno::EACCES);
                     return;
                 }
                 if flags as i32 & FMODE_EXEC != 0 {
@@ -1098,7 +1096,7 @@ impl Filesystem for SimpleFS {
             libc::O_RDWR => (libc::R_OK | libc::W_OK, true, true),
             // Exactly one access mode flag must be specified
             _ => {
-                reply.error(libc::EINVAL);
+                reply.error(Errno::EINVAL);
                 return;
             }
         };
@@ -1116,7 +1114,7 @@ impl Filesystem for SimpleFS {
                     reply.opened(self.allocate_next_file_handle(read, write), 0);
                     return;
                 } else {
-                    reply.error(libc::EACCES);
+                    reply.error(Errno::EACCES);
                     return;
                 }
             }
@@ -1124,19 +1122,11 @@ impl Filesystem for SimpleFS {
         }
     }
 
-    fn read(
-        &mut self,
-        _req: &Request,
-        inode: u64,
-        fh: u64,
-        offset: i64,
-        size: u32,
-        reply: ReplyData,
-    ) {
+    fn read(&self, _req: &Request, inode: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
         debug!("read() called on {:?}", inode);
         assert!(offset >= 0);
         if !self.check_file_handle_read(fh) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -1150,24 +1140,26 @@ impl Filesystem for SimpleFS {
             file.read_exact_at(&mut buffer, offset as u64).unwrap();
             reply.data(&buffer);
         } else {
-            reply.error(libc::ENOENT);
+            reply.error(Errno::ENOENT);
         }
     }
 
     fn write(
-        &mut self,
+        &self,
         _req: &Request,
         inode: u64,
         fh: u64,
         offset: i64,
         data: &[u8],
+        _write_flags: WriteFlags,
         _flags: u32,
+        _lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
         debug!("write() called with {:?}", inode);
         assert!(offset >= 0);
         if !self.check_file_handle_write(fh) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
 
@@ -1186,17 +1178,17 @@ impl Filesystem for SimpleFS {
 
             reply.written(data.len() as u32);
         } else {
-            reply.error(libc::EBADF);
+            reply.error(Errno::EBADF);
         }
     }
 
-    fn opendir(&mut self, req: &Request, inode: u64, flags: u32, reply: ReplyOpen) {
+    fn opendir(&self, req: &Request, inode: u64, flags: u32, reply: ReplyOpen) {
         debug!("opendir() called on {:?}", inode);
         let (access_mask, read, write) = match flags as i32 & libc::O_ACCMODE {
             libc::O_RDONLY => {
                 // Behavior is undefined, but most filesystems return EACCES
                 if flags as i32 & libc::O_TRUNC != 0 {
-                    reply.error(libc::EACCES);
+                    reply.error(Errno::EACCES);
                     return;
                 }
                 (libc::R_OK, true, false)
@@ -1205,7 +1197,7 @@ impl Filesystem for SimpleFS {
             libc::O_RDWR => (libc::R_OK | libc::W_OK, true, true),
             // Exactly one access mode flag must be specified
             _ => {
-                reply.error(libc::EINVAL);
+                reply.error(Errno::EINVAL);
                 return;
             }
         };
@@ -1223,7 +1215,7 @@ impl Filesystem for SimpleFS {
                     reply.opened(self.allocate_next_file_handle(read, write), 0);
                     return;
                 } else {
-                    reply.error(libc::EACCES);
+                    reply.error(Errno::EACCES);
                     return;
                 }
             }
@@ -1232,7 +1224,7 @@ impl Filesystem for SimpleFS {
     }
 
     fn readdir(
-        &mut self,
+        &self,
         _req: &Request,
         inode: u64,
         _fh: u64,
@@ -1263,7 +1255,7 @@ impl Filesystem for SimpleFS {
         reply.ok();
     }
 
-    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+    fn statfs(&self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
         warn!("statfs() implementation is a stub");
         // TODO: real implementation of this
         reply.statfs(
@@ -1278,14 +1270,14 @@ impl Filesystem for SimpleFS {
         );
     }
 
-    fn access(&mut self, req: &Request, inode: u64, mask: u32, reply: ReplyEmpty) {
+    fn access(&self, req: &Request, inode: u64, mask: u32, reply: ReplyEmpty) {
         debug!("access() called with {:?} {:?}", inode, mask);
         match self.get_inode(inode) {
             Ok(attr) => {
                 if check_access(attr.uid, attr.gid, attr.mode, req.uid(), req.gid(), mask) {
                     reply.ok();
                 } else {
-                    reply.error(libc::EACCES);
+                    reply.error(Errno::EACCES);
                 }
             }
             Err(error_code) => reply.error(error_code),
@@ -1293,7 +1285,7 @@ impl Filesystem for SimpleFS {
     }
 
     fn create(
-        &mut self,
+        &self,
         req: &Request,
         parent: u64,
         name: &OsStr,
@@ -1303,7 +1295,7 @@ impl Filesystem for SimpleFS {
     ) {
         debug!("create() called with {:?} {:?}", parent, name);
         if self.lookup_name(parent, name).is_ok() {
-            reply.error(libc::EEXIST);
+            reply.error(Errno::EEXIST);
             return;
         }
 
@@ -1311,7 +1303,7 @@ impl Filesystem for SimpleFS {
             value
         } else {
             error!("Path component is not UTF-8");
-            reply.error(libc::EINVAL);
+            reply.error(Errno::EINVAL);
             return;
         };
         let (read, write) = match flags as i32 & libc::O_ACCMODE {
@@ -1320,7 +1312,7 @@ impl Filesystem for SimpleFS {
             libc::O_RDWR => (true, true),
             // Exactly one access mode flag must be specified
             _ => {
-                reply.error(libc::EINVAL);
+                reply.error(Errno::EINVAL);
                 return;
             }
         };
@@ -1341,7 +1333,7 @@ impl Filesystem for SimpleFS {
             req.gid(),
             libc::W_OK as u32,
         ) {
-            reply.error(libc::EACCES);
+            reply.error(Errno::EACCES);
             return;
         }
         parent_attrs.last_modified = SystemTime::now();
@@ -0,0 +1,1286 @@
+//! Asynchronous filesystem API
+//!
+//! `AsyncFilesystem` is an alternative to the `Filesystem` trait for filesystems that are
+//! implemented with async code (e.g. on top of tokio or async-std). Instead of taking a reply
+//! object, every method is an `async fn` that returns its result. An `AsyncSession` receives
+//! kernel requests and runs every operation as a separate task, so many requests can be
+//! served concurrently. The session doesn't depend on a specific executor, the async runtime
+//! is plugged in by implementing `AsyncRuntime`.
+
+use libc::{EAGAIN, ENODEV};
+use log::{debug, error, warn};
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::channel::Channel;
+use crate::fuse_abi::consts::*;
+use crate::fuse_abi::{fuse_init_out, FUSE_COMPAT_22_INIT_OUT_SIZE, FUSE_KERNEL_MINOR_VERSION};
+use crate::kernel_config::KernelConfig;
+use crate::ll;
+use crate::notify::{Notifier, PollHandle};
+use crate::reply::{Reply, ReplyDirectory, ReplyRaw, ReplySender};
+use crate::reply::{ReplyAttr, ReplyCreate, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen};
+use crate::reply::{ReplyDirectoryPlus, ReplyIoctl, ReplyLock, ReplyLseek, ReplyPoll};
+use crate::reply::{ReplyStatfs, ReplyWrite, ReplyXattr};
+use crate::request::{write_args, WriteFlags};
+use crate::session::{is_retryable, MountState, BUFFER_SIZE};
+#[cfg(not(feature = "libfuse"))]
+use crate::MountOption;
+use crate::{Errno, SessionExit, SessionUnmounter};
+
+pub use crate::fs_types::{AttrOut, CreateOut, DirEntry, DirEntryPlus, EntryOut, IoctlOut};
+pub use crate::fs_types::{LockOut, OpenOut, SetAttrIn, StatfsOut, XattrOut};
+
+/// Information about the caller of a filesystem operation
+#[derive(Clone, Copy, Debug)]
+pub struct RequestInfo {
+    /// Unique id of the request
+    pub unique: u64,
+    /// The uid of the process that triggered the request
+    pub uid: u32,
+    /// The gid of the process that triggered the request
+    pub gid: u32,
+    /// The pid of the process that triggered the request
+    pub pid: u32,
+}
+
+impl<'a> From<&ll::Request<'a>> for RequestInfo {
+    fn from(req: &ll::Request<'a>) -> RequestInfo {
+        RequestInfo {
+            unique: req.unique(),
+            uid: req.uid(),
+            gid: req.gid(),
+            pid: req.pid(),
+        }
+    }
+}
+
+/// Asynchronous filesystem trait.
+///
+/// Like `Filesystem`, but every method is asynchronous and returns its result instead of
+/// taking a reply object. Errors are `Errno` values (e.g. `Errno::ENOENT`). Methods may run
+/// concurrently, so the filesystem must handle its own synchronization. The default
+/// implementations behave like the ones of `Filesystem`. Operations that are not part of
+/// this trait (bmap, syncfs, tmpfile and the DAX mappings) are answered with ENOSYS.
+///
+/// Implementations can use `async fn` for the methods.
+#[allow(clippy::too_many_arguments)]
+pub trait AsyncFilesystem: Send + Sync + 'static {
+    /// Initialize filesystem. See `Filesystem::init`.
+    fn init(
+        &self,
+        _req: RequestInfo,
+        _config: &mut KernelConfig,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Clean up filesystem. Called on filesystem exit.
+    fn destroy(&self, _req: RequestInfo) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Look up a directory entry by name and get its attributes.
+    fn lookup(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+    ) -> impl Future<Output = Result<EntryOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Forget about an inode. See `Filesystem::forget`.
+    fn forget(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _nlookup: u64,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Get file attributes.
+    fn getattr(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+    ) -> impl Future<Output = Result<AttrOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Set file attributes.
+    fn setattr(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _attr: SetAttrIn,
+    ) -> impl Future<Output = Result<AttrOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Read symbolic link.
+    fn readlink(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+    ) -> impl Future<Output = Result<Vec<u8>, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Create file node.
+    fn mknod(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _rdev: u32,
+    ) -> impl Future<Output = Result<EntryOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Create a directory.
+    fn mkdir(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+    ) -> impl Future<Output = Result<EntryOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Remove a file.
+    fn unlink(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Remove a directory.
+    fn rmdir(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Create a symbolic link.
+    fn symlink(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+        _link: &Path,
+    ) -> impl Future<Output = Result<EntryOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Rename a file. See `Filesystem::rename` for the flags.
+    fn rename(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _flags: u32,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Create a hard link.
+    fn link(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _newparent: u64,
+        _newname: &OsStr,
+    ) -> impl Future<Output = Result<EntryOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Open a file. See `Filesystem::open`.
+    fn open(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _flags: u32,
+    ) -> impl Future<Output = Result<OpenOut, Errno>> + Send {
+        async { Ok(OpenOut::default()) }
+    }
+
+    /// Read data. See `Filesystem::read`.
+    fn read(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _size: u32,
+    ) -> impl Future<Output = Result<Vec<u8>, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Write data and return the number of bytes written. See `Filesystem::write`.
+    fn write(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _data: &[u8],
+        _write_flags: WriteFlags,
+        _flags: u32,
+        _lock_owner: Option<u64>,
+    ) -> impl Future<Output = Result<u32, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Flush method. See `Filesystem::flush`.
+    fn flush(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Release an open file. See `Filesystem::release`.
+    fn release(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Synchronize file contents.
+    fn fsync(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Open a directory. See `Filesystem::opendir`.
+    fn opendir(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _flags: u32,
+    ) -> impl Future<Output = Result<OpenOut, Errno>> + Send {
+        async { Ok(OpenOut::default()) }
+    }
+
+    /// Read directory entries, starting after the entry with the given offset (or at the
+    /// beginning if offset is 0). The session sends as many of the returned entries as fit
+    /// into the kernel's buffer, the kernel calls readdir again with the offset of the last
+    /// entry it received. Return no entries at the end of the directory.
+    fn readdir(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+    ) -> impl Future<Output = Result<Vec<DirEntry>, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Read directory entries with their attributes, like readdir. See
+    /// `Filesystem::readdirplus`.
+    fn readdirplus(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+    ) -> impl Future<Output = Result<Vec<DirEntryPlus>, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Release an open directory.
+    fn releasedir(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Synchronize directory contents.
+    fn fsyncdir(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Get file system statistics.
+    fn statfs(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+    ) -> impl Future<Output = Result<StatfsOut, Errno>> + Send {
+        async { Ok(StatfsOut::default()) }
+    }
+
+    /// Set an extended attribute.
+    fn setxattr(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: u32,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Get an extended attribute. If size is 0, return the size of the value, otherwise
+    /// return the value or ERANGE if it doesn't fit into size.
+    fn getxattr(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _name: &OsStr,
+        _size: u32,
+    ) -> impl Future<Output = Result<XattrOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// List extended attribute names, like getxattr.
+    fn listxattr(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _size: u32,
+    ) -> impl Future<Output = Result<XattrOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Remove an extended attribute.
+    fn removexattr(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _name: &OsStr,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Check file access permissions.
+    fn access(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _mask: u32,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Create and open a file. See `Filesystem::create`.
+    fn create(
+        &self,
+        _req: RequestInfo,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _flags: u32,
+    ) -> impl Future<Output = Result<CreateOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Test for a POSIX file lock. See `Filesystem::getlk`.
+    fn getlk(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+        _start: u64,
+        _end: u64,
+        _typ: u32,
+        _pid: u32,
+    ) -> impl Future<Output = Result<LockOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Acquire, modify or release a POSIX file lock. See `Filesystem::setlk`.
+    fn setlk(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+        _start: u64,
+        _end: u64,
+        _typ: u32,
+        _pid: u32,
+        _sleep: bool,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Control device. See `Filesystem::ioctl`.
+    fn ioctl(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _cmd: u32,
+        _arg: u64,
+        _in_data: &[u8],
+        _out_size: u32,
+    ) -> impl Future<Output = Result<IoctlOut, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Poll for IO readiness events and return the events that are ready. See
+    /// `Filesystem::poll`.
+    fn poll(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _ph: Option<PollHandle>,
+        _events: u32,
+        _flags: u32,
+    ) -> impl Future<Output = Result<u32, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Preallocate or deallocate space to a file. See `Filesystem::fallocate`.
+    fn fallocate(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _length: i64,
+        _mode: i32,
+    ) -> impl Future<Output = Result<(), Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Reposition the offset of an open file and return the new offset. See
+    /// `Filesystem::lseek`.
+    fn lseek(
+        &self,
+        _req: RequestInfo,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _whence: i32,
+    ) -> impl Future<Output = Result<i64, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+
+    /// Copy a range of data from one file to another and return the number of bytes
+    /// copied. See `Filesystem::copy_file_range`.
+    fn copy_file_range(
+        &self,
+        _req: RequestInfo,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        _ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u32,
+    ) -> impl Future<Output = Result<u32, Errno>> + Send {
+        async { Err(Errno::ENOSYS) }
+    }
+}
+
+/// Readiness notifications for the connection to the kernel driver, provided by the async
+/// runtime (e.g. with tokio's `AsyncFd` or async-io's `Async`).
+pub trait Readiness: Send + Sync {
+    /// Poll whether the file descriptor is readable. The implementation may clear the
+    /// readiness state when returning `Poll::Ready`, since the session reads until the
+    /// file descriptor would block before polling again.
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+/// Adapter to the async runtime that runs an `AsyncSession`
+pub trait AsyncRuntime: Send + Sync {
+    /// Register the given (non-blocking) file descriptor for readiness notifications
+    fn register(&self, fd: RawFd) -> io::Result<Box<dyn Readiness>>;
+
+    /// Spawn a task that runs concurrently to the session loop
+    fn spawn(&self, task: Pin<Box<dyn Future<Output = ()> + Send>>);
+}
+
+/// Session of an asynchronous filesystem
+#[derive(Debug)]
+pub struct AsyncSession<FS: AsyncFilesystem> {
+    /// Filesystem operation implementations
+    filesystem: Arc<FS>,
+    /// Communication channel to the kernel driver
+    ch: Channel,
+    /// Notifier for sending notifications to the kernel driver
+    notifier: Notifier,
+    /// The mounted filesystem, shared with unmounters
+    state: Arc<MountState>,
+    /// FUSE protocol major version
+    pub proto_major: u32,
+    /// FUSE protocol minor version
+    pub proto_minor: u32,
+    /// True if the filesystem is initialized (init operation done)
+    pub initialized: bool,
+    /// True if the filesystem was destroyed (destroy operation done)
+    pub destroyed: bool,
+}
+
+impl<FS: AsyncFilesystem> AsyncSession<FS> {
+    /// Create a new session by mounting the given filesystem to the given mountpoint
+    #[cfg(feature = "libfuse")]
+    pub fn new(
+        filesystem: FS,
+        mountpoint: &Path,
+        options: &[&OsStr],
+    ) -> io::Result<AsyncSession<FS>> {
+        Channel::new(mountpoint, options).and_then(|ch| AsyncSession::with_channel(filesystem, ch))
+    }
+
+    /// Create a new session by mounting the given filesystem to the given mountpoint
+    #[cfg(not(feature = "libfuse"))]
+    pub fn new2(
+        filesystem: FS,
+        mountpoint: &Path,
+        options: &[MountOption],
+    ) -> io::Result<AsyncSession<FS>> {
+        Channel::new2(mountpoint, options).and_then(|ch| AsyncSession::with_channel(filesystem, ch))
+    }
+
+    fn with_channel(filesystem: FS, ch: Channel) -> io::Result<AsyncSession<FS>> {
+        Ok(AsyncSession {
+            filesystem: Arc::new(filesystem),
+            notifier: Notifier::new(ch.sender()),
+            state: Arc::new(MountState::new(&ch)?),
+            ch,
+            proto_major: 0,
+            proto_minor: 0,
+            initialized: false,
+            destroyed: false,
+        })
+    }
+
+    /// Return path of the mounted filesystem
+    pub fn mountpoint(&self) -> &Path {
+        self.ch.mountpoint()
+    }
+
+    /// Returns the filesystem
+    pub fn filesystem(&self) -> &Arc<FS> {
+        &self.filesystem
+    }
+
+    /// Returns a notifier for sending notifications to the kernel driver
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// Returns a handle for unmounting the filesystem, which ends the session loop. See
+    /// `Session::unmounter`.
+    pub fn unmounter(&self) -> SessionUnmounter {
+        SessionUnmounter::new(&self.state)
+    }
+
+    /// Run the session loop until the filesystem is unmounted. Requests are received when
+    /// the runtime reports the connection as readable, and every filesystem operation is
+    /// spawned as a separate task on the runtime. Only init and destroy are awaited by the
+    /// session loop itself. Returns why the session ended, like `Session::run`.
+    pub async fn run<R: AsyncRuntime>(&mut self, runtime: &R) -> io::Result<SessionExit> {
+        set_nonblocking(self.ch.fd)?;
+        let readiness = runtime.register(self.ch.fd)?;
+        // Buffer for receiving requests from the kernel. Requests are copied out of it
+        // before they're passed to a task, so only one buffer of the maximum size is needed.
+        let mut buffer: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+        loop {
+            match self.ch.receive(&mut buffer) {
+                Ok(()) => (),
+                Err(err) => match err.raw_os_error() {
+                    // Filesystem was unmounted, quit the loop
+                    Some(ENODEV) => break,
+                    // No request available, wait until the kernel sends one
+                    Some(EAGAIN) => {
+                        std::future::poll_fn(|cx| readiness.poll_readable(cx)).await?;
+                        continue;
+                    }
+                    // Retry on recoverable errors
+                    _ if is_retryable(&err) => continue,
+                    // Unhandled error
+                    _ => return Err(err),
+                },
+            }
+            let sender = self.ch.sender();
+            let request = match ll::Request::parse(&buffer[..], sender.proto_minor()) {
+                Ok(request) => request,
+                Err(err) => {
+                    reply_invalid(err, sender)?;
+                    continue;
+                }
+            };
+            debug!("{}", request);
+            match request.operation() {
+                ll::Operation::Init { arg } => {
+                    let reply: ReplyRaw<fuse_init_out> =
+                        Reply::new(request.unique(), sender.clone());
+                    // We don't support ABI versions before 7.6
+                    if arg.major < 7 || (arg.major == 7 && arg.minor < 6) {
+                        error!("Unsupported FUSE ABI version {}.{}", arg.major, arg.minor);
+                        reply.error(Errno::EPROTO);
+                        continue;
+                    }
+                    self.proto_major = arg.major;
+                    self.proto_minor = arg.minor.min(FUSE_KERNEL_MINOR_VERSION);
+                    sender.set_proto_minor(self.proto_minor);
+                    let mut config = KernelConfig::new(arg.flags, arg.max_readahead);
+                    let req = RequestInfo::from(&request);
+                    if let Err(err) = self.filesystem.init(req, &mut config).await {
+                        reply.error(err);
+                        continue;
+                    }
+                    self.initialized = true;
+                    // Kernels before ABI 7.23 reject init replies longer than they know
+                    if arg.minor < 23 {
+                        reply.ok_truncated(&config.init_out(), FUSE_COMPAT_22_INIT_OUT_SIZE);
+                    } else {
+                        reply.ok(&config.init_out());
+                    }
+                }
+                // Any operation is invalid before initialization
+                _ if !self.initialized => {
+                    warn!("Ignoring FUSE operation before init: {}", request);
+                    ReplyEmpty::new(request.unique(), sender).error(Errno::EIO);
+                }
+                ll::Operation::Destroy => {
+                    self.filesystem.destroy(RequestInfo::from(&request)).await;
+                    self.destroyed = true;
+                    ReplyEmpty::new(request.unique(), sender).ok();
+                }
+                // Any operation is invalid after destroy
+                _ if self.destroyed => {
+                    warn!("Ignoring FUSE operation after destroy: {}", request);
+                    ReplyEmpty::new(request.unique(), sender).error(Errno::EIO);
+                }
+                _ => {
+                    let fs = self.filesystem.clone();
+                    let notifier = self.notifier.clone();
+                    let data = buffer.to_vec();
+                    runtime.spawn(Box::pin(async move {
+                        dispatch(&*fs, &data, sender, &notifier).await;
+                    }));
+                }
+            }
+        }
+        Ok(self.state.exit_reason())
+    }
+}
+
+/// Switch the given file descriptor to non-blocking mode
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reply to a request that couldn't be parsed with an error (ENOSYS for unknown operations,
+/// EIO for malformed requests). Fails if not even the header of the request can be read.
+fn reply_invalid<S: ReplySender>(err: ll::InvalidRequest<'_>, sender: S) -> io::Result<()> {
+    let header = match err.header {
+        Some(header) => header,
+        None => {
+            error!("{}", err);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()));
+        }
+    };
+    warn!("{}", err);
+    if let Some(errno) = err.reply_error() {
+        ReplyEmpty::new(header.unique, sender).error(errno);
+    }
+    Ok(())
+}
+
+/// Reply to a request with the result of a filesystem operation
+macro_rules! reply {
+    ($reply:ident, $result:expr, |$reply_ok:ident, $value:pat_param| $ok:expr) => {
+        match $result {
+            Ok($value) => {
+                let $reply_ok = $reply;
+                $ok
+            }
+            Err(err) => $reply.error(err),
+        }
+    };
+}
+
+/// Call the filesystem method of a regular filesystem operation and send its reply. Init
+/// and destroy must be handled by the session.
+async fn dispatch<FS: AsyncFilesystem, S: ReplySender + Clone>(
+    fs: &FS,
+    data: &[u8],
+    sender: S,
+    notifier: &Notifier,
+) {
+    let request = match ll::Request::parse(data, sender.proto_minor()) {
+        Ok(request) => request,
+        Err(err) => {
+            let _ = reply_invalid(err, sender);
+            return;
+        }
+    };
+    let req = RequestInfo::from(&request);
+    let ino = request.nodeid();
+    let unique = request.unique();
+
+    match request.operation() {
+        ll::Operation::Init { .. } | ll::Operation::Destroy => {
+            unreachable!("Init and destroy must be dispatched by the session")
+        }
+        // Interrupts are not supported, the interrupted operation just completes
+        ll::Operation::Interrupt { .. } => (),
+        ll::Operation::Forget { arg } => fs.forget(req, ino, arg.nlookup).await, // no reply
+        ll::Operation::BatchForget { nodes, .. } => {
+            for node in nodes.iter() {
+                fs.forget(req, node.nodeid, node.nlookup).await; // no reply
+            }
+        }
+
+        ll::Operation::Lookup { name } => {
+            let result = fs.lookup(req, ino, name).await;
+            reply_entry(ReplyEntry::new(unique, sender.clone()), result);
+        }
+        ll::Operation::GetAttr => {
+            let result = fs.getattr(req, ino).await;
+            reply_attr(ReplyAttr::new(unique, sender.clone()), result);
+        }
+        ll::Operation::SetAttr { arg } => {
+            let result = fs.setattr(req, ino, SetAttrIn::from_arg(arg)).await;
+            reply_attr(ReplyAttr::new(unique, sender.clone()), result);
+        }
+        ll::Operation::ReadLink => {
+            let reply: ReplyData = Reply::new(unique, sender.clone());
+            reply!(reply, fs.readlink(req, ino).await, |r, data| r.data(&data));
+        }
+        ll::Operation::MkNod { arg, name } => {
+            let result = fs.mknod(req, ino, name, arg.mode, arg.rdev).await;
+            reply_entry(ReplyEntry::new(unique, sender.clone()), result);
+        }
+        ll::Operation::MkDir { arg, name } => {
+            let result = fs.mkdir(req, ino, name, arg.mode).await;
+            reply_entry(ReplyEntry::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Unlink { name } => {
+            let result = fs.unlink(req, ino, name).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::RmDir { name } => {
+            let result = fs.rmdir(req, ino, name).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::SymLink { name, link } => {
+            let result = fs.symlink(req, ino, name, Path::new(link)).await;
+            reply_entry(ReplyEntry::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Rename { arg, name, newname } => {
+            let result = fs.rename(req, ino, name, arg.newdir, newname, 0).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Rename2 { arg, name, newname } => {
+            let result = fs
+                .rename(req, ino, name, arg.newdir, newname, arg.flags)
+                .await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Link { arg, name } => {
+            let result = fs.link(req, arg.oldnodeid, ino, name).await;
+            reply_entry(ReplyEntry::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Open { arg } => {
+            let result = fs.open(req, ino, arg.flags).await;
+            reply_open(ReplyOpen::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Read { arg } => {
+            let result = fs.read(req, ino, arg.fh, arg.offset as i64, arg.size).await;
+            let reply: ReplyData = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, data| r.data(&data));
+        }
+        ll::Operation::Write { arg, data } => {
+            let (write_flags, flags, lock_owner) = write_args(arg);
+            let result = fs
+                .write(
+                    req,
+                    ino,
+                    arg.fh,
+                    arg.offset as i64,
+                    data,
+                    write_flags,
+                    flags,
+                    lock_owner,
+                )
+                .await;
+            let reply: ReplyWrite = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, size| r.written(size));
+        }
+        ll::Operation::Flush { arg } => {
+            let result = fs.flush(req, ino, arg.fh, arg.lock_owner).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Release { arg } => {
+            let flush = arg.release_flags & FUSE_RELEASE_FLUSH != 0;
+            let result = fs
+                .release(req, ino, arg.fh, arg.flags, arg.lock_owner, flush)
+                .await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::FSync { arg } => {
+            let datasync = arg.fsync_flags & 1 != 0;
+            let result = fs.fsync(req, ino, arg.fh, datasync).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::OpenDir { arg } => {
+            let result = fs.opendir(req, ino, arg.flags).await;
+            reply_open(ReplyOpen::new(unique, sender.clone()), result);
+        }
+        ll::Operation::ReadDir { arg } => {
+            let result = fs.readdir(req, ino, arg.fh, arg.offset as i64).await;
+            let reply = ReplyDirectory::new(unique, sender.clone(), arg.size as usize);
+            reply!(reply, result, |r, entries| {
+                let mut r = r;
+                for entry in entries {
+                    if r.add(entry.ino, entry.offset, entry.kind, &entry.name) {
+                        break;
+                    }
+                }
+                r.ok()
+            });
+        }
+        ll::Operation::ReadDirPlus { arg } => {
+            let result = fs.readdirplus(req, ino, arg.fh, arg.offset as i64).await;
+            let reply = ReplyDirectoryPlus::new(unique, sender.clone(), arg.size as usize);
+            reply!(reply, result, |r, entries| {
+                let mut r = r;
+                for entry in entries {
+                    let e = &entry.entry;
+                    if r.add(
+                        e.attr.ino,
+                        entry.offset,
+                        &entry.name,
+                        &e.ttl,
+                        &e.attr,
+                        e.generation,
+                    ) {
+                        break;
+                    }
+                }
+                r.ok()
+            });
+        }
+        ll::Operation::ReleaseDir { arg } => {
+            let result = fs.releasedir(req, ino, arg.fh, arg.flags).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::FSyncDir { arg } => {
+            let datasync = arg.fsync_flags & 1 != 0;
+            let result = fs.fsyncdir(req, ino, arg.fh, datasync).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::StatFs => {
+            let reply: ReplyStatfs = Reply::new(unique, sender.clone());
+            reply!(reply, fs.statfs(req, ino).await, |r, st| r.statfs(
+                st.blocks, st.bfree, st.bavail, st.files, st.ffree, st.bsize, st.namelen, st.frsize
+            ));
+        }
+        ll::Operation::SetXAttr { arg, name, value } => {
+            let result = fs.setxattr(req, ino, name, value, arg.flags).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::GetXAttr { arg, name } => {
+            let result = fs.getxattr(req, ino, name, arg.size).await;
+            reply_xattr(ReplyXattr::new(unique, sender.clone()), result);
+        }
+        ll::Operation::ListXAttr { arg } => {
+            let result = fs.listxattr(req, ino, arg.size).await;
+            reply_xattr(ReplyXattr::new(unique, sender.clone()), result);
+        }
+        ll::Operation::RemoveXAttr { name } => {
+            let result = fs.removexattr(req, ino, name).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Access { arg } => {
+            let result = fs.access(req, ino, arg.mask).await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Create { arg, name } => {
+            let result = fs.create(req, ino, name, arg.mode, arg.flags).await;
+            let reply: ReplyCreate = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, created| r.created(
+                &created.entry.ttl,
+                &created.entry.attr,
+                created.entry.generation,
+                created.open.fh,
+                created.open.flags
+            ));
+        }
+
+        ll::Operation::GetLk { arg } => {
+            let (lk, owner) = (&arg.lk, arg.owner);
+            let result = fs
+                .getlk(req, ino, arg.fh, owner, lk.start, lk.end, lk.typ, lk.pid)
+                .await;
+            let reply: ReplyLock = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, lock| r
+                .locked(lock.start, lock.end, lock.typ, lock.pid));
+        }
+        ll::Operation::SetLk { arg } | ll::Operation::SetLkW { arg } => {
+            let sleep = matches!(request.operation(), ll::Operation::SetLkW { .. });
+            let (lk, owner) = (&arg.lk, arg.owner);
+            let result = fs
+                .setlk(
+                    req, ino, arg.fh, owner, lk.start, lk.end, lk.typ, lk.pid, sleep,
+                )
+                .await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::IoCtl { arg, data } => {
+            let in_size = (arg.in_size as usize).min(data.len());
+            let result = fs
+                .ioctl(
+                    req,
+                    ino,
+                    arg.fh,
+                    arg.flags,
+                    arg.cmd,
+                    arg.arg,
+                    &data[..in_size],
+                    arg.out_size,
+                )
+                .await;
+            let reply: ReplyIoctl = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, ioctl| match ioctl {
+                IoctlOut::Done { result, data } => r.ioctl(result, &data),
+                IoctlOut::Retry { in_iovs, out_iovs } => r.retry(&in_iovs, &out_iovs),
+            });
+        }
+        ll::Operation::Poll { arg } => {
+            let ph = if arg.flags & FUSE_POLL_SCHEDULE_NOTIFY != 0 {
+                Some(PollHandle::new(arg.kh, notifier.clone()))
+            } else {
+                None
+            };
+            let result = fs.poll(req, ino, arg.fh, ph, arg.events, arg.flags).await;
+            let reply: ReplyPoll = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, revents| r.poll(revents));
+        }
+        ll::Operation::FAllocate { arg } => {
+            let result = fs
+                .fallocate(
+                    req,
+                    ino,
+                    arg.fh,
+                    arg.offset as i64,
+                    arg.length as i64,
+                    arg.mode as i32,
+                )
+                .await;
+            reply_empty(ReplyEmpty::new(unique, sender.clone()), result);
+        }
+        ll::Operation::Lseek { arg } => {
+            let result = fs
+                .lseek(req, ino, arg.fh, arg.offset as i64, arg.whence as i32)
+                .await;
+            let reply: ReplyLseek = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, offset| r.offset(offset));
+        }
+        ll::Operation::CopyFileRange { arg } => {
+            let result = fs
+                .copy_file_range(
+                    req,
+                    ino,
+                    arg.fh_in,
+                    arg.off_in as i64,
+                    arg.nodeid_out,
+                    arg.fh_out,
+                    arg.off_out as i64,
+                    arg.len,
+                    arg.flags as u32,
+                )
+                .await;
+            let reply: ReplyWrite = Reply::new(unique, sender.clone());
+            reply!(reply, result, |r, size| r.written(size));
+        }
+
+        // Data retrieved by a retrieve notification, sent with the notification's unique
+        // id. This isn't a request, so there's no reply.
+        ll::Operation::NotifyReply { arg, data } => {
+            let size = (arg.size as usize).min(data.len());
+            notifier.retrieve_reply(unique, &data[..size]);
+        }
+        _ => ReplyEmpty::new(unique, sender.clone()).error(Errno::ENOSYS),
+    }
+}
+
+fn reply_entry(reply: ReplyEntry, result: Result<EntryOut, Errno>) {
+    reply!(reply, result, |r, entry| r.entry(
+        &entry.ttl,
+        &entry.attr,
+        entry.generation
+    ));
+}
+
+fn reply_attr(reply: ReplyAttr, result: Result<AttrOut, Errno>) {
+    reply!(reply, result, |r, attr| r.attr(&attr.ttl, &attr.attr));
+}
+
+fn reply_open(reply: ReplyOpen, result: Result<OpenOut, Errno>) {
+    reply!(reply, result, |r, open| r.opened(open.fh, open.flags));
+}
+
+fn reply_empty(reply: ReplyEmpty, result: Result<(), Errno>) {
+    reply!(reply, result, |r, ()| r.ok());
+}
+
+fn reply_xattr(reply: ReplyXattr, result: Result<XattrOut, Errno>) {
+    reply!(reply, result, |r, xattr| match xattr {
+        XattrOut::Size(size) => r.size(size),
+        XattrOut::Data(data) => r.data(&data),
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::channel;
+    use crate::{FileAttr, FileType};
+    use std::sync::Mutex;
+    use std::task::Waker;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Reply sender that collects sent replies
+    #[derive(Clone, Copy)]
+    struct CollectSender(&'static Mutex<Vec<Vec<u8>>>);
+
+    impl ReplySender for CollectSender {
+        fn send(&self, data: &[&[u8]]) {
+            self.0.lock().unwrap().push(data.concat());
+        }
+    }
+
+    /// Poll the given future to completion (it must not wait for anything)
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Build a request with the given opcode, nodeid and argument data
+    fn request(opcode: u32, nodeid: u64, arg: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(40 + arg.len() as u32).to_ne_bytes()); // len
+        data.extend_from_slice(&opcode.to_ne_bytes());
+        data.extend_from_slice(&0xdead_beef_u64.to_ne_bytes()); // unique
+        data.extend_from_slice(&nodeid.to_ne_bytes());
+        data.extend_from_slice(&[0; 16]); // uid, gid, pid, padding
+        data.extend_from_slice(arg);
+        data
+    }
+
+    fn reply_error(reply: &[u8]) -> i32 {
+        i32::from_ne_bytes([reply[4], reply[5], reply[6], reply[7]])
+    }
+
+    struct TestFs;
+
+    impl AsyncFilesystem for TestFs {
+        async fn lookup(
+            &self,
+            _req: RequestInfo,
+            parent: u64,
+            name: &OsStr,
+        ) -> Result<EntryOut, Errno> {
+            if parent != 1 || name != "hello" {
+                return Err(Errno::ENOENT);
+            }
+            Ok(EntryOut {
+                attr: FileAttr {
+                    ino: 2,
+                    size: 0,
+                    blocks: 0,
+                    atime: UNIX_EPOCH,
+                    mtime: UNIX_EPOCH,
+                    ctime: UNIX_EPOCH,
+                    crtime: UNIX_EPOCH,
+                    kind: FileType::RegularFile,
+                    perm: 0o644,
+                    nlink: 1,
+                    uid: 0,
+                    gid: 0,
+                    rdev: 0,
+                    blksize: 4096,
+                    padding: 0,
+                    flags: 0,
+                },
+                ttl: Duration::from_secs(1),
+                generation: 0,
+            })
+        }
+
+        async fn lseek(
+            &self,
+            _req: RequestInfo,
+            _ino: u64,
+            _fh: u64,
+            offset: i64,
+            whence: i32,
+        ) -> Result<i64, Errno> {
+            match whence {
+                libc::SEEK_DATA => Ok(offset),
+                libc::SEEK_HOLE => Ok(0x1000),
+                _ => Err(Errno::EINVAL),
+            }
+        }
+    }
+
+    #[test]
+    fn dispatch_lookup() {
+        static REPLIES: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+        let sender = CollectSender(&REPLIES);
+        let (ch, _driver) = channel("async-lookup");
+        let notifier = Notifier::new(ch.sender());
+        block_on(dispatch(
+            &TestFs,
+            &request(1, 1, b"hello\0"),
+            sender,
+            &notifier,
+        ));
+        block_on(dispatch(
+            &TestFs,
+            &request(1, 1, b"world\0"),
+            sender,
+            &notifier,
+        ));
+        let replies = REPLIES.lock().unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(reply_error(&replies[0]), 0);
+        // The entry's nodeid follows the header
+        assert_eq!(&replies[0][16..24], &2u64.to_ne_bytes());
+        assert_eq!(reply_error(&replies[1]), -libc::ENOENT);
+    }
+
+    #[test]
+    fn dispatch_default() {
+        static REPLIES: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+        let sender = CollectSender(&REPLIES);
+        let (ch, _driver) = channel("async-default");
+        let notifier = Notifier::new(ch.sender());
+        // getattr isn't implemented, statfs has a default implementation
+        block_on(dispatch(&TestFs, &request(3, 1, &[]), sender, &notifier));
+        block_on(dispatch(&TestFs, &request(17, 1, &[]), sender, &notifier));
+        let replies = REPLIES.lock().unwrap();
+        assert_eq!(reply_error(&replies[0]), -libc::ENOSYS);
+        assert_eq!(reply_error(&replies[1]), 0);
+    }
+
+    #[test]
+    fn dispatch_lseek() {
+        static REPLIES: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+        let sender = CollectSender(&REPLIES);
+        let (ch, _driver) = channel("async-lseek");
+        let notifier = Notifier::new(ch.sender());
+        let lseek = |offset: u64, whence: i32| {
+            let mut arg = Vec::new();
+            arg.extend_from_slice(&1u64.to_ne_bytes()); // fh
+            arg.extend_from_slice(&offset.to_ne_bytes());
+            arg.extend_from_slice(&(whence as u32).to_ne_bytes());
+            arg.extend_from_slice(&0u32.to_ne_bytes()); // padding
+            request(46, 2, &arg)
+        };
+        block_on(dispatch(
+            &TestFs,
+            &lseek(0x200, libc::SEEK_HOLE),
+            sender,
+            &notifier,
+        ));
+        block_on(dispatch(
+            &TestFs,
+            &lseek(0x200, libc::SEEK_SET),
+            sender,
+            &notifier,
+        ));
+        // Operations without an implementation aren't supported, e.g. fallocate
+        block_on(dispatch(
+            &TestFs,
+            &request(43, 2, &[0; 32]),
+            sender,
+            &notifier,
+        ));
+        let replies = REPLIES.lock().unwrap();
+        assert_eq!(reply_error(&replies[0]), 0);
+        // The new offset follows the header
+        assert_eq!(&replies[0][16..24], &0x1000u64.to_ne_bytes());
+        assert_eq!(reply_error(&replies[1]), -libc::EINVAL);
+        assert_eq!(reply_error(&replies[2]), -libc::ENOSYS);
+    }
+
+    #[test]
+    fn dispatch_invalid() {
+        static REPLIES: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+        let sender = CollectSender(&REPLIES);
+        let (ch, _driver) = channel("async-invalid");
+        let notifier = Notifier::new(ch.sender());
+        // Unknown operations are answered with ENOSYS, malformed forgets aren't answered
+        block_on(dispatch(&TestFs, &request(255, 1, &[]), sender, &notifier));
+        block_on(dispatch(
+            &TestFs,
+            &request(2, 2, &[0; 4]),
+            sender,
+            &notifier,
+        ));
+        let replies = REPLIES.lock().unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(&replies[0][8..16], &0xdead_beef_u64.to_ne_bytes());
+        assert_eq!(reply_error(&replies[0]), -libc::ENOSYS);
+        // Only data without a complete header is an error
+        let err = ll::Request::parse(&[0; 20], FUSE_KERNEL_MINOR_VERSION).unwrap_err();
+        assert!(reply_invalid(err, sender).is_err());
+    }
+}
@@ -0,0 +1,434 @@
+//! Recording and replaying FUSE traffic
+//!
+//! A `Recorder` writes every request received from the kernel and every reply (and
+//! notification) sent to it to a capture file, e.g. to reproduce a bug of a filesystem later.
+//! `replay` feeds the requests of a capture file into a filesystem the same way a session
+//! would and compares the filesystem's replies to the recorded ones.
+//!
+//! A capture file starts with the magic bytes `FUSECAP\0` and a little-endian `u32` version,
+//! followed by a `u32` that is reserved. Each record consists of its direction (`u8`, 0 for
+//! requests and 1 for replies), 3 bytes of padding, the little-endian `u32` length of its data,
+//! a little-endian `u64` timestamp in nanoseconds since the Unix epoch and the data. The data
+//! is the raw message as exchanged with the kernel, i.e. in native byte order.
+
+use log::{error, warn};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::channel::ChannelSender;
+use crate::fuse_abi::fuse_out_header;
+use crate::notify::Notifier;
+use crate::request::{InterruptTracker, Request};
+use crate::stats::SessionStats;
+use crate::Filesystem;
+
+/// Magic bytes at the start of a capture file
+const MAGIC: &[u8; 8] = b"FUSECAP\0";
+
+/// Version of the capture file format
+const VERSION: u32 = 1;
+
+/// Direction of a recorded message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Request from the kernel
+    Request,
+    /// Reply or notification to the kernel
+    Reply,
+}
+
+/// A recorded message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Direction of the message
+    pub direction: Direction,
+    /// Time the message was received or sent
+    pub timestamp: SystemTime,
+    /// Raw message
+    pub data: Vec<u8>,
+}
+
+/// Destination of recorded messages
+#[derive(Debug)]
+enum Sink {
+    File(BufWriter<File>),
+    Memory(Vec<Record>),
+}
+
+/// Recorder of the messages exchanged with the kernel. It's cheap to clone, all clones write
+/// to the same destination. See `Session::set_recorder`.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl Recorder {
+    /// Create a new capture file at the given path (an existing file is truncated)
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Recorder {
+            sink: Arc::new(Mutex::new(Sink::File(writer))),
+        })
+    }
+
+    /// Create a recorder that keeps the messages in memory
+    pub(crate) fn memory() -> Recorder {
+        Recorder {
+            sink: Arc::new(Mutex::new(Sink::Memory(Vec::new()))),
+        }
+    }
+
+    /// Write buffered records to the capture file
+    pub fn flush(&self) -> io::Result<()> {
+        match &mut *self.sink.lock().unwrap() {
+            Sink::File(writer) => writer.flush(),
+            Sink::Memory(_) => Ok(()),
+        }
+    }
+
+    /// Record a request received from the kernel
+    pub(crate) fn request(&self, data: &[u8]) {
+        self.record(Direction::Request, &[data]);
+    }
+
+    /// Record a reply or notification sent to the kernel
+    pub(crate) fn reply(&self, data: &[&[u8]]) {
+        self.record(Direction::Reply, data);
+    }
+
+    fn record(&self, direction: Direction, data: &[&[u8]]) {
+        let timestamp = SystemTime::now();
+        match &mut *self.sink.lock().unwrap() {
+            Sink::File(writer) => {
+                if let Err(err) = write_record(writer, direction, timestamp, data) {
+                    error!("Failed to record FUSE message: {}", err);
+                }
+            }
+            Sink::Memory(records) => records.push(Record {
+                direction,
+                timestamp,
+                data: data.concat(),
+            }),
+        }
+    }
+
+    /// Take the records of a recorder that keeps them in memory
+    pub(crate) fn take_records(&self) -> Vec<Record> {
+        match &mut *self.sink.lock().unwrap() {
+            Sink::File(_) => Vec::new(),
+            Sink::Memory(records) => mem::take(records),
+        }
+    }
+}
+
+fn write_record<W: Write>(
+    writer: &mut W,
+    direction: Direction,
+    timestamp: SystemTime,
+    data: &[&[u8]],
+) -> io::Result<()> {
+    let len: usize = data.iter().map(|d| d.len()).sum();
+    let nanos = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let direction: u8 = match direction {
+        Direction::Request => 0,
+        Direction::Reply => 1,
+    };
+    writer.write_all(&[direction, 0, 0, 0])?;
+    writer.write_all(&(len as u32).to_le_bytes())?;
+    writer.write_all(&nanos.to_le_bytes())?;
+    for d in data {
+        writer.write_all(d)?;
+    }
+    Ok(())
+}
+
+/// Reader of the records of a capture file
+#[derive(Debug)]
+pub struct CaptureReader {
+    reader: BufReader<File>,
+}
+
+impl CaptureReader {
+    /// Open the capture file at the given path. Fails with `InvalidData` if it isn't a capture
+    /// file of a supported version.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CaptureReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0; 16];
+        reader.read_exact(&mut header)?;
+        if &header[0..8] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a FUSE capture file",
+            ));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported FUSE capture version {}", version),
+            ));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    /// Read the next record, or None at the end of the file
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0; 16];
+        match self.reader.read_exact(&mut header[..1]) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        self.reader.read_exact(&mut header[1..])?;
+        let direction = match header[0] {
+            0 => Direction::Request,
+            1 => Direction::Reply,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid FUSE capture record",
+                ))
+            }
+        };
+        let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let nanos = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let mut data = vec![0; len];
+        self.reader.read_exact(&mut data)?;
+        Ok(Some(Record {
+            direction,
+            timestamp: UNIX_EPOCH + Duration::from_nanos(nanos),
+            data,
+        }))
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        self.read_record().transpose()
+    }
+}
+
+/// A reply that differs between the recording and the replay
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayMismatch {
+    /// Unique id of the request (0 for notifications)
+    pub unique: u64,
+    /// Recorded reply, None if the filesystem sent a reply that wasn't recorded
+    pub recorded: Option<Vec<u8>>,
+    /// Reply of the filesystem, None if the filesystem didn't send a recorded reply
+    pub replayed: Option<Vec<u8>>,
+}
+
+/// Result of replaying a capture file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Number of requests passed to the filesystem
+    pub requests: usize,
+    /// Number of replies that match the recording
+    pub matched: usize,
+    /// Replies that differ from the recording
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayReport {
+    /// Returns true if all replies match the recording
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Replay the requests of the capture file at the given path to the given filesystem and
+/// compare its replies to the recorded ones. Requests are dispatched one after another, like
+/// in `Session::run`. Replies that are sent from other threads after the last request was
+/// dispatched are waited for up to the given timeout.
+pub fn replay<FS: Filesystem, P: AsRef<Path>>(
+    filesystem: &FS,
+    path: P,
+    timeout: Duration,
+) -> io::Result<ReplayReport> {
+    let mut recorded: HashMap<u64, Vec<Vec<u8>>> = HashMap::new();
+    let mut expected = 0;
+    // Replies are collected by a recorder instead of being sent to the kernel
+    let recorder = Recorder::memory();
+    let sender = ChannelSender::recording(recorder.clone());
+    let interrupts = Arc::new(InterruptTracker::default());
+    let stats = SessionStats::default();
+    let notifier = Notifier::new(sender.clone());
+    let mut initialized = false;
+    let destroyed = AtomicBool::new(false);
+    let mut report = ReplayReport::default();
+    for record in CaptureReader::open(path)? {
+        let record = record?;
+        match record.direction {
+            Direction::Request => {
+                match Request::new(sender.clone(), &record.data, &interrupts, &stats) {
+                    Ok(Some(req)) => {
+                        req.dispatch_standalone(
+                            filesystem,
+                            &mut initialized,
+                            &destroyed,
+                            &notifier,
+                        );
+                    }
+                    // Invalid requests are replied to with an error, like in a session
+                    Ok(None) => {}
+                    Err(_) => warn!("Skipping corrupt request in FUSE capture"),
+                }
+                report.requests += 1;
+            }
+            Direction::Reply => {
+                recorded
+                    .entry(reply_unique(&record.data))
+                    .or_default()
+                    .push(record.data);
+                expected += 1;
+            }
+        }
+    }
+
+    // Wait for replies that are sent asynchronously
+    let mut replies = recorder.take_records();
+    let deadline = SystemTime::now() + timeout;
+    while replies.len() < expected && SystemTime::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+        replies.extend(recorder.take_records());
+    }
+    let mut replayed: HashMap<u64, Vec<Vec<u8>>> = HashMap::new();
+    for reply in replies {
+        replayed
+            .entry(reply_unique(&reply.data))
+            .or_default()
+            .push(reply.data);
+    }
+
+    let mut uniques: Vec<u64> = recorded.keys().chain(replayed.keys()).copied().collect();
+    uniques.sort_unstable();
+    uniques.dedup();
+    for unique in uniques {
+        let mut recorded = recorded.remove(&unique).unwrap_or_default().into_iter();
+        let mut replayed = replayed.remove(&unique).unwrap_or_default().into_iter();
+        loop {
+            match (recorded.next(), replayed.next()) {
+                (None, None) => break,
+                (Some(a), Some(b)) if a == b => report.matched += 1,
+                (recorded, replayed) => report.mismatches.push(ReplayMismatch {
+                    unique,
+                    recorded,
+                    replayed,
+                }),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the unique id of the request a raw reply belongs to
+fn reply_unique(data: &[u8]) -> u64 {
+    let offset = mem::size_of::<fuse_out_header>() - mem::size_of::<u64>();
+    match data.get(offset..offset + 8) {
+        Some(bytes) => u64::from_ne_bytes(bytes.try_into().unwrap()),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{replay, CaptureReader, Direction, Recorder};
+    use crate::test_util::temp_path;
+    use crate::Filesystem;
+    use std::time::Duration;
+
+    /// Raw lookup request for the given name in the root directory
+    fn lookup(unique: u64, name: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(40 + name.len() as u32).to_ne_bytes());
+        data.extend_from_slice(&1u32.to_ne_bytes()); // FUSE_LOOKUP
+        data.extend_from_slice(&unique.to_ne_bytes());
+        data.extend_from_slice(&1u64.to_ne_bytes()); // nodeid
+        data.extend_from_slice(&[0; 16]); // uid, gid, pid, padding
+        data.extend_from_slice(name);
+        data
+    }
+
+    /// Raw error reply
+    fn error(unique: u64, err: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&16u32.to_ne_bytes());
+        data.extend_from_slice(&(-err).to_ne_bytes());
+        data.extend_from_slice(&unique.to_ne_bytes());
+        data
+    }
+
+    struct TestFs;
+
+    impl Filesystem for TestFs {}
+
+    #[test]
+    fn record_and_read() {
+        let path = temp_path("capture-read");
+        let recorder = Recorder::create(&path).unwrap();
+        recorder.request(&lookup(1, b"hello\0"));
+        recorder.reply(&[&error(1, libc::ENOENT)[..8], &error(1, libc::ENOENT)[8..]]);
+        drop(recorder);
+        let records: Vec<_> = CaptureReader::open(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Request);
+        assert_eq!(records[0].data, lookup(1, b"hello\0"));
+        assert_eq!(records[1].direction, Direction::Reply);
+        assert_eq!(records[1].data, error(1, libc::ENOENT));
+    }
+
+    #[test]
+    fn replay_before_init() {
+        let path = temp_path("capture-replay");
+        let recorder = Recorder::create(&path).unwrap();
+        // Requests before init are replied to with EIO
+        recorder.request(&lookup(1, b"hello\0"));
+        recorder.reply(&[&error(1, libc::EIO)]);
+        recorder.request(&lookup(2, b"hello\0"));
+        recorder.reply(&[&error(2, libc::ENOENT)]);
+        drop(recorder);
+        let report = replay(&TestFs, &path, Duration::from_secs(1)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(report.requests, 2);
+        assert_eq!(report.matched, 1);
+        assert!(!report.is_match());
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].unique, 2);
+        assert_eq!(report.mismatches[0].recorded, Some(error(2, libc::ENOENT)));
+        assert_eq!(report.mismatches[0].replayed, Some(error(2, libc::EIO)));
+    }
+
+    #[test]
+    fn invalid_file() {
+        let path = temp_path("capture-invalid");
+        std::fs::write(&path, b"not a capture file").unwrap();
+        let err = CaptureReader::open(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
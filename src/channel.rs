@@ -6,6 +6,8 @@
 use crate::fuse_sys::fuse_args;
 #[cfg(all(not(feature = "abi-7-20"), feature = "libfuse"))]
 use crate::fuse_sys::fuse_mount_compat25;
+#[cfg(target_os = "linux")]
+use crate::fuse_sys::fuse_unmount_fusermount;
 #[cfg(not(feature = "libfuse"))]
 use crate::fuse_sys::{fuse_mount_pure, fuse_unmount_pure};
 #[cfg(all(feature = "abi-7-20", feature = "libfuse"))]
@@ -14,15 +16,21 @@ use crate::fuse_sys::{
     fuse_session_unmount,
 };
 use libc::{self, c_int, c_void, size_t};
-use log::error;
+use log::{error, info};
 #[cfg(any(feature = "libfuse", test))]
 use std::ffi::OsStr;
 use std::ffi::{CStr, CString};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 use std::{io, ptr};
 
-use crate::reply::ReplySender;
+use crate::capture::Recorder;
+use crate::fuse_abi::FUSE_KERNEL_MINOR_VERSION;
+use crate::reply::{self, ReplySender};
+use crate::splice::{self, Pipe, SpliceMode};
 #[cfg(not(feature = "libfuse"))]
 use crate::MountOption;
 
@@ -46,6 +54,9 @@ pub struct Channel {
     mountpoint: PathBuf,
     pub(in crate) fd: c_int,
     pub(in crate) fuse_session: *mut c_void,
+    splice: SpliceMode,
+    recorder: Option<Recorder>,
+    proto_minor: Arc<AtomicU32>,
 }
 
 impl Channel {
@@ -66,6 +77,9 @@ impl Channel {
                     mountpoint,
                     fd,
                     fuse_session: ptr::null_mut(),
+                    splice: SpliceMode::default(),
+                    recorder: None,
+                    proto_minor: Arc::new(AtomicU32::new(FUSE_KERNEL_MINOR_VERSION)),
                 })
             }
         })
@@ -92,6 +106,9 @@ impl Channel {
                     mountpoint,
                     fd,
                     fuse_session,
+                    splice: SpliceMode::default(),
+                    recorder: None,
+                    proto_minor: Arc::new(AtomicU32::new(FUSE_KERNEL_MINOR_VERSION)),
                 })
             }
         })
@@ -108,10 +125,27 @@ impl Channel {
                 mountpoint,
                 fd,
                 fuse_session: ptr::null_mut(),
+                splice: SpliceMode::default(),
+                recorder: None,
+                proto_minor: Arc::new(AtomicU32::new(FUSE_KERNEL_MINOR_VERSION)),
             })
         }
     }
 
+    /// Create a channel that communicates through the given fd instead of a mounted
+    /// filesystem, e.g. a socket standing in for the kernel driver in tests
+    #[cfg(test)]
+    pub(crate) fn from_fd(mountpoint: &Path, fd: c_int) -> Channel {
+        Channel {
+            mountpoint: mountpoint.to_path_buf(),
+            fd,
+            fuse_session: ptr::null_mut(),
+            splice: SpliceMode::default(),
+            recorder: None,
+            proto_minor: Arc::new(AtomicU32::new(FUSE_KERNEL_MINOR_VERSION)),
+        }
+    }
+
     /// Return path of the mounted filesystem
     pub fn mountpoint(&self) -> &Path {
         &self.mountpoint
@@ -119,23 +153,35 @@ impl Channel {
 
     /// Receives data up to the capacity of the given buffer (can block).
     pub fn receive(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
-        let rc = unsafe {
-            libc::read(
-                self.fd,
-                buffer.as_ptr() as *mut c_void,
-                buffer.capacity() as size_t,
-            )
-        };
-        if rc < 0 {
-            Err(io::Error::last_os_error())
-        } else {
-            unsafe {
-                buffer.set_len(rc as usize);
-            }
-            Ok(())
+        receive(self.fd, buffer, self.recorder.as_ref())
+    }
+
+    /// Receives a request through the given pipe (see `Pipe::receive`)
+    pub(crate) fn receive_splice(&self, buffer: &mut Vec<u8>, pipe: &Pipe) -> io::Result<usize> {
+        pipe.receive(self.fd, buffer)
+    }
+
+    /// Set the splice mode negotiated during initialization. It's used by senders and clones
+    /// created afterwards.
+    pub(crate) fn set_splice(&mut self, splice: SpliceMode) {
+        self.splice = splice;
+    }
+
+    /// Returns the splice mode of this channel. Splicing is disabled while recording, so
+    /// that all data passes through the recorder.
+    pub(crate) fn splice(&self) -> SpliceMode {
+        match self.recorder {
+            Some(_) => SpliceMode::default(),
+            None => self.splice,
         }
     }
 
+    /// Set a recorder for all requests received and replies sent. It's used by senders and
+    /// clones created afterwards.
+    pub(crate) fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
     /// Returns a sender object for this channel. The sender object can be
     /// used to send to the channel. Multiple sender objects can be used
     /// and they can safely be sent to other threads.
@@ -144,12 +190,142 @@ impl Channel {
         // a sender by using the same fd and use it in other threads. Only
         // the channel closes the fd when dropped. If any sender is used after
         // dropping the channel, it'll return an EBADF error.
-        ChannelSender { fd: self.fd }
+        ChannelSender {
+            fd: self.fd,
+            splice: self.splice(),
+            recorder: self.recorder.clone(),
+            proto_minor: self.proto_minor.clone(),
+        }
+    }
+
+    /// Returns a handle for unmounting the filesystem of this channel from any thread
+    pub(crate) fn mount(&self) -> io::Result<Mount> {
+        let fd = unsafe { libc::fcntl(self.fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mount {
+            mountpoint: self.mountpoint.clone(),
+            fd,
+        })
+    }
+
+    /// Create a clone of this channel's connection to the kernel driver. A clone can be
+    /// used to receive requests concurrently to this channel (e.g. in another thread).
+    pub fn clone_channel(&self) -> io::Result<ChannelClone> {
+        let fd = clone_fd(self.fd)?;
+        Ok(ChannelClone {
+            fd,
+            splice: self.splice(),
+            recorder: self.recorder.clone(),
+            proto_minor: self.proto_minor.clone(),
+        })
     }
 }
 
 unsafe impl Send for Channel {}
 
+/// Receives data up to the capacity of the given buffer from the given fd (can block) and
+/// records it if a recorder is given.
+fn receive(fd: c_int, buffer: &mut Vec<u8>, recorder: Option<&Recorder>) -> io::Result<()> {
+    let rc = unsafe {
+        libc::read(
+            fd,
+            buffer.as_ptr() as *mut c_void,
+            buffer.capacity() as size_t,
+        )
+    };
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        unsafe {
+            buffer.set_len(rc as usize);
+        }
+        if let Some(recorder) = recorder {
+            recorder.request(buffer);
+        }
+        Ok(())
+    }
+}
+
+/// Ioctl to attach a new /dev/fuse fd to an existing FUSE connection,
+/// _IOR(229, 0, uint32_t)
+#[cfg(target_os = "linux")]
+const FUSE_DEV_IOC_CLONE: libc::c_ulong = 0x8004_e500;
+
+/// Returns a new fd for the FUSE connection of the given fd. On Linux, the new fd is opened
+/// with FUSE_DEV_IOC_CLONE, so that it gets its own request queue in the kernel. If cloning
+/// is not supported (non-Linux systems or kernels before 4.5), the fd is duplicated instead.
+fn clone_fd(fd: c_int) -> io::Result<c_int> {
+    #[cfg(target_os = "linux")]
+    {
+        let path = CString::new("/dev/fuse").unwrap();
+        let clone_fd = unsafe { libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        if clone_fd >= 0 {
+            let mut master_fd = fd as u32;
+            let rc = unsafe { libc::ioctl(clone_fd, FUSE_DEV_IOC_CLONE as _, &mut master_fd) };
+            if rc == 0 {
+                return Ok(clone_fd);
+            }
+            unsafe {
+                libc::close(clone_fd);
+            }
+        }
+    }
+    let dup_fd = unsafe { libc::dup(fd) };
+    if dup_fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(dup_fd)
+    }
+}
+
+/// A clone of a channel's connection to the kernel driver. Requests received on a clone
+/// must be replied to using the sender of the same clone. Dropping a clone only closes
+/// its fd, the filesystem stays mounted until the original channel is dropped.
+#[derive(Debug)]
+pub struct ChannelClone {
+    fd: c_int,
+    splice: SpliceMode,
+    recorder: Option<Recorder>,
+    proto_minor: Arc<AtomicU32>,
+}
+
+impl ChannelClone {
+    /// Receives data up to the capacity of the given buffer (can block).
+    pub fn receive(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        receive(self.fd, buffer, self.recorder.as_ref())
+    }
+
+    /// Receives a request through the given pipe (see `Pipe::receive`)
+    pub(crate) fn receive_splice(&self, buffer: &mut Vec<u8>, pipe: &Pipe) -> io::Result<usize> {
+        pipe.receive(self.fd, buffer)
+    }
+
+    /// Returns the splice mode of this channel clone
+    pub(crate) fn splice(&self) -> SpliceMode {
+        self.splice
+    }
+
+    /// Returns a sender object for this channel clone.
+    pub fn sender(&self) -> ChannelSender {
+        ChannelSender {
+            fd: self.fd,
+            splice: self.splice,
+            recorder: self.recorder.clone(),
+            proto_minor: self.proto_minor.clone(),
+        }
+    }
+}
+
+impl Drop for ChannelClone {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
 impl Drop for Channel {
     fn drop(&mut self) {
         // TODO: send ioctl FUSEDEVIOCSETDAEMONDEAD on macOS before closing the fd
@@ -161,17 +337,103 @@ impl Drop for Channel {
         // Unmount this channel's mount point
         let _ = unmount(&self.mountpoint, self.fuse_session, self.fd);
         self.fuse_session = ptr::null_mut(); // unmount frees this pointer
+        info!("Unmounted {}", self.mountpoint.display());
+    }
+}
+
+/// Handle for unmounting the filesystem of a channel while the channel is in use. It keeps
+/// a duplicate of the channel's fd, which tells whether the filesystem is still mounted.
+#[derive(Debug)]
+pub(crate) struct Mount {
+    mountpoint: PathBuf,
+    fd: c_int,
+}
+
+impl Mount {
+    /// Return path of the mounted filesystem
+    pub(crate) fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Unmount the filesystem lazily, i.e. it's released by the kernel driver once files that
+    /// are still open on it are closed. Fails if the filesystem isn't mounted anymore.
+    pub(crate) fn unmount(&self) -> io::Result<()> {
+        // The kernel driver reports an error on the fd once the filesystem is unmounted. The
+        // mountpoint isn't unmounted again then, since another filesystem may be mounted there
+        // by now.
+        let mut pollfd = libc::pollfd {
+            fd: self.fd,
+            events: 0,
+            revents: 0,
+        };
+        let rc = unsafe { libc::poll(&mut pollfd, 1, 0) };
+        if rc > 0 && pollfd.revents & libc::POLLERR != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Filesystem is not mounted",
+            ));
+        }
+        let mnt = CString::new(self.mountpoint.as_os_str().as_bytes())?;
+        #[cfg(target_os = "linux")]
+        let rc = unsafe { libc::umount2(mnt.as_ptr(), libc::MNT_DETACH) };
+        #[cfg(not(target_os = "linux"))]
+        let rc = unsafe { libc::unmount(mnt.as_ptr(), 0) };
+        if rc == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        // Linux always returns EPERM for non-root users, which unmount through the
+        // setuid-root fusermount instead
+        #[cfg(target_os = "linux")]
+        if err.raw_os_error() == Some(libc::EPERM) {
+            return fuse_unmount_fusermount(&mnt);
+        }
+        Err(err)
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct ChannelSender {
     fd: c_int,
+    splice: SpliceMode,
+    recorder: Option<Recorder>,
+    proto_minor: Arc<AtomicU32>,
 }
 
 impl ChannelSender {
+    /// Create a sender that isn't connected to the kernel driver and only records everything
+    /// sent, to collect the replies of a replayed capture
+    pub(crate) fn recording(recorder: Recorder) -> ChannelSender {
+        ChannelSender {
+            fd: -1,
+            splice: SpliceMode::default(),
+            recorder: Some(recorder),
+            proto_minor: Arc::new(AtomicU32::new(FUSE_KERNEL_MINOR_VERSION)),
+        }
+    }
+
+    /// Set the ABI minor version negotiated during initialization. It's shared with the
+    /// channel this sender belongs to and all its other senders and clones.
+    pub(crate) fn set_proto_minor(&self, minor: u32) {
+        self.proto_minor.store(minor, Ordering::SeqCst);
+    }
+
     /// Send all data in the slice of slice of bytes in a single write (can block).
     pub fn send(&self, buffer: &[&[u8]]) -> io::Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.reply(buffer);
+            if self.fd < 0 {
+                return Ok(());
+            }
+        }
         let iovecs: Vec<_> = buffer
             .iter()
             .map(|d| libc::iovec {
@@ -194,6 +456,19 @@ impl ReplySender for ChannelSender {
             error!("Failed to send FUSE reply: {}", err);
         }
     }
+
+    fn send_from_fd(&self, unique: u64, fd: RawFd, offset: i64, len: usize) -> io::Result<usize> {
+        if let Some(flags) = self.splice.write_flags() {
+            if let Some(sent) = splice::send_from_fd(self.fd, unique, fd, offset, len, flags)? {
+                return Ok(sent);
+            }
+        }
+        reply::copy_from_fd(self, unique, fd, offset, len)
+    }
+
+    fn proto_minor(&self) -> u32 {
+        self.proto_minor.load(Ordering::SeqCst)
+    }
 }
 
 /// Unmount an arbitrary mount point
@@ -243,13 +518,15 @@ pub fn unmount(mountpoint: &Path, fuse_session: *mut c_void, fd: c_int) -> io::R
             }
             #[cfg(all(feature = "abi-7-20", feature = "libfuse"))]
             unsafe {
-                if fuse_session.is_null() {
+                if !fuse_session.is_null() {
                     fuse_session_unmount(fuse_session);
                     fuse_session_destroy(fuse_session);
                 }
             }
             #[cfg(not(feature = "libfuse"))]
-            fuse_unmount_pure(mnt, fd);
+            if let Err(err) = fuse_unmount_pure(mnt, fd) {
+                error!("Failed to unmount {}: {}", mnt.to_string_lossy(), err);
+            }
 
             0
         } else {
@@ -0,0 +1,214 @@
+//! Error numbers
+//!
+//! Failed operations are replied to with a positive error number (errno), which the kernel
+//! returns to the calling process. `Errno` makes sure that only valid error numbers are sent.
+
+use libc::c_int;
+use std::convert::TryFrom;
+use std::{error, fmt, io};
+
+/// Largest error number the kernel accepts in replies (MAX_ERRNO)
+const MAX_ERRNO: c_int = 4095;
+
+/// An error number to fail an operation with. It's always a valid error number, i.e. positive
+/// and at most 4095. Well-known errors are available as constants, other error codes can be
+/// checked with `Errno::new` or `TryFrom<i32>` (e.g. the `i32` value of another library's errno
+/// type). An `io::Error` converts to its OS error code, or to the error that best matches its
+/// kind if it has none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Errno(c_int);
+
+impl Errno {
+    /// Operation not permitted
+    pub const EPERM: Errno = Errno(libc::EPERM);
+    /// No such file or directory
+    pub const ENOENT: Errno = Errno(libc::ENOENT);
+    /// Interrupted system call
+    pub const EINTR: Errno = Errno(libc::EINTR);
+    /// I/O error
+    pub const EIO: Errno = Errno(libc::EIO);
+    /// No such device or address
+    pub const ENXIO: Errno = Errno(libc::ENXIO);
+    /// Argument list too long
+    pub const E2BIG: Errno = Errno(libc::E2BIG);
+    /// Bad file descriptor
+    pub const EBADF: Errno = Errno(libc::EBADF);
+    /// Resource temporarily unavailable, try again
+    pub const EAGAIN: Errno = Errno(libc::EAGAIN);
+    /// Out of memory
+    pub const ENOMEM: Errno = Errno(libc::ENOMEM);
+    /// Permission denied
+    pub const EACCES: Errno = Errno(libc::EACCES);
+    /// Bad address
+    pub const EFAULT: Errno = Errno(libc::EFAULT);
+    /// Device or resource busy
+    pub const EBUSY: Errno = Errno(libc::EBUSY);
+    /// File exists
+    pub const EEXIST: Errno = Errno(libc::EEXIST);
+    /// Cross-device link
+    pub const EXDEV: Errno = Errno(libc::EXDEV);
+    /// No such device
+    pub const ENODEV: Errno = Errno(libc::ENODEV);
+    /// Not a directory
+    pub const ENOTDIR: Errno = Errno(libc::ENOTDIR);
+    /// Is a directory
+    pub const EISDIR: Errno = Errno(libc::EISDIR);
+    /// Invalid argument
+    pub const EINVAL: Errno = Errno(libc::EINVAL);
+    /// Too many open files in system
+    pub const ENFILE: Errno = Errno(libc::ENFILE);
+    /// Too many open files
+    pub const EMFILE: Errno = Errno(libc::EMFILE);
+    /// Inappropriate ioctl for device
+    pub const ENOTTY: Errno = Errno(libc::ENOTTY);
+    /// Text file busy
+    pub const ETXTBSY: Errno = Errno(libc::ETXTBSY);
+    /// File too large
+    pub const EFBIG: Errno = Errno(libc::EFBIG);
+    /// No space left on device
+    pub const ENOSPC: Errno = Errno(libc::ENOSPC);
+    /// Illegal seek
+    pub const ESPIPE: Errno = Errno(libc::ESPIPE);
+    /// Read-only file system
+    pub const EROFS: Errno = Errno(libc::EROFS);
+    /// Too many links
+    pub const EMLINK: Errno = Errno(libc::EMLINK);
+    /// Broken pipe
+    pub const EPIPE: Errno = Errno(libc::EPIPE);
+    /// Result too large
+    pub const ERANGE: Errno = Errno(libc::ERANGE);
+    /// Resource deadlock would occur
+    pub const EDEADLK: Errno = Errno(libc::EDEADLK);
+    /// File name too long
+    pub const ENAMETOOLONG: Errno = Errno(libc::ENAMETOOLONG);
+    /// No locks available
+    pub const ENOLCK: Errno = Errno(libc::ENOLCK);
+    /// Function not implemented
+    pub const ENOSYS: Errno = Errno(libc::ENOSYS);
+    /// Directory not empty
+    pub const ENOTEMPTY: Errno = Errno(libc::ENOTEMPTY);
+    /// Too many levels of symbolic links
+    pub const ELOOP: Errno = Errno(libc::ELOOP);
+    /// No data available
+    pub const ENODATA: Errno = Errno(libc::ENODATA);
+    /// Protocol error
+    pub const EPROTO: Errno = Errno(libc::EPROTO);
+    /// Value too large for defined data type
+    pub const EOVERFLOW: Errno = Errno(libc::EOVERFLOW);
+    /// Operation not supported
+    pub const ENOTSUP: Errno = Errno(libc::ENOTSUP);
+    /// Operation not supported on socket
+    pub const EOPNOTSUPP: Errno = Errno(libc::EOPNOTSUPP);
+    /// Connection timed out
+    pub const ETIMEDOUT: Errno = Errno(libc::ETIMEDOUT);
+    /// Stale file handle
+    pub const ESTALE: Errno = Errno(libc::ESTALE);
+    /// Disk quota exceeded
+    pub const EDQUOT: Errno = Errno(libc::EDQUOT);
+    /// Extended attribute not found (ENODATA on Linux, ENOATTR on macOS)
+    #[cfg(not(target_os = "macos"))]
+    pub const NO_XATTR: Errno = Errno(libc::ENODATA);
+    /// Extended attribute not found (ENODATA on Linux, ENOATTR on macOS)
+    #[cfg(target_os = "macos")]
+    pub const NO_XATTR: Errno = Errno(libc::ENOATTR);
+
+    /// Returns the error number with the given code, or None if it isn't a valid error number
+    pub fn new(code: c_int) -> Option<Errno> {
+        if code > 0 && code <= MAX_ERRNO {
+            Some(Errno(code))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the raw error code
+    pub fn code(&self) -> c_int {
+        self.0
+    }
+}
+
+impl TryFrom<c_int> for Errno {
+    /// The invalid error code
+    type Error = c_int;
+
+    fn try_from(code: c_int) -> Result<Errno, c_int> {
+        Errno::new(code).ok_or(code)
+    }
+}
+
+impl From<Errno> for c_int {
+    fn from(errno: Errno) -> c_int {
+        errno.0
+    }
+}
+
+impl From<io::ErrorKind> for Errno {
+    fn from(kind: io::ErrorKind) -> Errno {
+        match kind {
+            io::ErrorKind::NotFound => Errno::ENOENT,
+            io::ErrorKind::PermissionDenied => Errno::EACCES,
+            io::ErrorKind::AlreadyExists => Errno::EEXIST,
+            io::ErrorKind::WouldBlock => Errno::EAGAIN,
+            io::ErrorKind::InvalidInput => Errno::EINVAL,
+            io::ErrorKind::TimedOut => Errno::ETIMEDOUT,
+            io::ErrorKind::Interrupted => Errno::EINTR,
+            io::ErrorKind::BrokenPipe => Errno::EPIPE,
+            io::ErrorKind::Unsupported => Errno::ENOTSUP,
+            io::ErrorKind::OutOfMemory => Errno::ENOMEM,
+            _ => Errno::EIO,
+        }
+    }
+}
+
+impl From<io::Error> for Errno {
+    fn from(err: io::Error) -> Errno {
+        match err.raw_os_error().and_then(Errno::new) {
+            Some(errno) => errno,
+            None => Errno::from(err.kind()),
+        }
+    }
+}
+
+impl From<Errno> for io::Error {
+    fn from(errno: Errno) -> io::Error {
+        io::Error::from_raw_os_error(errno.0)
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", io::Error::from_raw_os_error(self.0))
+    }
+}
+
+impl error::Error for Errno {}
+
+#[cfg(test)]
+mod test {
+    use super::Errno;
+    use std::convert::TryFrom;
+    use std::io;
+
+    #[test]
+    fn validation() {
+        assert_eq!(Errno::new(libc::ENOENT), Some(Errno::ENOENT));
+        assert_eq!(Errno::new(0), None);
+        assert_eq!(Errno::new(-libc::ENOENT), None);
+        assert_eq!(Errno::new(4096), None);
+        assert_eq!(Errno::try_from(libc::EIO), Ok(Errno::EIO));
+        assert_eq!(Errno::try_from(-1), Err(-1));
+        assert_eq!(libc::c_int::from(Errno::EACCES), libc::EACCES);
+    }
+
+    #[test]
+    fn io_error() {
+        let err = io::Error::from_raw_os_error(libc::ENOSPC);
+        assert_eq!(Errno::from(err), Errno::ENOSPC);
+        let err = io::Error::new(io::ErrorKind::NotFound, "not found");
+        assert_eq!(Errno::from(err), Errno::ENOENT);
+        let err = io::Error::new(io::ErrorKind::Other, "other");
+        assert_eq!(Errno::from(err), Errno::EIO);
+        let err = io::Error::from(Errno::EEXIST);
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+}
@@ -0,0 +1,208 @@
+//! Argument and result types of filesystem operations
+//!
+//! These are shared by `AsyncFilesystem` and `PathFilesystem`, whose methods return their
+//! results instead of replying through a reply object. Both modules re-export them.
+
+use std::ffi::OsString;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::fuse_abi::consts::*;
+use crate::fuse_abi::fuse_setattr_in;
+use crate::{FileAttr, FileType};
+
+/// Result of an operation that looks up or creates a directory entry
+#[derive(Clone, Copy, Debug)]
+pub struct EntryOut {
+    /// Attributes of the entry's inode
+    pub attr: FileAttr,
+    /// Time the kernel may cache the entry and its attributes
+    pub ttl: Duration,
+    /// Generation of the inode, which must be different for every reuse of an inode number
+    pub generation: u64,
+}
+
+/// Result of an operation that returns file attributes
+#[derive(Clone, Copy, Debug)]
+pub struct AttrOut {
+    /// Attributes of the inode
+    pub attr: FileAttr,
+    /// Time the kernel may cache the attributes
+    pub ttl: Duration,
+}
+
+/// Result of opening a file or directory
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OpenOut {
+    /// File handle that is passed to all operations on the opened file
+    pub fh: u64,
+    /// FOPEN_* flags in `consts`
+    pub flags: u32,
+}
+
+/// Result of creating and opening a file
+#[derive(Clone, Copy, Debug)]
+pub struct CreateOut {
+    /// The created entry
+    pub entry: EntryOut,
+    /// The opened file
+    pub open: OpenOut,
+}
+
+/// Filesystem statistics
+#[derive(Clone, Copy, Debug)]
+#[allow(missing_docs)]
+pub struct StatfsOut {
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub bsize: u32,
+    pub namelen: u32,
+    pub frsize: u32,
+}
+
+impl Default for StatfsOut {
+    fn default() -> StatfsOut {
+        StatfsOut {
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: 0,
+            ffree: 0,
+            bsize: 512,
+            namelen: 255,
+            frsize: 0,
+        }
+    }
+}
+
+/// Result of getting or listing extended attributes
+#[derive(Clone, Debug)]
+pub enum XattrOut {
+    /// Size of the value, if the requested size was 0
+    Size(u32),
+    /// The value, if it fits into the requested size
+    Data(Vec<u8>),
+}
+
+/// An entry returned by readdir
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    /// Inode number of the entry
+    pub ino: u64,
+    /// Offset of the next entry, which is passed to readdir to continue after this entry
+    pub offset: i64,
+    /// Kind of the entry
+    pub kind: FileType,
+    /// Name of the entry
+    pub name: OsString,
+}
+
+/// An entry returned by readdirplus
+#[derive(Clone, Debug)]
+pub struct DirEntryPlus {
+    /// Offset of the next entry, which is passed to readdirplus to continue after this entry
+    pub offset: i64,
+    /// Name of the entry
+    pub name: OsString,
+    /// The entry and its attributes, like returned by lookup
+    pub entry: EntryOut,
+}
+
+/// A POSIX file lock, as returned by getlk
+#[derive(Clone, Copy, Debug)]
+pub struct LockOut {
+    /// Start of the locked range
+    pub start: u64,
+    /// End of the locked range
+    pub end: u64,
+    /// Type of the lock (F_RDLCK, F_WRLCK or F_UNLCK)
+    pub typ: u32,
+    /// Process holding the lock
+    pub pid: u32,
+}
+
+/// Result of an ioctl
+#[derive(Clone, Debug)]
+pub enum IoctlOut {
+    /// Result and output data of the ioctl
+    Done {
+        /// Return value of the ioctl call
+        result: i32,
+        /// Output data, at most the requested output size
+        data: Vec<u8>,
+    },
+    /// Retry an unrestricted ioctl with the given input and output buffers, see
+    /// `ReplyIoctl::retry`
+    Retry {
+        /// Input buffers as (address, length) pairs
+        in_iovs: Vec<(u64, u64)>,
+        /// Output buffers as (address, length) pairs
+        out_iovs: Vec<(u64, u64)>,
+    },
+}
+
+/// Attributes to change in a setattr operation. Unset values are not changed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetAttrIn {
+    /// New permissions and file type
+    pub mode: Option<u32>,
+    /// New owner
+    pub uid: Option<u32>,
+    /// New group
+    pub gid: Option<u32>,
+    /// New size, i.e. truncate or extend the file
+    pub size: Option<u64>,
+    /// New access time
+    pub atime: Option<SystemTime>,
+    /// Set the access time to the current time
+    pub atime_now: bool,
+    /// New modification time
+    pub mtime: Option<SystemTime>,
+    /// Set the modification time to the current time
+    pub mtime_now: bool,
+    /// File handle, if the attributes are changed on an open file (e.g. ftruncate)
+    pub fh: Option<u64>,
+}
+
+impl SetAttrIn {
+    pub(crate) fn from_arg(arg: &fuse_setattr_in) -> SetAttrIn {
+        let valid = |flag: u32| arg.valid & flag != 0;
+        SetAttrIn {
+            mode: if valid(FATTR_MODE) {
+                Some(arg.mode)
+            } else {
+                None
+            },
+            uid: if valid(FATTR_UID) {
+                Some(arg.uid)
+            } else {
+                None
+            },
+            gid: if valid(FATTR_GID) {
+                Some(arg.gid)
+            } else {
+                None
+            },
+            size: if valid(FATTR_SIZE) {
+                Some(arg.size)
+            } else {
+                None
+            },
+            atime: if valid(FATTR_ATIME) {
+                Some(UNIX_EPOCH + Duration::new(arg.atime, arg.atimensec))
+            } else {
+                None
+            },
+            atime_now: arg.atime_now(),
+            mtime: if valid(FATTR_MTIME) {
+                Some(UNIX_EPOCH + Duration::new(arg.mtime, arg.mtimensec))
+            } else {
+                None
+            },
+            mtime_now: arg.mtime_now(),
+            fh: if valid(FATTR_FH) { Some(arg.fh) } else { None },
+        }
+    }
+}
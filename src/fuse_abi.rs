@@ -16,65 +16,31 @@
 //! - supports ABI 7.19 since FUSE 2.9.1
 //! - supports ABI 7.26 since FUSE 3.0.0
 //!
-//! Items without a version annotation are valid with ABI 7.8 and later
+//! All items are defined as of the latest ABI version supported by this library. The ABI
+//! version is negotiated at runtime: fields that were added later are zero with older kernel
+//! drivers and structs that grew are sent with their former size (see `FUSE_COMPAT_*`).
 
 #![warn(missing_debug_implementations)]
 #![allow(missing_docs)]
 
-#[cfg(feature = "abi-7-9")]
 use crate::consts::{FATTR_ATIME_NOW, FATTR_MTIME_NOW};
 use std::convert::TryFrom;
+use std::mem;
 
 pub const FUSE_KERNEL_VERSION: u32 = 7;
 
-#[cfg(not(feature = "abi-7-9"))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 8;
-#[cfg(all(feature = "abi-7-9", not(feature = "abi-7-10")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 9;
-#[cfg(all(feature = "abi-7-10", not(feature = "abi-7-11")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 10;
-#[cfg(all(feature = "abi-7-11", not(feature = "abi-7-12")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 11;
-#[cfg(all(feature = "abi-7-12", not(feature = "abi-7-13")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 12;
-#[cfg(all(feature = "abi-7-13", not(feature = "abi-7-14")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 13;
-#[cfg(all(feature = "abi-7-14", not(feature = "abi-7-15")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 14;
-#[cfg(all(feature = "abi-7-15", not(feature = "abi-7-16")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 15;
-#[cfg(all(feature = "abi-7-16", not(feature = "abi-7-17")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 16;
-#[cfg(all(feature = "abi-7-17", not(feature = "abi-7-18")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 17;
-#[cfg(all(feature = "abi-7-18", not(feature = "abi-7-19")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 18;
-#[cfg(all(feature = "abi-7-19", not(feature = "abi-7-20")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 19;
-#[cfg(all(feature = "abi-7-20", not(feature = "abi-7-21")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 20;
-#[cfg(all(feature = "abi-7-21", not(feature = "abi-7-22")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 21;
-#[cfg(all(feature = "abi-7-22", not(feature = "abi-7-23")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 22;
-#[cfg(all(feature = "abi-7-23", not(feature = "abi-7-24")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 23;
-#[cfg(all(feature = "abi-7-24", not(feature = "abi-7-25")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 24;
-#[cfg(all(feature = "abi-7-25", not(feature = "abi-7-26")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 25;
-#[cfg(all(feature = "abi-7-26", not(feature = "abi-7-27")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 26;
-#[cfg(all(feature = "abi-7-27", not(feature = "abi-7-28")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 27;
-#[cfg(all(feature = "abi-7-28", not(feature = "abi-7-29")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 28;
-#[cfg(all(feature = "abi-7-29", not(feature = "abi-7-30")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 29;
-#[cfg(all(feature = "abi-7-30", not(feature = "abi-7-31")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 30;
-#[cfg(feature = "abi-7-31")]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 37;
+
+// Sizes of structs before the ABI version they grew in
+pub const FUSE_COMPAT_ENTRY_OUT_SIZE: usize = mem::size_of::<fuse_entry_out>() - 8; // 7.9
+pub const FUSE_COMPAT_ATTR_OUT_SIZE: usize = mem::size_of::<fuse_attr_out>() - 8; // 7.9
+pub const FUSE_COMPAT_READ_IN_SIZE: usize = 24; // 7.9
+pub const FUSE_COMPAT_WRITE_IN_SIZE: usize = 24; // 7.9
+pub const FUSE_COMPAT_LK_IN_SIZE: usize = 40; // 7.9
+pub const FUSE_COMPAT_MKNOD_IN_SIZE: usize = 8; // 7.12
+pub const FUSE_COMPAT_CREATE_IN_SIZE: usize = 8; // 7.12
+pub const FUSE_COMPAT_22_INIT_OUT_SIZE: usize = 24; // 7.23
+pub const FUSE_COMPAT_INIT_IN_SIZE: usize = 16; // 7.36
 
 pub const FUSE_ROOT_ID: u64 = 1;
 
@@ -101,9 +67,7 @@ pub struct fuse_attr {
     pub rdev: u32,
     #[cfg(target_os = "macos")]
     pub flags: u32, // see chflags(2)
-    #[cfg(feature = "abi-7-9")]
     pub blksize: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -140,12 +104,11 @@ pub mod consts {
     pub const FATTR_ATIME: u32 = 1 << 4;
     pub const FATTR_MTIME: u32 = 1 << 5;
     pub const FATTR_FH: u32 = 1 << 6;
-    #[cfg(feature = "abi-7-9")]
     pub const FATTR_ATIME_NOW: u32 = 1 << 7;
-    #[cfg(feature = "abi-7-9")]
     pub const FATTR_MTIME_NOW: u32 = 1 << 8;
-    #[cfg(feature = "abi-7-9")]
     pub const FATTR_LOCKOWNER: u32 = 1 << 9;
+    pub const FATTR_CTIME: u32 = 1 << 10;
+    pub const FATTR_KILL_SUIDGID: u32 = 1 << 11;
 
     #[cfg(target_os = "macos")]
     pub const FATTR_CRTIME: u32 = 1 << 28;
@@ -159,8 +122,10 @@ pub mod consts {
     // Flags returned by the open request
     pub const FOPEN_DIRECT_IO: u32 = 1 << 0; // bypass page cache for this open file
     pub const FOPEN_KEEP_CACHE: u32 = 1 << 1; // don't invalidate the data cache on open
-    #[cfg(feature = "abi-7-10")]
     pub const FOPEN_NONSEEKABLE: u32 = 1 << 2; // the file is not seekable
+    pub const FOPEN_CACHE_DIR: u32 = 1 << 3; // allow caching this directory
+    pub const FOPEN_STREAM: u32 = 1 << 4; // the file is stream-like (no file position at all)
+    pub const FOPEN_NOFLUSH: u32 = 1 << 5; // don't flush data cache on close (unless FUSE_WRITEBACK_CACHE)
 
     #[cfg(target_os = "macos")]
     pub const FOPEN_PURGE_ATTR: u32 = 1 << 30;
@@ -170,36 +135,42 @@ pub mod consts {
     // Init request/reply flags
     pub const FUSE_ASYNC_READ: u32 = 1 << 0; // asynchronous read requests
     pub const FUSE_POSIX_LOCKS: u32 = 1 << 1; // remote locking for POSIX file locks
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_FILE_OPS: u32 = 1 << 2; // kernel sends file handle for fstat, etc...
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_ATOMIC_O_TRUNC: u32 = 1 << 3; // handles the O_TRUNC open flag in the filesystem
-    #[cfg(feature = "abi-7-10")]
     pub const FUSE_EXPORT_SUPPORT: u32 = 1 << 4; // filesystem handles lookups of "." and ".."
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_BIG_WRITES: u32 = 1 << 5; // filesystem can handle write size larger than 4kB
-    #[cfg(feature = "abi-7-12")]
     pub const FUSE_DONT_MASK: u32 = 1 << 6; // don't apply umask to file mode on create operations
 
-    #[cfg(all(feature = "abi-7-14", not(target_os = "macos")))]
+    #[cfg(not(target_os = "macos"))]
     pub const FUSE_SPLICE_WRITE: u32 = 1 << 7; // kernel supports splice write on the device
-    #[cfg(all(feature = "abi-7-14", not(target_os = "macos")))]
+    #[cfg(not(target_os = "macos"))]
     pub const FUSE_SPLICE_MOVE: u32 = 1 << 8; // kernel supports splice move on the device
     #[cfg(not(target_os = "macos"))]
-    #[cfg(feature = "abi-7-14")]
     pub const FUSE_SPLICE_READ: u32 = 1 << 9; // kernel supports splice read on the device
-    #[cfg(feature = "abi-7-17")]
     pub const FUSE_FLOCK_LOCKS: u32 = 1 << 10; // remote locking for BSD style file locks
-    #[cfg(feature = "abi-7-18")]
     pub const FUSE_HAS_IOCTL_DIR: u32 = 1 << 11; // kernel supports ioctl on directories
-    #[cfg(feature = "abi-7-20")]
     pub const FUSE_AUTO_INVAL_DATA: u32 = 1 << 12; // automatically invalidate cached pages
-    #[cfg(feature = "abi-7-21")]
     pub const FUSE_DO_READDIRPLUS: u32 = 1 << 13; // do READDIRPLUS (READDIR+LOOKUP in one)
-    #[cfg(feature = "abi-7-21")]
     pub const FUSE_READDIRPLUS_AUTO: u32 = 1 << 14; // adaptive readdirplus
-    #[cfg(feature = "abi-7-22")]
     pub const FUSE_ASYNC_DIO: u32 = 1 << 15; // asynchronous direct I/O submission
+    pub const FUSE_WRITEBACK_CACHE: u32 = 1 << 16; // use writeback cache for buffered writes
+    pub const FUSE_PARALLEL_DIROPS: u32 = 1 << 18; // allow parallel lookups and readdir
+    pub const FUSE_HANDLE_KILLPRIV: u32 = 1 << 19; // fs handles killing suid/sgid/cap on write/chown/trunc
+    pub const FUSE_POSIX_ACL: u32 = 1 << 20; // filesystem supports posix acls
+    pub const FUSE_ABORT_ERROR: u32 = 1 << 21; // reading the device after abort returns ECONNABORTED
+    pub const FUSE_MAX_PAGES: u32 = 1 << 22; // init_out.max_pages contains the max number of req pages
+    pub const FUSE_CACHE_SYMLINKS: u32 = 1 << 23; // cache READLINK responses
+    pub const FUSE_NO_OPENDIR_SUPPORT: u32 = 1 << 24; // kernel supports zero-message opendir
+    pub const FUSE_EXPLICIT_INVAL_DATA: u32 = 1 << 25; // only invalidate cached pages on explicit request
+    pub const FUSE_MAP_ALIGNMENT: u32 = 1 << 26; // init_out.map_alignment contains log2(byte alignment)
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_SUBMOUNTS: u32 = 1 << 27; // kernel supports auto-mounting directory submounts
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_HANDLE_KILLPRIV_V2: u32 = 1 << 28; // fs kills suid/sgid/cap on write/chown/trunc
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_SETXATTR_EXT: u32 = 1 << 29; // server supports extended struct fuse_setxattr_in
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_INIT_EXT: u32 = 1 << 30; // extended fuse_init_in request (flags2)
 
     #[cfg(target_os = "macos")]
     pub const FUSE_ALLOCATE: u32 = 1 << 27;
@@ -213,50 +184,46 @@ pub mod consts {
     pub const FUSE_XTIMES: u32 = 1 << 31;
 
     // CUSE init request/reply flags
-    #[cfg(feature = "abi-7-12")]
     pub const CUSE_UNRESTRICTED_IOCTL: u32 = 1 << 0; // use unrestricted ioctl
 
     // Release flags
     pub const FUSE_RELEASE_FLUSH: u32 = 1 << 0;
-    #[cfg(feature = "abi-7-17")]
     pub const FUSE_RELEASE_FLOCK_UNLOCK: u32 = 1 << 1;
 
     // Getattr flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_GETATTR_FH: u32 = 1 << 0;
 
     // Lock flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_LK_FLOCK: u32 = 1 << 0;
 
     // Write flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_WRITE_CACHE: u32 = 1 << 0; // delayed write from page cache, file handle is guessed
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_WRITE_LOCKOWNER: u32 = 1 << 1; // lock_owner field is valid
+    pub const FUSE_WRITE_KILL_SUIDGID: u32 = 1 << 2; // kill suid and sgid bits
 
     // Read flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_READ_LOCKOWNER: u32 = 1 << 1;
 
     // IOCTL flags
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_COMPAT: u32 = 1 << 0; // 32bit compat ioctl on 64bit machine
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_UNRESTRICTED: u32 = 1 << 1; // not restricted to well-formed ioctls, retry allowed
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_RETRY: u32 = 1 << 2; // retry with new iovecs
-    #[cfg(feature = "abi-7-16")]
     pub const FUSE_IOCTL_32BIT: u32 = 1 << 3; // 32bit ioctl
-    #[cfg(feature = "abi-7-18")]
     pub const FUSE_IOCTL_DIR: u32 = 1 << 4; // is a directory
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_MAX_IOV: u32 = 256; // maximum of in_iovecs + out_iovecs
 
     // Poll flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_POLL_SCHEDULE_NOTIFY: u32 = 1 << 0; // request poll notify
 
+    // Setupmapping flags
+    pub const FUSE_SETUPMAPPING_FLAG_WRITE: u64 = 1 << 0;
+    pub const FUSE_SETUPMAPPING_FLAG_READ: u64 = 1 << 1;
+
+    // Fallocate mode flags (see fallocate(2))
+    pub const FALLOC_FL_KEEP_SIZE: i32 = 0x01; // don't change the file size
+    pub const FALLOC_FL_PUNCH_HOLE: i32 = 0x02; // deallocate the range (with KEEP_SIZE)
+    pub const FALLOC_FL_ZERO_RANGE: i32 = 0x10; // zero the range
+
     // The read buffer is required to be at least 8k, but may be much larger
     pub const FUSE_MIN_READ_BUFFER: usize = 8192;
 }
@@ -305,18 +272,19 @@ pub enum fuse_opcode {
     FUSE_INTERRUPT = 36,
     FUSE_BMAP = 37,
     FUSE_DESTROY = 38,
-    #[cfg(feature = "abi-7-11")]
     FUSE_IOCTL = 39,
-    #[cfg(feature = "abi-7-11")]
     FUSE_POLL = 40,
-    #[cfg(feature = "abi-7-15")]
     FUSE_NOTIFY_REPLY = 41,
-    #[cfg(feature = "abi-7-16")]
     FUSE_BATCH_FORGET = 42,
-    #[cfg(feature = "abi-7-19")]
     FUSE_FALLOCATE = 43,
-    #[cfg(feature = "abi-7-21")]
     FUSE_READDIRPLUS = 44,
+    FUSE_RENAME2 = 45,
+    FUSE_LSEEK = 46,
+    FUSE_COPY_FILE_RANGE = 47,
+    FUSE_SETUPMAPPING = 48,
+    FUSE_REMOVEMAPPING = 49,
+    FUSE_SYNCFS = 50,
+    FUSE_TMPFILE = 51,
 
     #[cfg(target_os = "macos")]
     FUSE_SETVOLNAME = 61,
@@ -325,7 +293,6 @@ pub enum fuse_opcode {
     #[cfg(target_os = "macos")]
     FUSE_EXCHANGE = 63,
 
-    #[cfg(feature = "abi-7-12")]
     CUSE_INIT = 4096,
 }
 
@@ -370,18 +337,19 @@ impl TryFrom<u32> for fuse_opcode {
             36 => Ok(fuse_opcode::FUSE_INTERRUPT),
             37 => Ok(fuse_opcode::FUSE_BMAP),
             38 => Ok(fuse_opcode::FUSE_DESTROY),
-            #[cfg(feature = "abi-7-11")]
             39 => Ok(fuse_opcode::FUSE_IOCTL),
-            #[cfg(feature = "abi-7-11")]
             40 => Ok(fuse_opcode::FUSE_POLL),
-            #[cfg(feature = "abi-7-15")]
             41 => Ok(fuse_opcode::FUSE_NOTIFY_REPLY),
-            #[cfg(feature = "abi-7-16")]
             42 => Ok(fuse_opcode::FUSE_BATCH_FORGET),
-            #[cfg(feature = "abi-7-19")]
             43 => Ok(fuse_opcode::FUSE_FALLOCATE),
-            #[cfg(feature = "abi-7-21")]
             44 => Ok(fuse_opcode::FUSE_READDIRPLUS),
+            45 => Ok(fuse_opcode::FUSE_RENAME2),
+            46 => Ok(fuse_opcode::FUSE_LSEEK),
+            47 => Ok(fuse_opcode::FUSE_COPY_FILE_RANGE),
+            48 => Ok(fuse_opcode::FUSE_SETUPMAPPING),
+            49 => Ok(fuse_opcode::FUSE_REMOVEMAPPING),
+            50 => Ok(fuse_opcode::FUSE_SYNCFS),
+            51 => Ok(fuse_opcode::FUSE_TMPFILE),
 
             #[cfg(target_os = "macos")]
             61 => Ok(fuse_opcode::FUSE_SETVOLNAME),
@@ -390,7 +358,6 @@ impl TryFrom<u32> for fuse_opcode {
             #[cfg(target_os = "macos")]
             63 => Ok(fuse_opcode::FUSE_EXCHANGE),
 
-            #[cfg(feature = "abi-7-12")]
             4096 => Ok(fuse_opcode::CUSE_INIT),
 
             _ => Err(InvalidOpcodeError),
@@ -399,46 +366,31 @@ impl TryFrom<u32> for fuse_opcode {
 }
 
 /// Invalid notify code error.
-#[cfg(feature = "abi-7-11")]
 #[derive(Debug)]
 pub struct InvalidNotifyCodeError;
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
 pub enum fuse_notify_code {
-    #[cfg(feature = "abi-7-11")]
     FUSE_POLL = 1,
-    #[cfg(feature = "abi-7-12")]
     FUSE_NOTIFY_INVAL_INODE = 2,
-    #[cfg(feature = "abi-7-12")]
     FUSE_NOTIFY_INVAL_ENTRY = 3,
-    #[cfg(feature = "abi-7-15")]
     FUSE_NOTIFY_STORE = 4,
-    #[cfg(feature = "abi-7-15")]
     FUSE_NOTIFY_RETRIEVE = 5,
-    #[cfg(feature = "abi-7-18")]
     FUSE_NOTIFY_DELETE = 6,
 }
 
-#[cfg(feature = "abi-7-11")]
 impl TryFrom<u32> for fuse_notify_code {
     type Error = InvalidNotifyCodeError;
 
     fn try_from(n: u32) -> Result<Self, Self::Error> {
         match n {
-            #[cfg(feature = "abi-7-11")]
             1 => Ok(fuse_notify_code::FUSE_POLL),
-            #[cfg(feature = "abi-7-12")]
             2 => Ok(fuse_notify_code::FUSE_NOTIFY_INVAL_INODE),
-            #[cfg(feature = "abi-7-12")]
             3 => Ok(fuse_notify_code::FUSE_NOTIFY_INVAL_ENTRY),
-            #[cfg(feature = "abi-7-15")]
             4 => Ok(fuse_notify_code::FUSE_NOTIFY_STORE),
-            #[cfg(feature = "abi-7-15")]
             5 => Ok(fuse_notify_code::FUSE_NOTIFY_RETRIEVE),
-            #[cfg(feature = "abi-7-18")]
             6 => Ok(fuse_notify_code::FUSE_NOTIFY_DELETE),
 
             _ => Err(InvalidNotifyCodeError),
@@ -464,7 +416,6 @@ pub struct fuse_forget_in {
     pub nlookup: u64,
 }
 
-#[cfg(feature = "abi-7-16")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_forget_one {
@@ -472,7 +423,6 @@ pub struct fuse_forget_one {
     pub nlookup: u64,
 }
 
-#[cfg(feature = "abi-7-16")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_batch_forget_in {
@@ -480,7 +430,6 @@ pub struct fuse_batch_forget_in {
     pub dummy: u32,
 }
 
-#[cfg(feature = "abi-7-9")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_getattr_in {
@@ -513,9 +462,7 @@ pub struct fuse_getxtimes_out {
 pub struct fuse_mknod_in {
     pub mode: u32,
     pub rdev: u32,
-    #[cfg(feature = "abi-7-12")]
     pub umask: u32,
-    #[cfg(feature = "abi-7-12")]
     pub padding: u32,
 }
 
@@ -523,9 +470,6 @@ pub struct fuse_mknod_in {
 #[derive(Debug)]
 pub struct fuse_mkdir_in {
     pub mode: u32,
-    #[cfg(not(feature = "abi-7-12"))]
-    pub padding: u32,
-    #[cfg(feature = "abi-7-12")]
     pub umask: u32,
 }
 
@@ -535,6 +479,14 @@ pub struct fuse_rename_in {
     pub newdir: u64,
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_rename2_in {
+    pub newdir: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
 #[cfg(target_os = "macos")]
 #[repr(C)]
 #[derive(Debug)]
@@ -557,9 +509,6 @@ pub struct fuse_setattr_in {
     pub padding: u32,
     pub fh: u64,
     pub size: u64,
-    #[cfg(not(feature = "abi-7-9"))]
-    pub unused1: u64,
-    #[cfg(feature = "abi-7-9")]
     pub lock_owner: u64,
     pub atime: u64,
     pub mtime: u64,
@@ -589,25 +538,13 @@ pub struct fuse_setattr_in {
 }
 
 impl fuse_setattr_in {
-    #[cfg(feature = "abi-7-9")]
     pub fn atime_now(&self) -> bool {
         self.valid & FATTR_ATIME_NOW != 0
     }
 
-    #[cfg(not(feature = "abi-7-9"))]
-    pub fn atime_now(&self) -> bool {
-        false
-    }
-
-    #[cfg(feature = "abi-7-9")]
     pub fn mtime_now(&self) -> bool {
         self.valid & FATTR_MTIME_NOW != 0
     }
-
-    #[cfg(not(feature = "abi-7-9"))]
-    pub fn mtime_now(&self) -> bool {
-        false
-    }
 }
 
 #[repr(C)]
@@ -622,9 +559,7 @@ pub struct fuse_open_in {
 pub struct fuse_create_in {
     pub flags: u32,
     pub mode: u32,
-    #[cfg(feature = "abi-7-12")]
     pub umask: u32,
-    #[cfg(feature = "abi-7-12")]
     pub padding: u32,
 }
 
@@ -660,13 +595,9 @@ pub struct fuse_read_in {
     pub fh: u64,
     pub offset: u64,
     pub size: u32,
-    #[cfg(feature = "abi-7-9")]
     pub read_flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub lock_owner: u64,
-    #[cfg(feature = "abi-7-9")]
     pub flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -677,11 +608,8 @@ pub struct fuse_write_in {
     pub offset: u64,
     pub size: u32,
     pub write_flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub lock_owner: u64,
-    #[cfg(feature = "abi-7-9")]
     pub flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -741,9 +669,7 @@ pub struct fuse_lk_in {
     pub fh: u64,
     pub owner: u64,
     pub lk: fuse_file_lock,
-    #[cfg(feature = "abi-7-9")]
     pub lk_flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -767,6 +693,8 @@ pub struct fuse_init_in {
     pub minor: u32,
     pub max_readahead: u32,
     pub flags: u32,
+    pub flags2: u32,
+    pub unused: [u32; 11],
 }
 
 #[repr(C)]
@@ -776,16 +704,18 @@ pub struct fuse_init_out {
     pub minor: u32,
     pub max_readahead: u32,
     pub flags: u32,
-    #[cfg(not(feature = "abi-7-13"))]
-    pub unused: u32,
-    #[cfg(feature = "abi-7-13")]
     pub max_background: u16,
-    #[cfg(feature = "abi-7-13")]
     pub congestion_threshold: u16,
     pub max_write: u32,
+    pub time_gran: u32,
+    pub max_pages: u16,
+    pub map_alignment: u16,
+    pub flags2: u32,
+    pub reserved: [u32; 7],
 }
 
-#[cfg(feature = "abi-7-12")]
+// CUSE is not supported
+#[allow(dead_code)]
 #[repr(C)]
 #[derive(Debug)]
 pub struct cuse_init_in {
@@ -795,7 +725,8 @@ pub struct cuse_init_in {
     pub flags: u32,
 }
 
-#[cfg(feature = "abi-7-12")]
+// CUSE is not supported
+#[allow(dead_code)]
 #[repr(C)]
 #[derive(Debug)]
 pub struct cuse_init_out {
@@ -830,7 +761,6 @@ pub struct fuse_bmap_out {
     pub block: u64,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_ioctl_in {
@@ -842,7 +772,6 @@ pub struct fuse_ioctl_in {
     pub out_size: u32,
 }
 
-#[cfg(feature = "abi-7-16")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_ioctl_iovec {
@@ -850,7 +779,6 @@ pub struct fuse_ioctl_iovec {
     pub len: u64,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_ioctl_out {
@@ -860,20 +788,15 @@ pub struct fuse_ioctl_out {
     pub out_iovs: u32,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_poll_in {
     pub fh: u64,
     pub kh: u64,
     pub flags: u32,
-    #[cfg(not(feature = "abi-7-21"))]
-    pub padding: u32,
-    #[cfg(feature = "abi-7-21")]
     pub events: u32,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_poll_out {
@@ -881,22 +804,20 @@ pub struct fuse_poll_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_poll_wakeup_out {
     pub kh: u64,
 }
 
-#[cfg(feature = "abi-7-19")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_fallocate_in {
-    fh: u64,
-    offset: u64,
-    length: u64,
-    mode: u32,
-    padding: u32,
+    pub fh: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub mode: u32,
+    pub padding: u32,
 }
 
 #[repr(C)]
@@ -930,7 +851,13 @@ pub struct fuse_dirent {
     // followed by name of namelen bytes
 }
 
-#[cfg(feature = "abi-7-12")]
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_direntplus {
+    pub entry_out: fuse_entry_out,
+    pub dirent: fuse_dirent,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_inval_inode_out {
@@ -939,7 +866,6 @@ pub struct fuse_notify_inval_inode_out {
     pub len: i64,
 }
 
-#[cfg(feature = "abi-7-12")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_inval_entry_out {
@@ -948,17 +874,15 @@ pub struct fuse_notify_inval_entry_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-18")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_delete_out {
-    parent: u64,
-    child: u64,
-    namelen: u32,
-    padding: u32,
+    pub parent: u64,
+    pub child: u64,
+    pub namelen: u32,
+    pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-15")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_store_out {
@@ -968,7 +892,6 @@ pub struct fuse_notify_store_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-15")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_retrieve_out {
@@ -979,7 +902,6 @@ pub struct fuse_notify_retrieve_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-15")]
 #[repr(C)]
 #[derive(Debug)]
 pub struct fuse_notify_retrieve_in {
@@ -991,3 +913,59 @@ pub struct fuse_notify_retrieve_in {
     pub dummy3: u64,
     pub dummy4: u64,
 }
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_lseek_in {
+    pub fh: u64,
+    pub offset: u64,
+    pub whence: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_lseek_out {
+    pub offset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_copy_file_range_in {
+    pub fh_in: u64,
+    pub off_in: u64,
+    pub nodeid_out: u64,
+    pub fh_out: u64,
+    pub off_out: u64,
+    pub len: u64,
+    pub flags: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_setupmapping_in {
+    pub fh: u64,
+    pub foffset: u64,
+    pub len: u64,
+    pub flags: u64,
+    pub moffset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_removemapping_in {
+    pub count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_removemapping_one {
+    pub moffset: u64,
+    pub len: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct fuse_syncfs_in {
+    pub padding: u64,
+}
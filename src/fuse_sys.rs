@@ -16,14 +16,16 @@ use libc::{c_char, c_int};
 #[cfg(not(feature = "libfuse"))]
 use log::{debug, error};
 #[cfg(not(feature = "libfuse"))]
-use std::ffi::{CStr, CString, OsStr};
+use std::ffi::CString;
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
+use std::ffi::{CStr, OsStr};
 #[cfg(not(feature = "libfuse"))]
 use std::fs::{File, OpenOptions};
-#[cfg(not(feature = "libfuse"))]
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
 use std::io;
 #[cfg(not(feature = "libfuse"))]
 use std::io::{Error, ErrorKind, Read};
-#[cfg(not(feature = "libfuse"))]
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
 use std::os::unix::ffi::OsStrExt;
 #[cfg(not(feature = "libfuse"))]
 use std::os::unix::fs::PermissionsExt;
@@ -31,14 +33,16 @@ use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
 #[cfg(not(feature = "libfuse"))]
 use std::os::unix::net::UnixStream;
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
+use std::process::Command;
 #[cfg(not(feature = "libfuse"))]
-use std::process::{Command, Stdio};
+use std::process::Stdio;
 #[cfg(not(feature = "libfuse"))]
 use std::{mem, ptr};
 
-#[cfg(not(feature = "libfuse"))]
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
 const FUSERMOUNT_BIN: &str = "fusermount";
-#[cfg(not(feature = "libfuse"))]
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
 const FUSERMOUNT3_BIN: &str = "fusermount3";
 #[cfg(not(feature = "libfuse"))]
 const FUSERMOUNT_COMM_ENV: &str = "_FUSE_COMMFD";
@@ -100,7 +104,7 @@ pub fn fuse_mount_pure(mountpoint: &OsStr, options: &[MountOption]) -> Result<c_
 }
 
 #[cfg(not(feature = "libfuse"))]
-pub fn fuse_unmount_pure(mountpoint: &CStr, fd: c_int) {
+pub fn fuse_unmount_pure(mountpoint: &CStr, fd: c_int) -> io::Result<()> {
     if fd != -1 {
         let mut poll_result = libc::pollfd {
             fd,
@@ -115,7 +119,7 @@ pub fn fuse_unmount_pure(mountpoint: &CStr, fd: c_int) {
             // Unmounting it a second time could cause a race with a newly mounted filesystem
             // living at the same mountpoint
             if result > 0 && (poll_result.revents & libc::POLLERR) != 0 {
-                return;
+                return Ok(());
             }
         }
     }
@@ -124,33 +128,42 @@ pub fn fuse_unmount_pure(mountpoint: &CStr, fd: c_int) {
     unsafe {
         let result = libc::umount2(mountpoint.as_ptr(), libc::MNT_DETACH);
         if result == 0 {
-            return;
+            return Ok(());
         }
     }
     #[cfg(target_os = "macos")]
     unsafe {
         let result = libc::unmount(mountpoint.as_ptr(), libc::MNT_FORCE);
         if result == 0 {
-            return;
+            return Ok(());
         }
     }
 
-    let mut builder = Command::new(detect_fusermount_bin());
-    builder.stdout(Stdio::piped()).stderr(Stdio::piped());
-    builder
+    fuse_unmount_fusermount(mountpoint)
+}
+
+/// Unmount the given mountpoint with the setuid-root fusermount, which lets users unmount
+/// their own filesystems. Like `umount -l`, the filesystem is detached lazily.
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
+pub fn fuse_unmount_fusermount(mountpoint: &CStr) -> io::Result<()> {
+    let output = Command::new(detect_fusermount_bin())
         .arg("-u")
-        .arg("-q")
         .arg("-z")
         .arg("--")
-        .arg(OsStr::new(&mountpoint.to_string_lossy().into_owned()));
-
-    if let Ok(output) = builder.output() {
-        debug!("fusermount: {}", String::from_utf8_lossy(&output.stdout));
-        debug!("fusermount: {}", String::from_utf8_lossy(&output.stderr));
+        .arg(OsStr::from_bytes(mountpoint.to_bytes()))
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "fusermount failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
     }
 }
 
-#[cfg(not(feature = "libfuse"))]
+#[cfg(any(not(feature = "libfuse"), target_os = "linux"))]
 fn detect_fusermount_bin() -> String {
     for name in [
         FUSERMOUNT3_BIN.to_string(),
@@ -0,0 +1,218 @@
+//! Inode table with lookup counting
+//!
+//! The kernel counts how often it looked up an inode, i.e. how often an inode was returned
+//! in an entry reply (lookup, mknod, mkdir, symlink, link, create, readdirplus), and tells
+//! the filesystem with forget when it drops references. An inode must stay valid until its
+//! lookup count drops back to zero, and its number may only be reused with a different
+//! generation afterwards. `InodeTable` does this accounting for the state a filesystem keeps
+//! per inode.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::reply::{ReplyCreate, ReplyEntry};
+use crate::request::ForgetOne;
+use crate::{Errno, FileAttr, FUSE_ROOT_ID};
+
+/// An inode in the table
+#[derive(Debug)]
+struct Inode<T> {
+    value: T,
+    generation: u64,
+    lookups: u64,
+}
+
+/// Table of the inodes known to the kernel, with a value of type `T` for each. Inode numbers
+/// are allocated by the table. The root inode is always present and never freed, all other
+/// inodes are freed when the kernel forgets all lookups of them. The numbers of freed inodes
+/// are reused with an incremented generation.
+///
+/// The table isn't synchronized, filesystems usually keep it in a `Mutex`.
+#[derive(Debug)]
+pub struct InodeTable<T> {
+    inodes: HashMap<u64, Inode<T>>,
+    /// Freed inode numbers and the generation of their last use
+    free: Vec<(u64, u64)>,
+    next_ino: u64,
+}
+
+impl<T> InodeTable<T> {
+    /// Create an inode table with the given value for the root inode
+    pub fn new(root: T) -> InodeTable<T> {
+        let mut inodes = HashMap::new();
+        inodes.insert(
+            FUSE_ROOT_ID,
+            Inode {
+                value: root,
+                generation: 0,
+                lookups: 1,
+            },
+        );
+        InodeTable {
+            inodes,
+            free: Vec::new(),
+            next_ino: FUSE_ROOT_ID + 1,
+        }
+    }
+
+    /// Allocate an inode number for the given value. The inode starts with a lookup count of
+    /// zero, it must be passed to the kernel with `entry`, `created` or `lookup` (or removed)
+    /// before it's forgotten. Returns the inode number and its generation.
+    pub fn insert(&mut self, value: T) -> (u64, u64) {
+        let (ino, generation) = match self.free.pop() {
+            Some((ino, generation)) => (ino, generation.wrapping_add(1)),
+            None => {
+                let ino = self.next_ino;
+                self.next_ino = ino.checked_add(1).expect("Inode numbers exhausted");
+                (ino, 0)
+            }
+        };
+        let inode = Inode {
+            value,
+            generation,
+            lookups: 0,
+        };
+        self.inodes.insert(ino, inode);
+        (ino, generation)
+    }
+
+    /// Returns the value of the given inode
+    pub fn get(&self, ino: u64) -> Option<&T> {
+        self.inodes.get(&ino).map(|inode| &inode.value)
+    }
+
+    /// Returns the value of the given inode for modification
+    pub fn get_mut(&mut self, ino: u64) -> Option<&mut T> {
+        self.inodes.get_mut(&ino).map(|inode| &mut inode.value)
+    }
+
+    /// Returns the generation of the given inode
+    pub fn generation(&self, ino: u64) -> Option<u64> {
+        self.inodes.get(&ino).map(|inode| inode.generation)
+    }
+
+    /// Returns the lookup count of the given inode
+    pub fn lookups(&self, ino: u64) -> Option<u64> {
+        self.inodes.get(&ino).map(|inode| inode.lookups)
+    }
+
+    /// Returns the number of inodes in the table (including the root inode)
+    pub fn len(&self) -> usize {
+        self.inodes.len()
+    }
+
+    /// Returns true if the table contains no inodes, which is never the case since the root
+    /// inode is always present
+    pub fn is_empty(&self) -> bool {
+        self.inodes.is_empty()
+    }
+
+    /// Increment the lookup count of the given inode, for passing it to the kernel in an entry
+    /// reply. Returns its generation, or None if the inode isn't in the table.
+    pub fn lookup(&mut self, ino: u64) -> Option<u64> {
+        let inode = self.inodes.get_mut(&ino)?;
+        inode.lookups += 1;
+        Some(inode.generation)
+    }
+
+    /// Reply with the entry of the inode with the given attributes (`attr.ino`) and increment
+    /// its lookup count. Replies with ESTALE if the inode isn't in the table.
+    pub fn entry(&mut self, reply: ReplyEntry, ttl: &Duration, attr: &FileAttr) {
+        match self.lookup(attr.ino) {
+            Some(generation) => reply.entry(ttl, attr, generation),
+            None => reply.error(Errno::ESTALE),
+        }
+    }
+
+    /// Reply with the created and opened inode with the given attributes (`attr.ino`) and
+    /// increment its lookup count. Replies with ESTALE if the inode isn't in the table.
+    pub fn created(
+        &mut self,
+        reply: ReplyCreate,
+        ttl: &Duration,
+        attr: &FileAttr,
+        fh: u64,
+        flags: u32,
+    ) {
+        match self.lookup(attr.ino) {
+            Some(generation) => reply.created(ttl, attr, generation, fh, flags),
+            None => reply.error(Errno::ESTALE),
+        }
+    }
+
+    /// Decrement the lookup count of the given inode by nlookup, as requested by a forget.
+    /// If the count drops to zero, the inode is removed and its value is returned.
+    pub fn forget(&mut self, ino: u64, nlookup: u64) -> Option<T> {
+        if ino == FUSE_ROOT_ID {
+            return None;
+        }
+        let inode = self.inodes.get_mut(&ino)?;
+        inode.lookups = inode.lookups.saturating_sub(nlookup);
+        if inode.lookups > 0 {
+            return None;
+        }
+        self.remove(ino)
+    }
+
+    /// Decrement the lookup counts of the given inodes, as requested by a batch forget.
+    /// Returns the values of the removed inodes.
+    pub fn batch_forget(&mut self, nodes: &[ForgetOne]) -> Vec<T> {
+        nodes
+            .iter()
+            .filter_map(|node| self.forget(node.ino(), node.nlookup()))
+            .collect()
+    }
+
+    /// Remove an inode regardless of its lookup count, e.g. an inode that was inserted but
+    /// never passed to the kernel. The root inode can't be removed.
+    pub fn remove(&mut self, ino: u64) -> Option<T> {
+        if ino == FUSE_ROOT_ID {
+            return None;
+        }
+        let inode = self.inodes.remove(&ino)?;
+        self.free.push((ino, inode.generation));
+        Some(inode.value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InodeTable;
+    use crate::FUSE_ROOT_ID;
+
+    #[test]
+    fn lookup_count() {
+        let mut table = InodeTable::new("root");
+        let (ino, generation) = table.insert("a");
+        assert_ne!(ino, FUSE_ROOT_ID);
+        assert_eq!(table.lookup(ino), Some(generation));
+        assert_eq!(table.lookup(ino), Some(generation));
+        assert_eq!(table.lookups(ino), Some(2));
+        assert_eq!(table.forget(ino, 1), None);
+        assert_eq!(table.get(ino), Some(&"a"));
+        assert_eq!(table.forget(ino, 1), Some("a"));
+        assert_eq!(table.get(ino), None);
+        assert_eq!(table.forget(ino, 1), None);
+        // The root inode is never freed
+        assert_eq!(table.forget(FUSE_ROOT_ID, 1), None);
+        assert_eq!(table.remove(FUSE_ROOT_ID), None);
+        assert_eq!(table.get(FUSE_ROOT_ID), Some(&"root"));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn reuse() {
+        let mut table = InodeTable::new(());
+        let (a, a_generation) = table.insert(());
+        let (b, _) = table.insert(());
+        assert_ne!(a, b);
+        table.lookup(a);
+        table.forget(a, 1);
+        // The freed inode number is reused with a new generation
+        let (c, c_generation) = table.insert(());
+        assert_eq!(c, a);
+        assert_eq!(c_generation, a_generation + 1);
+        let (d, _) = table.insert(());
+        assert!(d != a && d != b);
+    }
+}
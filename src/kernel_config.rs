@@ -0,0 +1,280 @@
+//! Kernel configuration
+//!
+//! When a filesystem is initialized, the kernel driver reports the capabilities it supports.
+//! The filesystem can decide which of them to use and adjust some limits of the connection
+//! before the session replies to the kernel's init request.
+
+use std::time::Duration;
+
+use crate::fuse_abi::consts::*;
+use crate::fuse_abi::{fuse_init_out, FUSE_KERNEL_MINOR_VERSION, FUSE_KERNEL_VERSION};
+use crate::session::MAX_WRITE_SIZE;
+
+/// Returns the capabilities that are used by default if the kernel supports them
+fn default_capabilities() -> u32 {
+    // We generally support async reads
+    let mut flags = FUSE_ASYNC_READ;
+    // Writes may be larger than a page and ioctls on directories are supported
+    #[cfg(not(target_os = "macos"))]
+    {
+        flags |= FUSE_BIG_WRITES | FUSE_HAS_IOCTL_DIR;
+    }
+    // Writes may be larger than 32 pages
+    flags |= FUSE_MAX_PAGES;
+    // On macOS, we additionally support case insensitiveness, volume renames and xtimes
+    #[cfg(target_os = "macos")]
+    {
+        flags |= FUSE_CASE_INSENSITIVE | FUSE_VOL_RENAME | FUSE_XTIMES;
+    }
+    // Readdirplus isn't enabled by default, since filesystems need to implement it. They can
+    // request FUSE_DO_READDIRPLUS in their init method.
+    // TODO: Add FUSE_EXPORT_SUPPORT
+    flags
+}
+
+/// Capabilities that are implemented by this library rather than the kernel. They are always
+/// offered to the filesystem, but never sent to the kernel.
+#[cfg(target_os = "linux")]
+const USERSPACE_CAPABILITIES: u32 = FUSE_SPLICE_WRITE | FUSE_SPLICE_MOVE | FUSE_SPLICE_READ;
+#[cfg(not(target_os = "linux"))]
+const USERSPACE_CAPABILITIES: u32 = 0;
+
+/// Configuration of the connection to the kernel driver, negotiated during initialization.
+/// It is passed to the filesystem's init method, which can request or decline capabilities
+/// offered by the kernel (see FUSE_* init flags in `consts`) and adjust the limits the kernel
+/// should use. Setters fail if the kernel didn't offer a capability or if a value is out of
+/// range, the error contains the unsupported capabilities or the nearest valid value then.
+///
+/// E.g. writeback caching, which lets the kernel buffer small writes in the page cache and
+/// send them in larger chunks, is enabled with `config.add_capabilities(FUSE_WRITEBACK_CACHE)`
+/// (see `Filesystem::write` for the semantics of writes and attributes in this mode).
+#[derive(Debug)]
+pub struct KernelConfig {
+    /// Capabilities offered by the kernel
+    capabilities: u32,
+    /// Capabilities to use
+    requested: u32,
+    max_readahead: u32,
+    /// Maximum readahead size offered by the kernel
+    max_max_readahead: u32,
+    max_write: u32,
+    max_background: u16,
+    congestion_threshold: u16,
+    time_gran: Duration,
+}
+
+impl KernelConfig {
+    pub(crate) fn new(capabilities: u32, max_readahead: u32) -> KernelConfig {
+        let capabilities = capabilities | USERSPACE_CAPABILITIES;
+        KernelConfig {
+            capabilities,
+            requested: default_capabilities() & capabilities,
+            max_readahead,
+            max_max_readahead: max_readahead,
+            max_write: MAX_WRITE_SIZE as u32,
+            max_background: 16,
+            congestion_threshold: 12,
+            time_gran: Duration::new(0, 1),
+        }
+    }
+
+    /// Returns the capabilities offered by the kernel
+    pub fn capabilities(&self) -> u32 {
+        self.capabilities
+    }
+
+    /// Returns the capabilities that will be used
+    pub fn requested_capabilities(&self) -> u32 {
+        self.requested
+    }
+
+    /// Use the given capabilities. Fails with the capabilities the kernel doesn't offer, none of
+    /// the given capabilities are used then.
+    pub fn add_capabilities(&mut self, capabilities: u32) -> Result<(), u32> {
+        let unsupported = capabilities & !self.capabilities;
+        if unsupported != 0 {
+            return Err(unsupported);
+        }
+        self.requested |= capabilities;
+        Ok(())
+    }
+
+    /// Don't use the given capabilities
+    pub fn remove_capabilities(&mut self, capabilities: u32) {
+        self.requested &= !capabilities;
+    }
+
+    /// Set the maximum readahead size in bytes. It can't exceed the size offered by the kernel.
+    /// Returns the previous value on success, or the nearest valid value on failure.
+    pub fn set_max_readahead(&mut self, value: u32) -> Result<u32, u32> {
+        if value == 0 {
+            return Err(1);
+        }
+        if value > self.max_max_readahead {
+            return Err(self.max_max_readahead);
+        }
+        Ok(std::mem::replace(&mut self.max_readahead, value))
+    }
+
+    /// Set the maximum size of write requests in bytes. It must be at least 4096 and can't
+    /// exceed `MAX_WRITE_SIZE`, the size that fits into the session's request buffer.
+    /// Returns the previous value on success, or the nearest valid value on failure.
+    pub fn set_max_write(&mut self, value: u32) -> Result<u32, u32> {
+        if value < 4096 {
+            return Err(4096);
+        }
+        if value > MAX_WRITE_SIZE as u32 {
+            return Err(MAX_WRITE_SIZE as u32);
+        }
+        Ok(std::mem::replace(&mut self.max_write, value))
+    }
+
+    /// Set the maximum number of pending background requests (e.g. readahead or writeback).
+    /// Returns the previous value on success, or the nearest valid value on failure.
+    pub fn set_max_background(&mut self, value: u16) -> Result<u16, u16> {
+        if value == 0 {
+            return Err(1);
+        }
+        Ok(std::mem::replace(&mut self.max_background, value))
+    }
+
+    /// Set the number of pending background requests at which the kernel considers the
+    /// filesystem congested. Returns the previous value on success, or the nearest valid value
+    /// on failure.
+    pub fn set_congestion_threshold(&mut self, value: u16) -> Result<u16, u16> {
+        if value == 0 {
+            return Err(1);
+        }
+        Ok(std::mem::replace(&mut self.congestion_threshold, value))
+    }
+
+    /// Set the granularity of timestamps the filesystem supports. The kernel truncates
+    /// timestamps to it (e.g. in writeback cache mode). It must be a power of 10 nanoseconds
+    /// between 1 nanosecond and 1 second. Returns the previous value on success, or the nearest
+    /// valid value on failure.
+    pub fn set_time_granularity(&mut self, value: Duration) -> Result<Duration, Duration> {
+        if value > Duration::from_secs(1) {
+            return Err(Duration::from_secs(1));
+        }
+        let nanos = value.as_nanos() as u32;
+        let mut power_of_ten = 1;
+        while power_of_ten < nanos {
+            power_of_ten *= 10;
+        }
+        if nanos != power_of_ten {
+            return Err(Duration::new(0, power_of_ten));
+        }
+        Ok(std::mem::replace(&mut self.time_gran, value))
+    }
+
+    /// Returns the reply to the kernel's init request
+    pub(crate) fn init_out(&self) -> fuse_init_out {
+        fuse_init_out {
+            major: FUSE_KERNEL_VERSION,
+            minor: FUSE_KERNEL_MINOR_VERSION,
+            max_readahead: self.max_readahead,
+            flags: self.requested & !USERSPACE_CAPABILITIES,
+            max_background: self.max_background,
+            congestion_threshold: self.congestion_threshold,
+            max_write: self.max_write,
+            time_gran: self.time_gran.as_nanos() as u32,
+            max_pages: self.max_pages(),
+            map_alignment: 0,
+            flags2: 0,
+            reserved: [0; 7],
+        }
+    }
+
+    /// Returns the maximum number of pages per request, enough for requests of max_write size
+    fn max_pages(&self) -> u16 {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u32;
+        ((self.max_write - 1) / page_size + 1) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities() {
+        let mut config = KernelConfig::new(FUSE_ASYNC_READ | FUSE_POSIX_LOCKS, 4096);
+        assert_eq!(config.requested_capabilities(), FUSE_ASYNC_READ);
+        assert_eq!(config.add_capabilities(FUSE_POSIX_LOCKS), Ok(()));
+        assert_eq!(
+            config.requested_capabilities(),
+            FUSE_ASYNC_READ | FUSE_POSIX_LOCKS
+        );
+        config.remove_capabilities(FUSE_ASYNC_READ);
+        assert_eq!(config.requested_capabilities(), FUSE_POSIX_LOCKS);
+        assert_eq!(config.init_out().flags, FUSE_POSIX_LOCKS);
+    }
+
+    #[test]
+    fn unsupported_capabilities() {
+        let mut config = KernelConfig::new(FUSE_ASYNC_READ, 4096);
+        assert_eq!(
+            config.add_capabilities(FUSE_ASYNC_READ | FUSE_POSIX_LOCKS),
+            Err(FUSE_POSIX_LOCKS)
+        );
+        assert_eq!(config.requested_capabilities(), FUSE_ASYNC_READ);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn userspace_capabilities() {
+        let mut config = KernelConfig::new(FUSE_ASYNC_READ, 4096);
+        assert_eq!(config.requested_capabilities(), FUSE_ASYNC_READ);
+        assert_eq!(config.add_capabilities(FUSE_SPLICE_READ), Ok(()));
+        assert_eq!(
+            config.requested_capabilities(),
+            FUSE_ASYNC_READ | FUSE_SPLICE_READ
+        );
+        assert_eq!(config.init_out().flags, FUSE_ASYNC_READ);
+    }
+
+    #[test]
+    fn writeback_cache() {
+        let mut config = KernelConfig::new(FUSE_ASYNC_READ | FUSE_WRITEBACK_CACHE, 4096);
+        assert_eq!(config.requested_capabilities(), FUSE_ASYNC_READ);
+        assert_eq!(config.add_capabilities(FUSE_WRITEBACK_CACHE), Ok(()));
+        assert_eq!(
+            config.init_out().flags,
+            FUSE_ASYNC_READ | FUSE_WRITEBACK_CACHE
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let mut config = KernelConfig::new(0, 0x20000);
+        assert_eq!(config.set_max_readahead(0x40000), Err(0x20000));
+        assert_eq!(config.set_max_readahead(0x10000), Ok(0x20000));
+        assert_eq!(config.set_max_write(1024), Err(4096));
+        assert_eq!(
+            config.set_max_write(MAX_WRITE_SIZE as u32 + 1),
+            Err(MAX_WRITE_SIZE as u32)
+        );
+        assert_eq!(config.set_max_write(0x20000), Ok(MAX_WRITE_SIZE as u32));
+        let init = config.init_out();
+        assert_eq!(init.max_readahead, 0x10000);
+        assert_eq!(init.max_write, 0x20000);
+    }
+
+    #[test]
+    fn time_granularity() {
+        let mut config = KernelConfig::new(0, 4096);
+        assert_eq!(
+            config.set_time_granularity(Duration::from_secs(2)),
+            Err(Duration::from_secs(1))
+        );
+        assert_eq!(
+            config.set_time_granularity(Duration::from_micros(5)),
+            Err(Duration::from_micros(10))
+        );
+        assert_eq!(
+            config.set_time_granularity(Duration::from_millis(1)),
+            Ok(Duration::new(0, 1))
+        );
+        assert_eq!(config.init_out().time_gran, 1_000_000);
+    }
+}
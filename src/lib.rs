@@ -6,7 +6,6 @@
 
 #![warn(missing_docs, missing_debug_implementations, rust_2018_idioms)]
 
-use libc::{c_int, ENOSYS};
 use std::convert::AsRef;
 use std::ffi::OsStr;
 use std::io;
@@ -17,23 +16,51 @@ pub use crate::fuse_abi::consts;
 pub use crate::fuse_abi::FUSE_ROOT_ID;
 #[cfg(feature = "libfuse")]
 use crate::mount_options::option_to_string;
+pub use errno::Errno;
+pub use inode_table::InodeTable;
+pub use kernel_config::KernelConfig;
 pub use mount_options::MountOption;
+pub use notify::{Notifier, PollHandle};
+pub use reply::ReplyDirectoryPlus;
+pub use reply::ReplyLseek;
 #[cfg(target_os = "macos")]
 pub use reply::ReplyXTimes;
 pub use reply::ReplyXattr;
 pub use reply::{Reply, ReplyAttr, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen};
 pub use reply::{ReplyBmap, ReplyCreate, ReplyDirectory, ReplyLock, ReplyStatfs, ReplyWrite};
-pub use request::Request;
-pub use session::{BackgroundSession, Session};
-
+pub use reply::{ReplyIoctl, ReplyPoll};
+pub use request::ForgetOne;
+pub use request::RemoveMappingOne;
+pub use request::{InterruptToken, Request, WriteFlags};
+pub use session::SessionUnmounter;
+pub use session::{BackgroundSession, Session, SessionError, SessionExit, SessionHandle};
+pub use splice::SplicedData;
+pub use stats::{LatencyHistogram, OperationStats, SessionStats};
+
+pub mod async_fs;
+pub mod capture;
 mod channel;
+mod errno;
+mod fs_types;
 mod fuse_abi;
 mod fuse_sys;
+mod inode_table;
+mod kernel_config;
 mod ll;
 mod mount_options;
+mod notify;
+#[cfg(target_os = "linux")]
+pub mod passthrough;
+pub mod path_fs;
 mod reply;
 mod request;
 mod session;
+mod signal;
+mod splice;
+mod stats;
+#[cfg(test)]
+mod test_util;
+pub mod testing;
 
 /// File types
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -97,21 +124,26 @@ pub struct FileAttr {
 /// These methods correspond to fuse_lowlevel_ops in libfuse. Reasonable default
 /// implementations are provided here to get a mountable filesystem that does
 /// nothing.
+///
+/// All methods take `&self`, so that a filesystem can serve requests from multiple
+/// threads at once (see `Session::run_multithreaded`). Filesystems that keep mutable
+/// state need to use interior mutability (e.g. a `Mutex`) for it.
 #[allow(clippy::too_many_arguments)]
 pub trait Filesystem {
     /// Initialize filesystem.
-    /// Called before any other filesystem method.
-    fn init(&mut self, _req: &Request<'_>) -> Result<(), c_int> {
+    /// Called before any other filesystem method. The kernel config can be used to choose
+    /// the capabilities and limits of the connection to the kernel.
+    fn init(&self, _req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), Errno> {
         Ok(())
     }
 
     /// Clean up filesystem.
     /// Called on filesystem exit.
-    fn destroy(&mut self, _req: &Request<'_>) {}
+    fn destroy(&self, _req: &Request<'_>) {}
 
     /// Look up a directory entry by name and get its attributes.
-    fn lookup(&mut self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEntry) {
-        reply.error(ENOSYS);
+    fn lookup(&self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEntry) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Forget about an inode.
@@ -121,16 +153,25 @@ pub trait Filesystem {
     /// each forget. The filesystem may ignore forget calls, if the inodes don't need to
     /// have a limited lifetime. On unmount it is not guaranteed, that all referenced
     /// inodes will receive a forget message.
-    fn forget(&mut self, _req: &Request<'_>, _ino: u64, _nlookup: u64) {}
+    fn forget(&self, _req: &Request<'_>, _ino: u64, _nlookup: u64) {}
+
+    /// Forget about multiple inodes at once.
+    /// The kernel sends a batch forget instead of single forgets if there are many inodes
+    /// to forget. The default implementation calls forget for each of the given inodes.
+    fn batch_forget(&self, req: &Request<'_>, nodes: &[ForgetOne]) {
+        for node in nodes {
+            self.forget(req, node.ino(), node.nlookup());
+        }
+    }
 
     /// Get file attributes.
-    fn getattr(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyAttr) {
-        reply.error(ENOSYS);
+    fn getattr(&self, _req: &Request<'_>, _ino: u64, reply: ReplyAttr) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Set file attributes.
     fn setattr(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _mode: Option<u32>,
@@ -148,18 +189,18 @@ pub trait Filesystem {
         _flags: Option<u32>,
         reply: ReplyAttr,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Read symbolic link.
-    fn readlink(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyData) {
-        reply.error(ENOSYS);
+    fn readlink(&self, _req: &Request<'_>, _ino: u64, reply: ReplyData) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Create file node.
     /// Create a regular file, character device, block device, fifo or socket node.
     fn mknod(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _parent: u64,
         _name: &OsStr,
@@ -167,66 +208,70 @@ pub trait Filesystem {
         _rdev: u32,
         reply: ReplyEntry,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Create a directory.
     fn mkdir(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _parent: u64,
         _name: &OsStr,
         _mode: u32,
         reply: ReplyEntry,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Remove a file.
-    fn unlink(&mut self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
-        reply.error(ENOSYS);
+    fn unlink(&self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Remove a directory.
-    fn rmdir(&mut self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
-        reply.error(ENOSYS);
+    fn rmdir(&self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Create a symbolic link.
     fn symlink(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _parent: u64,
         _name: &OsStr,
         _link: &Path,
         reply: ReplyEntry,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Rename a file.
+    /// flags may contain RENAME_NOREPLACE or RENAME_EXCHANGE (see renameat2(2)), they're only
+    /// sent with ABI 7.23 and later and zero otherwise. Unsupported flags should be answered
+    /// with EINVAL.
     fn rename(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _parent: u64,
         _name: &OsStr,
         _newparent: u64,
         _newname: &OsStr,
+        _flags: u32,
         reply: ReplyEmpty,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Create a hard link.
     fn link(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _newparent: u64,
         _newname: &OsStr,
         reply: ReplyEntry,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Open a file.
@@ -237,7 +282,7 @@ pub trait Filesystem {
     /// anything in fh. There are also some flags (direct_io, keep_cache) which the
     /// filesystem may set, to change the way the file is opened. See fuse_file_info
     /// structure in <fuse_common.h> for more details.
-    fn open(&mut self, _req: &Request<'_>, _ino: u64, _flags: u32, reply: ReplyOpen) {
+    fn open(&self, _req: &Request<'_>, _ino: u64, _flags: u32, reply: ReplyOpen) {
         reply.opened(0, 0);
     }
 
@@ -249,7 +294,7 @@ pub trait Filesystem {
     /// operation. fh will contain the value set by the open method, or will be undefined
     /// if the open method didn't set any value.
     fn read(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
@@ -257,7 +302,7 @@ pub trait Filesystem {
         _size: u32,
         reply: ReplyData,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Write data.
@@ -265,18 +310,62 @@ pub trait Filesystem {
     /// exception to this is when the file has been opened in 'direct_io' mode, in
     /// which case the return value of the write system call will reflect the return
     /// value of this operation. fh will contain the value set by the open method, or
-    /// will be undefined if the open method didn't set any value.
+    /// will be undefined if the open method didn't set any value. flags are the flags the
+    /// file was opened with and lock_owner is only set if the kernel knows it (ABI >= 7.9).
+    ///
+    /// In writeback cache mode (FUSE_WRITEBACK_CACHE, see `KernelConfig`), the kernel
+    /// buffers writes in the page cache and sends them later, possibly merged into larger
+    /// writes. Such writes are marked with `WriteFlags::is_cache()`: fh is guessed by the
+    /// kernel then (it's some handle of the inode that was opened for writing) and the
+    /// request's uid, gid and pid don't belong to the writing process, so permission checks
+    /// must be done in open. The kernel also maintains the file size and modification time
+    /// itself in this mode. It sends them with setattr and ignores the size and mtime
+    /// returned by getattr and lookup while it has dirty pages of the file.
     fn write(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
         _offset: i64,
         _data: &[u8],
+        _write_flags: WriteFlags,
         _flags: u32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Write data received in splice read mode (see `KernelConfig::add_capabilities` and
+    /// FUSE_SPLICE_READ). The data is still in a pipe and can be moved to a file without
+    /// copying it through userspace with `SplicedData::splice_to`. The default implementation
+    /// reads the data into memory and calls `write`.
+    fn write_spliced(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut data: SplicedData<'_>,
+        write_flags: WriteFlags,
+        flags: u32,
+        lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
-        reply.error(ENOSYS);
+        match data.read_to_vec() {
+            Ok(data) => self.write(
+                req,
+                ino,
+                fh,
+                offset,
+                &data,
+                write_flags,
+                flags,
+                lock_owner,
+                reply,
+            ),
+            Err(err) => reply.error(err.into()),
+        }
     }
 
     /// Flush method.
@@ -289,15 +378,8 @@ pub trait Filesystem {
     /// is not forced to flush pending writes. One reason to flush data, is if the
     /// filesystem wants to return write errors. If the filesystem supports file locking
     /// operations (setlk, getlk) it should remove all locks belonging to 'lock_owner'.
-    fn flush(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _fh: u64,
-        _lock_owner: u64,
-        reply: ReplyEmpty,
-    ) {
-        reply.error(ENOSYS);
+    fn flush(&self, _req: &Request<'_>, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Release an open file.
@@ -309,7 +391,7 @@ pub trait Filesystem {
     /// if the open method didn't set any value. flags will contain the same flags as for
     /// open.
     fn release(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
@@ -324,15 +406,8 @@ pub trait Filesystem {
     /// Synchronize file contents.
     /// If the datasync parameter is non-zero, then only the user data should be flushed,
     /// not the meta data.
-    fn fsync(
-        &mut self,
-        _req: &Request<'_>,
-        _ino: u64,
-        _fh: u64,
-        _datasync: bool,
-        reply: ReplyEmpty,
-    ) {
-        reply.error(ENOSYS);
+    fn fsync(&self, _req: &Request<'_>, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Open a directory.
@@ -342,7 +417,7 @@ pub trait Filesystem {
     /// anything in fh, though that makes it impossible to implement standard conforming
     /// directory stream operations in case the contents of the directory can change
     /// between opendir and releasedir.
-    fn opendir(&mut self, _req: &Request<'_>, _ino: u64, _flags: u32, reply: ReplyOpen) {
+    fn opendir(&self, _req: &Request<'_>, _ino: u64, _flags: u32, reply: ReplyOpen) {
         reply.opened(0, 0);
     }
 
@@ -352,28 +427,38 @@ pub trait Filesystem {
     /// value set by the opendir method, or will be undefined if the opendir method
     /// didn't set any value.
     fn readdir(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
         _offset: i64,
         reply: ReplyDirectory,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
-    /// Release an open directory.
-    /// For every opendir call there will be exactly one releasedir call. fh will
-    /// contain the value set by the opendir method, or will be undefined if the
-    /// opendir method didn't set any value.
-    fn releasedir(
-        &mut self,
+    /// Read directory with attributes of its entries.
+    /// Like readdir, but every entry is added with its attributes, like with lookup, so that
+    /// the kernel doesn't need to look up each entry separately. The lookup count of every
+    /// added entry, except "." and "..", is incremented. The kernel uses readdirplus instead
+    /// of readdir if the filesystem requests FUSE_DO_READDIRPLUS (and optionally
+    /// FUSE_READDIRPLUS_AUTO) in its init method (ABI 7.21 and later).
+    fn readdirplus(
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
-        _flags: u32,
-        reply: ReplyEmpty,
+        _offset: i64,
+        reply: ReplyDirectoryPlus,
     ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Release an open directory.
+    /// For every opendir call there will be exactly one releasedir call. fh will
+    /// contain the value set by the opendir method, or will be undefined if the
+    /// opendir method didn't set any value.
+    fn releasedir(&self, _req: &Request<'_>, _ino: u64, _fh: u64, _flags: u32, reply: ReplyEmpty) {
         reply.ok();
     }
 
@@ -382,24 +467,24 @@ pub trait Filesystem {
     /// be flushed, not the meta data. fh will contain the value set by the opendir
     /// method, or will be undefined if the opendir method didn't set any value.
     fn fsyncdir(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
         _datasync: bool,
         reply: ReplyEmpty,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Get file system statistics.
-    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+    fn statfs(&self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
         reply.statfs(0, 0, 0, 0, 0, 512, 255, 0);
     }
 
     /// Set an extended attribute.
     fn setxattr(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _name: &OsStr,
@@ -408,7 +493,7 @@ pub trait Filesystem {
         _position: u32,
         reply: ReplyEmpty,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Get an extended attribute.
@@ -416,35 +501,35 @@ pub trait Filesystem {
     /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
     /// `reply.error(ERANGE)` if it doesn't.
     fn getxattr(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _name: &OsStr,
         _size: u32,
         reply: ReplyXattr,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// List extended attribute names.
     /// If `size` is 0, the size of the value should be sent with `reply.size()`.
     /// If `size` is not 0, and the value fits, send it with `reply.data()`, or
     /// `reply.error(ERANGE)` if it doesn't.
-    fn listxattr(&mut self, _req: &Request<'_>, _ino: u64, _size: u32, reply: ReplyXattr) {
-        reply.error(ENOSYS);
+    fn listxattr(&self, _req: &Request<'_>, _ino: u64, _size: u32, reply: ReplyXattr) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Remove an extended attribute.
-    fn removexattr(&mut self, _req: &Request<'_>, _ino: u64, _name: &OsStr, reply: ReplyEmpty) {
-        reply.error(ENOSYS);
+    fn removexattr(&self, _req: &Request<'_>, _ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Check file access permissions.
     /// This will be called for the access() system call. If the 'default_permissions'
     /// mount option is given, this method is not called. This method is not called
     /// under Linux kernel versions 2.4.x
-    fn access(&mut self, _req: &Request<'_>, _ino: u64, _mask: u32, reply: ReplyEmpty) {
-        reply.error(ENOSYS);
+    fn access(&self, _req: &Request<'_>, _ino: u64, _mask: u32, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// Create and open a file.
@@ -458,7 +543,7 @@ pub trait Filesystem {
     /// implemented or under Linux kernel versions earlier than 2.6.15, the mknod()
     /// and open() methods will be called instead.
     fn create(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _parent: u64,
         _name: &OsStr,
@@ -466,12 +551,12 @@ pub trait Filesystem {
         _flags: u32,
         reply: ReplyCreate,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Test for a POSIX file lock.
     fn getlk(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
@@ -482,7 +567,7 @@ pub trait Filesystem {
         _pid: u32,
         reply: ReplyLock,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Acquire, modify or release a POSIX file lock.
@@ -493,7 +578,7 @@ pub trait Filesystem {
     /// implemented, the kernel will still allow file locking to work locally.
     /// Hence these are only interesting for network filesystems and similar.
     fn setlk(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _ino: u64,
         _fh: u64,
@@ -505,34 +590,169 @@ pub trait Filesystem {
         _sleep: bool,
         reply: ReplyEmpty,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// Map block index within file to block index within device.
     /// Note: This makes sense only for block device backed filesystems mounted
     /// with the 'blkdev' option
-    fn bmap(
-        &mut self,
+    fn bmap(&self, _req: &Request<'_>, _ino: u64, _blocksize: u32, _idx: u64, reply: ReplyBmap) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Control device.
+    /// The cmd and flags (FUSE_IOCTL_* in `consts`) are those of the ioctl call and arg is its
+    /// argument, i.e. an address in the memory of the calling process. in_data contains the
+    /// input data of the ioctl and out_size is the size of the expected output data. For
+    /// restricted ioctls, the kernel derives both sizes from cmd. Unrestricted ioctls (CUSE
+    /// only) may be retried with the buffers the ioctl actually uses, see `ReplyIoctl::retry`.
+    /// Ioctls on directories are flagged with FUSE_IOCTL_DIR (ABI 7.18 and later), fh is the
+    /// value set by the opendir method then.
+    fn ioctl(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _cmd: u32,
+        _arg: u64,
+        _in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Poll for IO readiness events.
+    /// events is the requested poll events (ABI 7.21 and later, zero otherwise). If flags
+    /// contain FUSE_POLL_SCHEDULE_NOTIFY, a poll handle ph is given and the filesystem should
+    /// keep it and call its `notify` method once the file becomes ready (unless it's ready
+    /// already). A newer poll handle for the same file supersedes older ones.
+    fn poll(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _ph: Option<PollHandle>,
+        _events: u32,
+        _flags: u32,
+        reply: ReplyPoll,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Preallocate or deallocate space to a file.
+    /// The range starting at offset with the given length is allocated (or deallocated)
+    /// according to mode, which is a combination of the FALLOC_FL_* flags in `consts`
+    /// (see fallocate(2)), e.g. FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE or
+    /// FALLOC_FL_ZERO_RANGE. Unsupported modes should be answered with EOPNOTSUPP.
+    fn fallocate(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _length: i64,
+        _mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Reposition the offset of an open file.
+    /// whence is one of SEEK_SET, SEEK_CUR, SEEK_END, SEEK_DATA or SEEK_HOLE (see lseek(2)).
+    /// If this method isn't implemented, the kernel handles seeking itself, except for
+    /// SEEK_DATA and SEEK_HOLE.
+    fn lseek(
+        &self,
         _req: &Request<'_>,
         _ino: u64,
-        _blocksize: u32,
-        _idx: u64,
-        reply: ReplyBmap,
+        _fh: u64,
+        _offset: i64,
+        _whence: i32,
+        reply: ReplyLseek,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Copy a range of data from one file to another.
+    /// Reply with the number of bytes copied. If this method isn't implemented, the kernel
+    /// falls back to reading and writing the data.
+    fn copy_file_range(
+        &self,
+        _req: &Request<'_>,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        _ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Map a range of a file into the DAX window (virtiofs only).
+    /// len bytes starting at foffset in the file are mapped at moffset in the DAX window.
+    /// flags contains FUSE_SETUPMAPPING_FLAG_READ and/or FUSE_SETUPMAPPING_FLAG_WRITE.
+    fn setupmapping(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _foffset: u64,
+        _len: u64,
+        _flags: u64,
+        _moffset: u64,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Unmap ranges of the DAX window (virtiofs only).
+    fn removemapping(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _mappings: &[RemoveMappingOne],
+        reply: ReplyEmpty,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Synchronize the whole filesystem.
+    /// Called on syncfs(2) for the filesystem containing ino (which is usually the root).
+    fn syncfs(&self, _req: &Request<'_>, _ino: u64, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
+    }
+
+    /// Create and open an unnamed temporary file in the directory parent.
+    /// Like create, but the file doesn't get a name (see O_TMPFILE in open(2)). It may be
+    /// linked into the filesystem later with link.
+    fn tmpfile(
+        &self,
+        _req: &Request<'_>,
+        _parent: u64,
+        _mode: u32,
+        _flags: u32,
+        reply: ReplyCreate,
+    ) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// macOS only: Rename the volume. Set fuse_init_out.flags during init to
     /// FUSE_VOL_RENAME to enable
     #[cfg(target_os = "macos")]
-    fn setvolname(&mut self, _req: &Request<'_>, _name: &OsStr, reply: ReplyEmpty) {
-        reply.error(ENOSYS);
+    fn setvolname(&self, _req: &Request<'_>, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(Errno::ENOSYS);
     }
 
     /// macOS only (undocumented)
     #[cfg(target_os = "macos")]
     fn exchange(
-        &mut self,
+        &self,
         _req: &Request<'_>,
         _parent: u64,
         _name: &OsStr,
@@ -541,14 +761,14 @@ pub trait Filesystem {
         _options: u64,
         reply: ReplyEmpty,
     ) {
-        reply.error(ENOSYS);
+        reply.error(Errno::ENOSYS);
     }
 
     /// macOS only: Query extended times (bkuptime and crtime). Set fuse_init_out.flags
     /// during init to FUSE_XTIMES to enable
     #[cfg(target_os = "macos")]
-    fn getxtimes(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyXTimes) {
-        reply.error(ENOSYS);
+    fn getxtimes(&self, _req: &Request<'_>, _ino: u64, reply: ReplyXTimes) {
+        reply.error(Errno::ENOSYS);
     }
 }
 
@@ -563,7 +783,9 @@ pub fn mount<FS: Filesystem, P: AsRef<Path>>(
     mountpoint: P,
     options: &[&OsStr],
 ) -> io::Result<()> {
-    Session::new(filesystem, mountpoint.as_ref(), options).and_then(|mut se| se.run())
+    Session::new(filesystem, mountpoint.as_ref(), options)
+        .and_then(|mut se| se.run())
+        .map(|_| ())
 }
 
 /// Mount the given filesystem to the given mountpoint. This function will
@@ -576,7 +798,9 @@ pub fn mount2<FS: Filesystem, P: AsRef<Path>>(
     mountpoint: P,
     options: &[MountOption],
 ) -> io::Result<()> {
-    Session::new2(filesystem, mountpoint.as_ref(), options).and_then(|mut se| se.run())
+    Session::new2(filesystem, mountpoint.as_ref(), options)
+        .and_then(|mut se| se.run())
+        .map(|_| ())
 }
 
 /// Mount the given filesystem to the given mountpoint. This function will
@@ -592,7 +816,9 @@ pub fn mount2<FS: Filesystem, P: AsRef<Path>>(
     let options: Vec<String> = options.iter().map(|x| option_to_string(x)).collect();
     let option_str = options.join(",");
     let args = vec![OsStr::new("-o"), OsStr::new(&option_str)];
-    Session::new(filesystem, mountpoint.as_ref(), &args).and_then(|mut se| se.run())
+    Session::new(filesystem, mountpoint.as_ref(), &args)
+        .and_then(|mut se| se.run())
+        .map(|_| ())
 }
 
 /// Mount the given filesystem to the given mountpoint. This function spawns
@@ -613,3 +839,34 @@ pub unsafe fn spawn_mount<'a, FS: Filesystem + Send + 'a, P: AsRef<Path>>(
 ) -> io::Result<BackgroundSession<'a>> {
     Session::new(filesystem, mountpoint.as_ref(), options).and_then(|se| se.spawn())
 }
+
+/// Mount the given filesystem to the given mountpoint and run its session in a background
+/// thread (see `Session::spawn2`). This function returns immediately. If the returned handle
+/// is dropped, the filesystem is unmounted. `SessionHandle::join` returns the filesystem
+/// once the session ended.
+#[cfg(not(feature = "libfuse"))]
+pub fn spawn_mount2<FS: Filesystem + Send + 'static, P: AsRef<Path>>(
+    filesystem: FS,
+    mountpoint: P,
+    options: &[MountOption],
+) -> io::Result<SessionHandle<FS>> {
+    Session::new2(filesystem, mountpoint.as_ref(), options)
+        .and_then(|se| se.spawn2().map_err(io::Error::from))
+}
+
+/// Mount the given filesystem to the given mountpoint and run its session in a background
+/// thread (see `Session::spawn2`). This function returns immediately. If the returned handle
+/// is dropped, the filesystem is unmounted. `SessionHandle::join` returns the filesystem
+/// once the session ended.
+#[cfg(feature = "libfuse")]
+pub fn spawn_mount2<FS: Filesystem + Send + 'static, P: AsRef<Path>>(
+    filesystem: FS,
+    mountpoint: P,
+    options: &[MountOption],
+) -> io::Result<SessionHandle<FS>> {
+    let options: Vec<String> = options.iter().map(|x| option_to_string(x)).collect();
+    let option_str = options.join(",");
+    let args = vec![OsStr::new("-o"), OsStr::new(&option_str)];
+    Session::new(filesystem, mountpoint.as_ref(), &args)
+        .and_then(|se| se.spawn2().map_err(io::Error::from))
+}
@@ -4,8 +4,8 @@
 //! structures (request arguments).
 
 use std::ffi::OsStr;
-use std::mem;
 use std::os::unix::ffi::OsStrExt;
+use std::{mem, ptr};
 
 /// An iterator that can be used to fetch typed arguments from a byte slice.
 pub struct ArgumentIterator<'a> {
@@ -48,6 +48,33 @@ impl<'a> ArgumentIterator<'a> {
         (bytes.as_ptr() as *const T).as_ref()
     }
 
+    /// Fetch a copy of a typed argument that is sent with the given (smaller) size by kernel
+    /// drivers of older ABI versions. The fields missing at the end are zeroed. Returns `None`
+    /// if there's not enough data left. This function is unsafe because there is no guarantee
+    /// that the data actually contains the type T and that T may be zeroed.
+    pub unsafe fn fetch_compat<T>(&mut self, size: usize) -> Option<T> {
+        let size = size.min(mem::size_of::<T>());
+        let bytes = self.fetch_bytes(size)?;
+        let mut arg: T = mem::zeroed();
+        ptr::copy_nonoverlapping(bytes.as_ptr(), &mut arg as *mut T as *mut u8, size);
+        Some(arg)
+    }
+
+    /// Fetch a slice of the given number of typed arguments. Returns `None` if there's not enough
+    /// data left or the data isn't aligned for the type T. This function is unsafe because there
+    /// is no guarantee that the data actually contains the type T.
+    pub unsafe fn fetch_slice<T>(&mut self, count: usize) -> Option<&'a [T]> {
+        if self.data.as_ptr().align_offset(mem::align_of::<T>()) != 0 {
+            return None;
+        }
+        let len = mem::size_of::<T>().checked_mul(count)?;
+        let bytes = self.fetch_bytes(len)?;
+        Some(std::slice::from_raw_parts(
+            bytes.as_ptr() as *const T,
+            count,
+        ))
+    }
+
     /// Fetch a (zero-terminated) string (can be non-utf8). Returns `None` if there's not enough
     /// data left or no zero-termination could be found. This function is unsafe because there is
     /// no guarantee that the data actually contains a string.
@@ -65,6 +92,9 @@ mod tests {
 
     const TEST_DATA: [u8; 10] = [0x66, 0x6f, 0x6f, 0x00, 0x62, 0x61, 0x72, 0x00, 0x62, 0x61];
 
+    #[repr(C, align(8))]
+    struct AlignedData<T>(T);
+
     #[repr(C)]
     struct TestArgument {
         p1: u8,
@@ -72,6 +102,23 @@ mod tests {
         p3: u16,
     }
 
+    #[test]
+    fn compat_argument() {
+        let mut it = ArgumentIterator::new(&TEST_DATA);
+        let arg: TestArgument = unsafe { it.fetch_compat(2).unwrap() };
+        assert_eq!(arg.p1, 0x66);
+        assert_eq!(arg.p2, 0x6f);
+        assert_eq!(arg.p3, 0);
+        assert_eq!(it.len(), 8);
+        // Larger sizes fetch the whole argument
+        let arg: TestArgument = unsafe { it.fetch_compat(8).unwrap() };
+        assert_eq!(arg.p1, 0x6f);
+        assert_eq!(arg.p2, 0x00);
+        assert_eq!(it.len(), 4);
+        it.fetch_bytes(3).unwrap();
+        assert!(unsafe { it.fetch_compat::<TestArgument>(2) }.is_none());
+    }
+
     #[test]
     fn all_data() {
         let mut it = ArgumentIterator::new(&TEST_DATA);
@@ -104,6 +151,21 @@ mod tests {
         assert_eq!(it.len(), 2);
     }
 
+    #[test]
+    fn slice_argument() {
+        let data = AlignedData(TEST_DATA);
+        let mut it = ArgumentIterator::new(&data.0);
+        let arg: &[TestArgument] = unsafe { it.fetch_slice(2).unwrap() };
+        assert_eq!(arg.len(), 2);
+        assert_eq!(arg[0].p1, 0x66);
+        assert_eq!(arg[1].p1, 0x62);
+        assert_eq!(arg[1].p3, u16::from_ne_bytes([0x72, 0x00]));
+        assert_eq!(it.len(), 2);
+        let arg: Option<&[TestArgument]> = unsafe { it.fetch_slice(1) };
+        assert!(arg.is_none());
+        assert_eq!(it.len(), 2);
+    }
+
     #[test]
     fn string_argument() {
         let mut it = ArgumentIterator::new(&TEST_DATA);
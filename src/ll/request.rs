@@ -3,6 +3,7 @@
 //! A request represents information about a filesystem operation the kernel driver wants us to
 //! perform.
 
+use crate::errno::Errno;
 use crate::fuse_abi::*;
 use std::convert::TryFrom;
 use std::ffi::OsStr;
@@ -43,8 +44,59 @@ impl fmt::Display for RequestError {
 
 impl error::Error for RequestError {}
 
+/// Opcodes of requests that the kernel driver doesn't expect a reply to
+const NO_REPLY_OPCODES: [u32; 4] = [
+    fuse_opcode::FUSE_FORGET as u32,
+    fuse_opcode::FUSE_INTERRUPT as u32,
+    fuse_opcode::FUSE_NOTIFY_REPLY as u32,
+    fuse_opcode::FUSE_BATCH_FORGET as u32,
+];
+
+/// Request that couldn't be parsed. If at least its header could be read, the request can still
+/// be answered with an error, so that the kernel driver doesn't wait for a reply forever.
+#[derive(Debug)]
+pub struct InvalidRequest<'a> {
+    /// Header of the request, None if the data was too short for a header
+    pub header: Option<&'a fuse_in_header>,
+    /// Reason why the request couldn't be parsed
+    pub error: RequestError,
+}
+
+impl<'a> InvalidRequest<'a> {
+    /// Returns the error to reply to the request with: ENOSYS for unknown operations and EIO
+    /// for malformed requests. Returns None if the request can't be replied to, because its
+    /// header is unreadable or because the kernel driver doesn't expect a reply to it.
+    pub fn reply_error(&self) -> Option<Errno> {
+        let header = self.header?;
+        if NO_REPLY_OPCODES.contains(&header.opcode) {
+            return None;
+        }
+        match self.error {
+            RequestError::UnknownOperation(_) => Some(Errno::ENOSYS),
+            _ => Some(Errno::EIO),
+        }
+    }
+}
+
+impl<'a> fmt::Display for InvalidRequest<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.header {
+            Some(header) => write!(
+                f,
+                "FUSE({:3}) ino {:#018x}: {}",
+                header.unique, header.nodeid, self.error
+            ),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl<'a> error::Error for InvalidRequest<'a> {}
+
 /// Filesystem operation (and arguments) the kernel driver wants us to perform. The fields of each
 /// variant needs to match the actual arguments the kernel driver sends for the specific operation.
+/// Arguments that are sent with a smaller size by older kernel drivers are copied and extended
+/// to the latest version.
 #[derive(Debug)]
 pub enum Operation<'a> {
     Lookup {
@@ -63,7 +115,7 @@ pub enum Operation<'a> {
         link: &'a OsStr,
     },
     MkNod {
-        arg: &'a fuse_mknod_in,
+        arg: fuse_mknod_in,
         name: &'a OsStr,
     },
     MkDir {
@@ -89,10 +141,10 @@ pub enum Operation<'a> {
         arg: &'a fuse_open_in,
     },
     Read {
-        arg: &'a fuse_read_in,
+        arg: fuse_read_in,
     },
     Write {
-        arg: &'a fuse_write_in,
+        arg: fuse_write_in,
         data: &'a [u8],
     },
     StatFs,
@@ -121,13 +173,13 @@ pub enum Operation<'a> {
         arg: &'a fuse_flush_in,
     },
     Init {
-        arg: &'a fuse_init_in,
+        arg: fuse_init_in,
     },
     OpenDir {
         arg: &'a fuse_open_in,
     },
     ReadDir {
-        arg: &'a fuse_read_in,
+        arg: fuse_read_in,
     },
     ReleaseDir {
         arg: &'a fuse_release_in,
@@ -136,19 +188,19 @@ pub enum Operation<'a> {
         arg: &'a fuse_fsync_in,
     },
     GetLk {
-        arg: &'a fuse_lk_in,
+        arg: fuse_lk_in,
     },
     SetLk {
-        arg: &'a fuse_lk_in,
+        arg: fuse_lk_in,
     },
     SetLkW {
-        arg: &'a fuse_lk_in,
+        arg: fuse_lk_in,
     },
     Access {
         arg: &'a fuse_access_in,
     },
     Create {
-        arg: &'a fuse_create_in,
+        arg: fuse_create_in,
         name: &'a OsStr,
     },
     Interrupt {
@@ -158,31 +210,51 @@ pub enum Operation<'a> {
         arg: &'a fuse_bmap_in,
     },
     Destroy,
-    #[cfg(feature = "abi-7-11")]
     IoCtl {
         arg: &'a fuse_ioctl_in,
         data: &'a [u8],
     },
-    #[cfg(feature = "abi-7-11")]
     Poll {
         arg: &'a fuse_poll_in,
     },
-    #[cfg(feature = "abi-7-15")]
     NotifyReply {
+        arg: &'a fuse_notify_retrieve_in,
         data: &'a [u8],
     },
-    #[cfg(feature = "abi-7-16")]
     BatchForget {
-        arg: &'a fuse_forget_in,
+        arg: &'a fuse_batch_forget_in,
         nodes: &'a [fuse_forget_one],
     },
-    #[cfg(feature = "abi-7-19")]
     FAllocate {
         arg: &'a fuse_fallocate_in,
     },
-    #[cfg(feature = "abi-7-21")]
     ReadDirPlus {
-        arg: &'a fuse_read_in,
+        arg: fuse_read_in,
+    },
+    Rename2 {
+        arg: &'a fuse_rename2_in,
+        name: &'a OsStr,
+        newname: &'a OsStr,
+    },
+    Lseek {
+        arg: &'a fuse_lseek_in,
+    },
+    CopyFileRange {
+        arg: &'a fuse_copy_file_range_in,
+    },
+    SetupMapping {
+        arg: &'a fuse_setupmapping_in,
+    },
+    RemoveMapping {
+        arg: &'a fuse_removemapping_in,
+        // Copied, since the mappings follow the 4 byte argument unaligned
+        mappings: Vec<fuse_removemapping_one>,
+    },
+    SyncFs {
+        arg: &'a fuse_syncfs_in,
+    },
+    TmpFile {
+        arg: fuse_create_in,
     },
 
     #[cfg(target_os = "macos")]
@@ -198,9 +270,8 @@ pub enum Operation<'a> {
         newname: &'a OsStr,
     },
 
-    #[cfg(feature = "abi-7-12")]
     CuseInit {
-        arg: &'a fuse_init_in,
+        arg: fuse_init_in,
     },
 }
 
@@ -230,7 +301,7 @@ impl<'a> fmt::Display for Operation<'a> {
             Operation::ListXAttr { arg } => write!(f, "LISTXATTR size {}", arg.size),
             Operation::RemoveXAttr { name } => write!(f, "REMOVEXATTR name {:?}", name),
             Operation::Flush { arg } => write!(f, "FLUSH fh {}, lock owner {}", arg.fh, arg.lock_owner),
-            Operation::Init { arg } => write!(f, "INIT kernel ABI {}.{}, flags {:#x}, max readahead {}", arg.major, arg.minor, arg.flags, arg.max_readahead),
+            Operation::Init { arg } => write!(f, "INIT kernel ABI {}.{}, flags {:#x}, flags2 {:#x}, max readahead {}", arg.major, arg.minor, arg.flags, arg.flags2, arg.max_readahead),
             Operation::OpenDir { arg } => write!(f, "OPENDIR flags {:#x}", arg.flags),
             Operation::ReadDir { arg } => write!(f, "READDIR fh {}, offset {}, size {}", arg.fh, arg.offset, arg.size),
             Operation::ReleaseDir { arg } => write!(f, "RELEASEDIR fh {}, flags {:#x}, release flags {:#x}, lock owner {}", arg.fh, arg.flags, arg.release_flags, arg.lock_owner),
@@ -243,18 +314,19 @@ impl<'a> fmt::Display for Operation<'a> {
             Operation::Interrupt { arg } => write!(f, "INTERRUPT unique {}", arg.unique),
             Operation::BMap { arg } => write!(f, "BMAP blocksize {}, ids {}", arg.blocksize, arg.block),
             Operation::Destroy => write!(f, "DESTROY"),
-            #[cfg(feature = "abi-7-11")]
             Operation::IoCtl { arg, data} => write!(f, "IOCTL fh {}, cmd {}, data size {}, flags {:#x}", arg.fh, arg.cmd, data.len(), arg.flags),
-            #[cfg(feature = "abi-7-11")]
             Operation::Poll { arg } => write!(f, "POLL fh {}, flags {:#x}", arg.fh, arg.flags),
-            #[cfg(feature = "abi-7-15")]
-            Operation::NotifyReply { data } => write!(f, "NOTIFYREPLY data len {}", data.len()),
-            #[cfg(feature = "abi-7-16")]
-            Operation::BatchForget { arg, nodes } => write!(f, "BATCHFORGET nodes {}, nlookup {}", nodes.len(), arg.nlookup),
-            #[cfg(feature = "abi-7-19")]
-            Operation::FAllocate { arg: _ } => write!(f, "FALLOCATE"),
-            #[cfg(feature = "abi-7-21")]
+            Operation::NotifyReply { arg, data } => write!(f, "NOTIFYREPLY offset {}, size {}, data len {}", arg.offset, arg.size, data.len()),
+            Operation::BatchForget { arg, nodes } => write!(f, "BATCHFORGET count {}, nodes {}", arg.count, nodes.len()),
+            Operation::FAllocate { arg } => write!(f, "FALLOCATE fh {}, offset {}, length {}, mode {:#x}", arg.fh, arg.offset, arg.length, arg.mode),
             Operation::ReadDirPlus { arg } => write!(f, "READDIRPLUS fh {}, offset {}, size {}", arg.fh, arg.offset, arg.size),
+            Operation::Rename2 { arg, name, newname } => write!(f, "RENAME2 name {:?}, newdir {:#018x}, newname {:?}, flags {:#x}", name, arg.newdir, newname, arg.flags),
+            Operation::Lseek { arg } => write!(f, "LSEEK fh {}, offset {}, whence {}", arg.fh, arg.offset, arg.whence),
+            Operation::CopyFileRange { arg } => write!(f, "COPY_FILE_RANGE fh_in {}, off_in {}, nodeid_out {:#018x}, fh_out {}, off_out {}, len {}, flags {:#x}", arg.fh_in, arg.off_in, arg.nodeid_out, arg.fh_out, arg.off_out, arg.len, arg.flags),
+            Operation::SetupMapping { arg } => write!(f, "SETUPMAPPING fh {}, foffset {}, len {}, flags {:#x}, moffset {}", arg.fh, arg.foffset, arg.len, arg.flags, arg.moffset),
+            Operation::RemoveMapping { arg, mappings } => write!(f, "REMOVEMAPPING count {}, mappings {}", arg.count, mappings.len()),
+            Operation::SyncFs { .. } => write!(f, "SYNCFS"),
+            Operation::TmpFile { arg } => write!(f, "TMPFILE mode {:#05o}, flags {:#x}", arg.mode, arg.flags),
 
             #[cfg(target_os = "macos")]
             Operation::SetVolName { name } => write!(f, "SETVOLNAME name {:?}", name),
@@ -263,14 +335,28 @@ impl<'a> fmt::Display for Operation<'a> {
             #[cfg(target_os = "macos")]
             Operation::Exchange { arg, oldname, newname } => write!(f, "EXCHANGE olddir {:#018x}, oldname {:?}, newdir {:#018x}, newname {:?}, options {:#x}", arg.olddir, oldname, arg.newdir, newname, arg.options),
 
-            #[cfg(feature = "abi-7-12")]
             Operation::CuseInit { arg } => write!(f, "CUSE_INIT kernel ABI {}.{}, flags {:#x}, max readahead {}", arg.major, arg.minor, arg.flags, arg.max_readahead),
         }
     }
 }
 
+/// Returns the size of the argument type T as sent by a kernel driver of the given ABI minor
+/// version, given the ABI version it last grew in and its size before
+fn arg_size<T>(minor: u32, grown_in: u32, compat_size: usize) -> usize {
+    if minor < grown_in {
+        compat_size
+    } else {
+        mem::size_of::<T>()
+    }
+}
+
 impl<'a> Operation<'a> {
-    fn parse(opcode: &fuse_opcode, data: &mut ArgumentIterator<'a>) -> Option<Self> {
+    fn parse(opcode: &fuse_opcode, data: &mut ArgumentIterator<'a>, minor: u32) -> Option<Self> {
+        let read_in_size = arg_size::<fuse_read_in>(minor, 9, FUSE_COMPAT_READ_IN_SIZE);
+        let lk_in_size = arg_size::<fuse_lk_in>(minor, 9, FUSE_COMPAT_LK_IN_SIZE);
+        let create_in_size = arg_size::<fuse_create_in>(minor, 12, FUSE_COMPAT_CREATE_IN_SIZE);
+        // The kernel driver sends init arguments of its own version, which isn't known yet
+        let init_in_size = data.len().max(FUSE_COMPAT_INIT_IN_SIZE);
         unsafe {
             Some(match opcode {
                 fuse_opcode::FUSE_LOOKUP => Operation::Lookup {
@@ -285,7 +371,11 @@ impl<'a> Operation<'a> {
                     link: data.fetch_str()?,
                 },
                 fuse_opcode::FUSE_MKNOD => Operation::MkNod {
-                    arg: data.fetch()?,
+                    arg: data.fetch_compat(arg_size::<fuse_mknod_in>(
+                        minor,
+                        12,
+                        FUSE_COMPAT_MKNOD_IN_SIZE,
+                    ))?,
                     name: data.fetch_str()?,
                 },
                 fuse_opcode::FUSE_MKDIR => Operation::MkDir {
@@ -308,9 +398,15 @@ impl<'a> Operation<'a> {
                     name: data.fetch_str()?,
                 },
                 fuse_opcode::FUSE_OPEN => Operation::Open { arg: data.fetch()? },
-                fuse_opcode::FUSE_READ => Operation::Read { arg: data.fetch()? },
+                fuse_opcode::FUSE_READ => Operation::Read {
+                    arg: data.fetch_compat(read_in_size)?,
+                },
                 fuse_opcode::FUSE_WRITE => Operation::Write {
-                    arg: data.fetch()?,
+                    arg: data.fetch_compat(arg_size::<fuse_write_in>(
+                        minor,
+                        9,
+                        FUSE_COMPAT_WRITE_IN_SIZE,
+                    ))?,
                     data: data.fetch_all(),
                 },
                 fuse_opcode::FUSE_STATFS => Operation::StatFs,
@@ -330,43 +426,75 @@ impl<'a> Operation<'a> {
                     name: data.fetch_str()?,
                 },
                 fuse_opcode::FUSE_FLUSH => Operation::Flush { arg: data.fetch()? },
-                fuse_opcode::FUSE_INIT => Operation::Init { arg: data.fetch()? },
+                fuse_opcode::FUSE_INIT => Operation::Init {
+                    arg: data.fetch_compat(init_in_size)?,
+                },
                 fuse_opcode::FUSE_OPENDIR => Operation::OpenDir { arg: data.fetch()? },
-                fuse_opcode::FUSE_READDIR => Operation::ReadDir { arg: data.fetch()? },
+                fuse_opcode::FUSE_READDIR => Operation::ReadDir {
+                    arg: data.fetch_compat(read_in_size)?,
+                },
                 fuse_opcode::FUSE_RELEASEDIR => Operation::ReleaseDir { arg: data.fetch()? },
                 fuse_opcode::FUSE_FSYNCDIR => Operation::FSyncDir { arg: data.fetch()? },
-                fuse_opcode::FUSE_GETLK => Operation::GetLk { arg: data.fetch()? },
-                fuse_opcode::FUSE_SETLK => Operation::SetLk { arg: data.fetch()? },
-                fuse_opcode::FUSE_SETLKW => Operation::SetLkW { arg: data.fetch()? },
+                fuse_opcode::FUSE_GETLK => Operation::GetLk {
+                    arg: data.fetch_compat(lk_in_size)?,
+                },
+                fuse_opcode::FUSE_SETLK => Operation::SetLk {
+                    arg: data.fetch_compat(lk_in_size)?,
+                },
+                fuse_opcode::FUSE_SETLKW => Operation::SetLkW {
+                    arg: data.fetch_compat(lk_in_size)?,
+                },
                 fuse_opcode::FUSE_ACCESS => Operation::Access { arg: data.fetch()? },
                 fuse_opcode::FUSE_CREATE => Operation::Create {
-                    arg: data.fetch()?,
+                    arg: data.fetch_compat(create_in_size)?,
                     name: data.fetch_str()?,
                 },
                 fuse_opcode::FUSE_INTERRUPT => Operation::Interrupt { arg: data.fetch()? },
                 fuse_opcode::FUSE_BMAP => Operation::BMap { arg: data.fetch()? },
                 fuse_opcode::FUSE_DESTROY => Operation::Destroy,
-                #[cfg(feature = "abi-7-11")]
                 fuse_opcode::FUSE_IOCTL => Operation::IoCtl {
                     arg: data.fetch()?,
                     data: data.fetch_all(),
                 },
-                #[cfg(feature = "abi-7-11")]
                 fuse_opcode::FUSE_POLL => Operation::Poll { arg: data.fetch()? },
-                #[cfg(feature = "abi-7-15")]
                 fuse_opcode::FUSE_NOTIFY_REPLY => Operation::NotifyReply {
+                    arg: data.fetch()?,
                     data: data.fetch_all(),
                 },
-                #[cfg(feature = "abi-7-16")]
-                // TODO: parse the nodes
-                fuse_opcode::FUSE_BATCH_FORGET => Operation::BatchForget {
+                fuse_opcode::FUSE_BATCH_FORGET => {
+                    let arg: &fuse_batch_forget_in = data.fetch()?;
+                    Operation::BatchForget {
+                        arg,
+                        nodes: data.fetch_slice(arg.count as usize)?,
+                    }
+                }
+                fuse_opcode::FUSE_FALLOCATE => Operation::FAllocate { arg: data.fetch()? },
+                fuse_opcode::FUSE_READDIRPLUS => Operation::ReadDirPlus {
+                    arg: data.fetch_compat(read_in_size)?,
+                },
+                fuse_opcode::FUSE_RENAME2 => Operation::Rename2 {
                     arg: data.fetch()?,
-                    nodes: &[],
+                    name: data.fetch_str()?,
+                    newname: data.fetch_str()?,
+                },
+                fuse_opcode::FUSE_LSEEK => Operation::Lseek { arg: data.fetch()? },
+                fuse_opcode::FUSE_COPY_FILE_RANGE => {
+                    Operation::CopyFileRange { arg: data.fetch()? }
+                }
+                fuse_opcode::FUSE_SETUPMAPPING => Operation::SetupMapping { arg: data.fetch()? },
+                fuse_opcode::FUSE_REMOVEMAPPING => {
+                    let arg: &fuse_removemapping_in = data.fetch()?;
+                    Operation::RemoveMapping {
+                        arg,
+                        mappings: (0..arg.count)
+                            .map(|_| data.fetch_compat(mem::size_of::<fuse_removemapping_one>()))
+                            .collect::<Option<_>>()?,
+                    }
+                }
+                fuse_opcode::FUSE_SYNCFS => Operation::SyncFs { arg: data.fetch()? },
+                fuse_opcode::FUSE_TMPFILE => Operation::TmpFile {
+                    arg: data.fetch_compat(create_in_size)?,
                 },
-                #[cfg(feature = "abi-7-19")]
-                fuse_opcode::FUSE_FALLOCATE => Operation::FAllocate { arg: data.fetch()? },
-                #[cfg(feature = "abi-7-21")]
-                fuse_opcode::FUSE_READDIRPLUS => Operation::ReadDirPlus { arg: data.fetch()? },
 
                 #[cfg(target_os = "macos")]
                 fuse_opcode::FUSE_SETVOLNAME => Operation::SetVolName {
@@ -381,8 +509,9 @@ impl<'a> Operation<'a> {
                     newname: data.fetch_str()?,
                 },
 
-                #[cfg(feature = "abi-7-12")]
-                fuse_opcode::CUSE_INIT => Operation::CuseInit { arg: data.fetch()? },
+                fuse_opcode::CUSE_INIT => Operation::CuseInit {
+                    arg: data.fetch_compat(init_in_size)?,
+                },
             })
         }
     }
@@ -406,26 +535,45 @@ impl<'a> fmt::Display for Request<'a> {
 }
 
 impl<'a> TryFrom<&'a [u8]> for Request<'a> {
-    type Error = RequestError;
+    type Error = InvalidRequest<'a>;
 
     fn try_from(data: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::parse(data, FUSE_KERNEL_MINOR_VERSION)
+    }
+}
+
+impl<'a> Request<'a> {
+    /// Parse a request sent by a kernel driver that negotiated the given ABI minor version.
+    /// Arguments that grew in later versions are extended to the latest version (with zeroed
+    /// fields), so the operation looks the same with every kernel driver.
+    pub fn parse(data: &'a [u8], minor: u32) -> Result<Self, InvalidRequest<'a>> {
         // Parse a raw packet as sent by the kernel driver into typed data. Every request always
         // begins with a `fuse_in_header` struct followed by arguments depending on the opcode.
         let data_len = data.len();
         let mut data = ArgumentIterator::new(data);
         // Parse header
-        let header: &fuse_in_header =
-            unsafe { data.fetch() }.ok_or_else(|| RequestError::ShortReadHeader(data.len()))?;
+        let header: &fuse_in_header = unsafe { data.fetch() }.ok_or_else(|| InvalidRequest {
+            header: None,
+            error: RequestError::ShortReadHeader(data.len()),
+        })?;
+        let invalid = |error| InvalidRequest {
+            header: Some(header),
+            error,
+        };
         // Parse/check opcode
-        let opcode = fuse_opcode::try_from(header.opcode)
-            .map_err(|_: InvalidOpcodeError| RequestError::UnknownOperation(header.opcode))?;
+        let opcode = fuse_opcode::try_from(header.opcode).map_err(|_: InvalidOpcodeError| {
+            invalid(RequestError::UnknownOperation(header.opcode))
+        })?;
         // Check data size
         if data_len < header.len as usize {
-            return Err(RequestError::ShortRead(data_len, header.len as usize));
+            return Err(invalid(RequestError::ShortRead(
+                data_len,
+                header.len as usize,
+            )));
         }
         // Parse/check operation arguments
-        let operation =
-            Operation::parse(&opcode, &mut data).ok_or_else(|| RequestError::InsufficientData)?;
+        let operation = Operation::parse(&opcode, &mut data, minor)
+            .ok_or_else(|| invalid(RequestError::InsufficientData))?;
         Ok(Self { header, operation })
     }
 }
@@ -441,6 +589,12 @@ impl<'a> Request<'a> {
         self.header.unique
     }
 
+    /// Returns the raw opcode of this request.
+    #[inline]
+    pub fn opcode(&self) -> u32 {
+        self.header.opcode
+    }
+
     /// Returns the node id of the inode this request is targeted to.
     #[inline]
     pub fn nodeid(&self) -> u64 {
@@ -477,7 +631,7 @@ mod tests {
     use super::*;
 
     #[cfg(target_endian = "big")]
-    const INIT_REQUEST: [u8; 56] = [
+    const COMPAT_INIT_REQUEST: [u8; 56] = [
         0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x1a, // len, opcode
         0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
         0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // nodeid
@@ -488,7 +642,7 @@ mod tests {
     ];
 
     #[cfg(target_endian = "little")]
-    const INIT_REQUEST: [u8; 56] = [
+    const COMPAT_INIT_REQUEST: [u8; 56] = [
         0x38, 0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, // len, opcode
         0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
         0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
@@ -499,7 +653,41 @@ mod tests {
     ];
 
     #[cfg(target_endian = "big")]
-    const MKNOD_REQUEST: [u8; 56] = [
+    const INIT_REQUEST: [u8; 104] = [
+        0x00, 0x00, 0x00, 0x68, 0x00, 0x00, 0x00, 0x1a, // len, opcode
+        0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // nodeid
+        0xc0, 0x01, 0xd0, 0x0d, 0xc0, 0x01, 0xca, 0xfe, // uid, gid
+        0xc0, 0xde, 0xba, 0x5e, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x24, // major, minor
+        0x00, 0x00, 0x10, 0x00, 0x40, 0x00, 0x00, 0x00, // max_readahead, flags
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // flags2, unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+    ];
+
+    #[cfg(target_endian = "little")]
+    const INIT_REQUEST: [u8; 104] = [
+        0x68, 0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x07, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00, // major, minor
+        0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, // max_readahead, flags
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // flags2, unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unused
+    ];
+
+    #[cfg(target_endian = "big")]
+    const COMPAT_MKNOD_REQUEST: [u8; 56] = [
         0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x08, // len, opcode
         0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
         0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // nodeid
@@ -509,8 +697,8 @@ mod tests {
         0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
     ];
 
-    #[cfg(all(target_endian = "little", not(feature = "abi-7-12")))]
-    const MKNOD_REQUEST: [u8; 56] = [
+    #[cfg(target_endian = "little")]
+    const COMPAT_MKNOD_REQUEST: [u8; 56] = [
         0x38, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, // len, opcode
         0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
         0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
@@ -520,9 +708,21 @@ mod tests {
         0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
     ];
 
-    #[cfg(all(target_endian = "little", feature = "abi-7-12"))]
+    #[cfg(target_endian = "big")]
     const MKNOD_REQUEST: [u8; 64] = [
-        0x38, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, // len, opcode
+        0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x08, // len, opcode
+        0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // nodeid
+        0xc0, 0x01, 0xd0, 0x0d, 0xc0, 0x01, 0xca, 0xfe, // uid, gid
+        0xc0, 0xde, 0xba, 0x5e, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x00, 0x00, 0x01, 0xa4, 0x00, 0x00, 0x00, 0x00, // mode, rdev
+        0x00, 0x00, 0x01, 0xed, 0x00, 0x00, 0x03, 0xe7, // umask, padding
+        0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
+    ];
+
+    #[cfg(target_endian = "little")]
+    const MKNOD_REQUEST: [u8; 64] = [
+        0x40, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, // len, opcode
         0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
         0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
         0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
@@ -532,10 +732,57 @@ mod tests {
         0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
     ];
 
+    #[repr(C, align(8))]
+    struct AlignedData<T>(T);
+
+    #[cfg(target_endian = "little")]
+    const BATCH_FORGET_REQUEST: AlignedData<[u8; 80]> = AlignedData([
+        0x50, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // count, dummy
+        0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nlookup
+        0x99, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, // nodeid
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nlookup
+    ]);
+
+    #[cfg(target_endian = "little")]
+    const FALLOCATE_REQUEST: AlignedData<[u8; 72]> = AlignedData([
+        0x48, 0x00, 0x00, 0x00, 0x2b, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // fh
+        0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // offset
+        0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // length
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mode, padding
+    ]);
+
+    #[cfg(target_endian = "little")]
+    const REMOVEMAPPING_REQUEST: AlignedData<[u8; 76]> = AlignedData([
+        0x4c, 0x00, 0x00, 0x00, 0x31, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x02, 0x00, 0x00, 0x00, // count
+        0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, // moffset
+        0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, // len
+        0x00, 0x00, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, // moffset
+        0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // len
+    ]);
+
     #[test]
     fn short_read_header() {
         match Request::try_from(&INIT_REQUEST[..20]) {
-            Err(RequestError::ShortReadHeader(20)) => (),
+            Err(InvalidRequest {
+                header: None,
+                error: RequestError::ShortReadHeader(20),
+            }) => (),
             _ => panic!("Unexpected request parsing result"),
         }
     }
@@ -543,7 +790,57 @@ mod tests {
     #[test]
     fn short_read() {
         match Request::try_from(&INIT_REQUEST[..48]) {
-            Err(RequestError::ShortRead(48, 56)) => (),
+            Err(InvalidRequest {
+                header: Some(header),
+                error: RequestError::ShortRead(48, len),
+            }) if len == INIT_REQUEST.len() => assert_eq!(header.opcode, 26),
+            _ => panic!("Unexpected request parsing result"),
+        }
+    }
+
+    #[test]
+    fn unknown_operation() {
+        let mut data = INIT_REQUEST;
+        data[4..8].copy_from_slice(&255u32.to_ne_bytes());
+        match Request::try_from(&data[..]) {
+            Err(
+                err @ InvalidRequest {
+                    header: Some(_),
+                    error: RequestError::UnknownOperation(255),
+                },
+            ) => {
+                assert_eq!(err.header.unwrap().unique, 0xdead_beef_baad_f00d);
+                assert_eq!(err.reply_error(), Some(Errno::ENOSYS));
+            }
+            _ => panic!("Unexpected request parsing result"),
+        }
+    }
+
+    #[test]
+    fn malformed_request() {
+        // A mknod request without the name is answered with EIO
+        let mut data = MKNOD_REQUEST;
+        data[0..4].copy_from_slice(&56u32.to_ne_bytes());
+        match Request::try_from(&data[..56]) {
+            Err(
+                err @ InvalidRequest {
+                    header: Some(_),
+                    error: RequestError::InsufficientData,
+                },
+            ) => assert_eq!(err.reply_error(), Some(Errno::EIO)),
+            _ => panic!("Unexpected request parsing result"),
+        }
+        // A forget request without the nlookup argument isn't answered, since the kernel
+        // doesn't expect a reply to forget
+        data[0..4].copy_from_slice(&44u32.to_ne_bytes());
+        data[4..8].copy_from_slice(&2u32.to_ne_bytes());
+        match Request::try_from(&data[..44]) {
+            Err(
+                err @ InvalidRequest {
+                    header: Some(_),
+                    error: RequestError::InsufficientData,
+                },
+            ) => assert_eq!(err.reply_error(), None),
             _ => panic!("Unexpected request parsing result"),
         }
     }
@@ -551,18 +848,34 @@ mod tests {
     #[test]
     fn init() {
         let req = Request::try_from(&INIT_REQUEST[..]).unwrap();
-        assert_eq!(req.header.len, 56);
+        assert_eq!(req.header.len as usize, INIT_REQUEST.len());
         assert_eq!(req.header.opcode, 26);
         assert_eq!(req.unique(), 0xdead_beef_baad_f00d);
         assert_eq!(req.nodeid(), 0x1122_3344_5566_7788);
         assert_eq!(req.uid(), 0xc001_d00d);
         assert_eq!(req.gid(), 0xc001_cafe);
         assert_eq!(req.pid(), 0xc0de_ba5e);
+        match req.operation() {
+            Operation::Init { arg } => {
+                assert_eq!(arg.major, 7);
+                assert_eq!(arg.minor, 36);
+                assert_eq!(arg.max_readahead, 4096);
+                assert_eq!(arg.flags2, 1);
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn compat_init() {
+        // Kernel drivers before ABI 7.36 send shorter init arguments
+        let req = Request::try_from(&COMPAT_INIT_REQUEST[..]).unwrap();
         match req.operation() {
             Operation::Init { arg } => {
                 assert_eq!(arg.major, 7);
                 assert_eq!(arg.minor, 8);
                 assert_eq!(arg.max_readahead, 4096);
+                assert_eq!(arg.flags2, 0);
             }
             _ => panic!("Unexpected request operation"),
         }
@@ -571,7 +884,7 @@ mod tests {
     #[test]
     fn mknod() {
         let req = Request::try_from(&MKNOD_REQUEST[..]).unwrap();
-        assert_eq!(req.header.len, 56);
+        assert_eq!(req.header.len, 64);
         assert_eq!(req.header.opcode, 8);
         assert_eq!(req.unique(), 0xdead_beef_baad_f00d);
         assert_eq!(req.nodeid(), 0x1122_3344_5566_7788);
@@ -581,13 +894,94 @@ mod tests {
         match req.operation() {
             Operation::MkNod { arg, name } => {
                 assert_eq!(arg.mode, 0o644);
-                #[cfg(feature = "abi-7-12")]
                 assert_eq!(arg.umask, 0o755);
-                #[cfg(feature = "abi-7-12")]
                 assert_eq!(arg.padding, 999);
                 assert_eq!(*name, "foo.txt");
             }
             _ => panic!("Unexpected request operation"),
         }
     }
+
+    #[test]
+    fn compat_mknod() {
+        // Before ABI 7.12, mknod arguments have no umask
+        let req = Request::parse(&COMPAT_MKNOD_REQUEST[..], 11).unwrap();
+        match req.operation() {
+            Operation::MkNod { arg, name } => {
+                assert_eq!(arg.mode, 0o644);
+                assert_eq!(arg.umask, 0);
+                assert_eq!(*name, "foo.txt");
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+        // The name would be taken for the umask with later versions
+        let req = Request::parse(&COMPAT_MKNOD_REQUEST[..], 12);
+        assert!(req.is_err());
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn batch_forget() {
+        let req = Request::try_from(&BATCH_FORGET_REQUEST.0[..]).unwrap();
+        assert_eq!(req.header.len, 80);
+        assert_eq!(req.header.opcode, 42);
+        match req.operation() {
+            Operation::BatchForget { arg, nodes } => {
+                assert_eq!(arg.count, 2);
+                assert_eq!(nodes.len(), 2);
+                assert_eq!(nodes[0].nodeid, 0x1122_3344_5566_7788);
+                assert_eq!(nodes[0].nlookup, 3);
+                assert_eq!(nodes[1].nodeid, 0x2233_4455_6677_8899);
+                assert_eq!(nodes[1].nlookup, 1);
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn fallocate() {
+        let req = Request::try_from(&FALLOCATE_REQUEST.0[..]).unwrap();
+        assert_eq!(req.header.len, 72);
+        assert_eq!(req.header.opcode, 43);
+        match req.operation() {
+            Operation::FAllocate { arg } => {
+                assert_eq!(arg.fh, 0x2a);
+                assert_eq!(arg.offset, 0x1000);
+                assert_eq!(arg.length, 0x2000);
+                // FALLOC_FL_KEEP_SIZE | FALLOC_FL_PUNCH_HOLE
+                assert_eq!(arg.mode, 0x3);
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn removemapping() {
+        let req = Request::try_from(&REMOVEMAPPING_REQUEST.0[..]).unwrap();
+        assert_eq!(req.header.len, 76);
+        assert_eq!(req.header.opcode, 49);
+        match req.operation() {
+            Operation::RemoveMapping { arg, mappings } => {
+                assert_eq!(arg.count, 2);
+                assert_eq!(mappings.len(), 2);
+                assert_eq!(mappings[0].moffset, 0x20_0000);
+                assert_eq!(mappings[0].len, 0x10_0000);
+                assert_eq!(mappings[1].moffset, 0x60_0000);
+                assert_eq!(mappings[1].len, 0x1000);
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+        // More mappings than sent are insufficient data
+        let mut data = REMOVEMAPPING_REQUEST;
+        data.0[40] = 3;
+        match Request::try_from(&data.0[..]) {
+            Err(InvalidRequest {
+                error: RequestError::InsufficientData,
+                ..
+            }) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+    }
 }
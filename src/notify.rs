@@ -0,0 +1,420 @@
+//! Kernel notifications
+//!
+//! Besides replying to requests, a filesystem can send unsolicited notifications to the kernel
+//! driver, e.g. to invalidate cached data of inodes that changed without the kernel knowing.
+//! Notifications can be sent at any time and from any thread while the filesystem is mounted.
+
+use libc::c_int;
+use log::warn;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::channel::ChannelSender;
+use crate::fuse_abi::fuse_notify_delete_out;
+use crate::fuse_abi::{fuse_notify_code, fuse_notify_poll_wakeup_out, fuse_out_header};
+use crate::fuse_abi::{fuse_notify_inval_entry_out, fuse_notify_inval_inode_out};
+use crate::fuse_abi::{fuse_notify_retrieve_out, fuse_notify_store_out};
+use crate::reply::{as_bytes, ReplySender};
+
+/// A handle for sending notifications to the kernel driver. It can be obtained from a
+/// `Session` or `BackgroundSession`, cloned and sent to other threads. Notifications can
+/// only be delivered while the filesystem is mounted.
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    ch: ChannelSender,
+    retrieves: Arc<RetrieveTracker>,
+}
+
+/// Pending retrieve notifications, waiting for the kernel to send the retrieved data
+#[derive(Debug, Default)]
+struct RetrieveTracker {
+    next_unique: AtomicU64,
+    pending: Mutex<HashMap<u64, Sender<Vec<u8>>>>,
+}
+
+impl Notifier {
+    pub(crate) fn new(ch: ChannelSender) -> Notifier {
+        Notifier {
+            ch,
+            retrieves: Arc::new(RetrieveTracker::default()),
+        }
+    }
+
+    /// Returns a notifier that sends through the given channel sender instead, but shares
+    /// the pending retrieve notifications with this one
+    pub(crate) fn with_sender(&self, ch: ChannelSender) -> Notifier {
+        Notifier {
+            ch,
+            retrieves: self.retrieves.clone(),
+        }
+    }
+
+    /// Wake up the waiters of the poll request with the given kernel poll handle, see
+    /// `PollHandle`.
+    pub fn poll(&self, kh: u64) -> io::Result<()> {
+        let notification = fuse_notify_poll_wakeup_out { kh };
+        as_bytes(&notification, |bytes| {
+            self.send(fuse_notify_code::FUSE_POLL, bytes)
+        })
+    }
+
+    /// Invalidate the kernel cache of the given inode. The attributes of the inode are
+    /// invalidated and, if `offset` is not negative, cached data in the range starting at
+    /// `offset` with the given length (or up to the end of the file if `len` is not positive).
+    pub fn inval_inode(&self, ino: u64, offset: i64, len: i64) -> io::Result<()> {
+        let notification = fuse_notify_inval_inode_out {
+            ino,
+            off: offset,
+            len,
+        };
+        as_bytes(&notification, |bytes| {
+            self.send(fuse_notify_code::FUSE_NOTIFY_INVAL_INODE, bytes)
+        })
+    }
+
+    /// Invalidate the kernel cache of the directory entry with the given name in the given
+    /// parent directory (and the attributes of the parent directory).
+    pub fn inval_entry(&self, parent: u64, name: &OsStr) -> io::Result<()> {
+        let name = name.as_bytes();
+        let notification = fuse_notify_inval_entry_out {
+            parent,
+            namelen: name.len() as u32,
+            padding: 0,
+        };
+        as_bytes(&notification, |bytes| {
+            self.send(
+                fuse_notify_code::FUSE_NOTIFY_INVAL_ENTRY,
+                &[bytes[0], name, &[0]],
+            )
+        })
+    }
+
+    /// Notify the kernel that the directory entry with the given name in the given parent
+    /// directory was deleted. Like `inval_entry`, but if the entry refers to the given child
+    /// inode, the kernel also removes it from its caches, e.g. to update open directories.
+    pub fn delete(&self, parent: u64, child: u64, name: &OsStr) -> io::Result<()> {
+        let name = name.as_bytes();
+        let notification = fuse_notify_delete_out {
+            parent,
+            child,
+            namelen: name.len() as u32,
+            padding: 0,
+        };
+        as_bytes(&notification, |bytes| {
+            self.send(
+                fuse_notify_code::FUSE_NOTIFY_DELETE,
+                &[bytes[0], name, &[0]],
+            )
+        })
+    }
+
+    /// Store the given data in the kernel's page cache of the given inode, starting at the
+    /// given offset. The file size is extended if the data ends behind the end of the file.
+    pub fn store(&self, ino: u64, offset: u64, data: &[u8]) -> io::Result<()> {
+        let notification = fuse_notify_store_out {
+            nodeid: ino,
+            offset,
+            size: data.len() as u32,
+            padding: 0,
+        };
+        as_bytes(&notification, |bytes| {
+            self.send(fuse_notify_code::FUSE_NOTIFY_STORE, &[bytes[0], data])
+        })
+    }
+
+    /// Retrieve data of the given inode from the kernel's page cache, starting at the given
+    /// offset with up to `size` bytes. The kernel sends the cached data asynchronously, the
+    /// returned receiver yields it once it arrives. The retrieved data may be shorter than
+    /// requested (or empty) if it isn't cached.
+    pub fn retrieve(&self, ino: u64, offset: u64, size: u32) -> io::Result<Receiver<Vec<u8>>> {
+        let notify_unique = self.retrieves.next_unique.fetch_add(1, Ordering::SeqCst) + 1;
+        let (tx, rx) = channel();
+        self.retrieves
+            .pending
+            .lock()
+            .unwrap()
+            .insert(notify_unique, tx);
+        let notification = fuse_notify_retrieve_out {
+            notify_unique,
+            nodeid: ino,
+            offset,
+            size,
+            padding: 0,
+        };
+        let res = as_bytes(&notification, |bytes| {
+            self.send(fuse_notify_code::FUSE_NOTIFY_RETRIEVE, bytes)
+        });
+        if res.is_err() {
+            self.retrieves
+                .pending
+                .lock()
+                .unwrap()
+                .remove(&notify_unique);
+        }
+        res.map(|()| rx)
+    }
+
+    /// Deliver the data of a retrieve reply (FUSE_NOTIFY_REPLY) sent by the kernel
+    pub(crate) fn retrieve_reply(&self, notify_unique: u64, data: &[u8]) {
+        match self
+            .retrieves
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&notify_unique)
+        {
+            // The receiver may have been dropped, the data isn't needed anymore then
+            Some(tx) => drop(tx.send(data.to_vec())),
+            None => warn!("Unexpected retrieve reply {}", notify_unique),
+        }
+    }
+
+    /// Send a notification with the given code and payload to the kernel. Fails with ENOSYS
+    /// if the negotiated ABI version doesn't know the notification yet.
+    fn send(&self, code: fuse_notify_code, payload: &[&[u8]]) -> io::Result<()> {
+        let min_minor = match code {
+            fuse_notify_code::FUSE_POLL => 11,
+            fuse_notify_code::FUSE_NOTIFY_INVAL_INODE => 12,
+            fuse_notify_code::FUSE_NOTIFY_INVAL_ENTRY => 12,
+            fuse_notify_code::FUSE_NOTIFY_STORE => 15,
+            fuse_notify_code::FUSE_NOTIFY_RETRIEVE => 15,
+            fuse_notify_code::FUSE_NOTIFY_DELETE => 18,
+        };
+        if self.ch.proto_minor() < min_minor {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+        let len = payload.iter().fold(0, |l, b| l + b.len());
+        let header = fuse_out_header {
+            len: (mem::size_of::<fuse_out_header>() + len) as u32,
+            error: code as c_int,
+            unique: 0,
+        };
+        as_bytes(&header, |headerbytes| {
+            let mut sendbytes = headerbytes.to_vec();
+            sendbytes.extend(payload);
+            self.ch.send(&sendbytes)
+        })
+    }
+}
+
+/// Handle of a poll request. If the kernel requested a notification (FUSE_POLL_SCHEDULE_NOTIFY
+/// in the poll flags), the filesystem keeps the handle and calls `notify` once the polled
+/// file becomes ready, to wake up the processes waiting for it.
+#[derive(Clone, Debug)]
+pub struct PollHandle {
+    kh: u64,
+    notifier: Notifier,
+}
+
+impl PollHandle {
+    pub(crate) fn new(kh: u64, notifier: Notifier) -> PollHandle {
+        PollHandle { kh, notifier }
+    }
+
+    /// Returns the kernel's poll handle
+    pub fn kh(&self) -> u64 {
+        self.kh
+    }
+
+    /// Notify the kernel that the polled file is ready. The kernel then polls it again.
+    pub fn notify(&self) -> io::Result<()> {
+        self.notifier.poll(self.kh)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Notifier, PollHandle};
+    use crate::fuse_abi::fuse_out_header;
+    use crate::test_util::{channel, out_header, FakeDriver};
+    use std::ffi::OsStr;
+    use std::mem;
+
+    /// Returns the header and payload of the only notification sent to the given driver
+    fn sent(driver: &FakeDriver) -> (fuse_out_header, Vec<u8>) {
+        let mut sent = driver.take_sent();
+        assert_eq!(sent.len(), 1);
+        let data = sent.pop().unwrap();
+        let header = out_header(&data);
+        assert_eq!(header.len as usize, data.len());
+        assert_eq!(header.unique, 0);
+        (header, data[mem::size_of::<fuse_out_header>()..].to_vec())
+    }
+
+    #[test]
+    fn poll_handle() {
+        let (ch, driver) = channel("notify-poll");
+        PollHandle::new(0x1234, Notifier::new(ch.sender()))
+            .notify()
+            .unwrap();
+        let (header, payload) = sent(&driver);
+        assert_eq!(header.error, 1); // FUSE_POLL
+        assert_eq!(payload, 0x1234u64.to_ne_bytes());
+    }
+
+    #[test]
+    fn inval_inode() {
+        let (ch, driver) = channel("notify-inval-inode");
+        Notifier::new(ch.sender())
+            .inval_inode(0x11, 0x22, -1)
+            .unwrap();
+        let (header, payload) = sent(&driver);
+        assert_eq!(header.error, 2); // FUSE_NOTIFY_INVAL_INODE
+        let expected = [
+            0x11u64.to_ne_bytes(),
+            0x22u64.to_ne_bytes(),
+            (-1i64).to_ne_bytes(),
+        ];
+        assert_eq!(payload, expected.concat());
+    }
+
+    #[test]
+    fn inval_entry() {
+        let (ch, driver) = channel("notify-inval-entry");
+        Notifier::new(ch.sender())
+            .inval_entry(0x11, OsStr::new("abc"))
+            .unwrap();
+        let (header, payload) = sent(&driver);
+        assert_eq!(header.error, 3); // FUSE_NOTIFY_INVAL_ENTRY
+        let expected: [&[u8]; 4] = [
+            &0x11u64.to_ne_bytes(),
+            &3u32.to_ne_bytes(),
+            &[0; 4],
+            b"abc\0",
+        ];
+        assert_eq!(payload, expected.concat());
+    }
+
+    #[test]
+    fn delete() {
+        let (ch, driver) = channel("notify-delete");
+        Notifier::new(ch.sender())
+            .delete(0x11, 0x22, OsStr::new("abc"))
+            .unwrap();
+        let (header, payload) = sent(&driver);
+        assert_eq!(header.error, 6); // FUSE_NOTIFY_DELETE
+        let expected: [&[u8]; 5] = [
+            &0x11u64.to_ne_bytes(),
+            &0x22u64.to_ne_bytes(),
+            &3u32.to_ne_bytes(),
+            &[0; 4],
+            b"abc\0",
+        ];
+        assert_eq!(payload, expected.concat());
+    }
+
+    #[test]
+    fn store() {
+        let (ch, driver) = channel("notify-store");
+        Notifier::new(ch.sender())
+            .store(0x11, 0x22, b"data")
+            .unwrap();
+        let (header, payload) = sent(&driver);
+        assert_eq!(header.error, 4); // FUSE_NOTIFY_STORE
+        let expected: [&[u8]; 5] = [
+            &0x11u64.to_ne_bytes(),
+            &0x22u64.to_ne_bytes(),
+            &4u32.to_ne_bytes(),
+            &[0; 4],
+            b"data",
+        ];
+        assert_eq!(payload, expected.concat());
+    }
+
+    #[test]
+    fn retrieve() {
+        use crate::fuse_abi::{fuse_in_header, fuse_notify_retrieve_in, fuse_opcode};
+        use crate::reply::as_bytes;
+        use crate::request::{InterruptTracker, Request};
+        use crate::stats::SessionStats;
+        use crate::Filesystem;
+        use std::convert::TryInto;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        struct NullFs;
+
+        impl Filesystem for NullFs {}
+
+        let (ch, driver) = channel("notify-retrieve");
+        let notifier = Notifier::new(ch.sender());
+        let receiver = notifier.retrieve(0x11, 0x22, 0x33).unwrap();
+        let (header, payload) = sent(&driver);
+        assert_eq!(header.error, 5); // FUSE_NOTIFY_RETRIEVE
+        let notify_unique = u64::from_ne_bytes(payload[..8].try_into().unwrap());
+        let expected: [&[u8]; 5] = [
+            &notify_unique.to_ne_bytes(),
+            &0x11u64.to_ne_bytes(),
+            &0x22u64.to_ne_bytes(),
+            &0x33u32.to_ne_bytes(),
+            &[0; 4],
+        ];
+        assert_eq!(payload, expected.concat());
+
+        // The kernel sends the retrieved data as a request with the notification's unique id
+        let mut arg: fuse_notify_retrieve_in = unsafe { mem::zeroed() };
+        arg.offset = 0x22;
+        arg.size = 4;
+        let mut data = as_bytes(
+            &fuse_in_header {
+                len: (mem::size_of::<fuse_in_header>()
+                    + mem::size_of::<fuse_notify_retrieve_in>()
+                    + 4) as u32,
+                opcode: fuse_opcode::FUSE_NOTIFY_REPLY as u32,
+                unique: notify_unique,
+                nodeid: 0x11,
+                uid: 0,
+                gid: 0,
+                pid: 0,
+                padding: 0,
+            },
+            |d| d.concat(),
+        );
+        data.extend(as_bytes(&arg, |d| d.concat()));
+        data.extend_from_slice(b"data");
+        let interrupts = Arc::new(InterruptTracker::default());
+        let req = Request::new(ch.sender(), &data, &interrupts, &SessionStats::default())
+            .unwrap()
+            .unwrap();
+        assert!(req.dispatch_concurrent(&NullFs, &AtomicBool::new(false), &notifier));
+        assert_eq!(receiver.try_recv().unwrap(), b"data");
+        // Retrieve replies aren't replied to
+        assert!(driver.take_sent().is_empty());
+    }
+
+    #[test]
+    fn with_sender() {
+        use std::convert::TryInto;
+
+        // Retrieve replies are delivered to notifiers sending through another sender, too
+        let (ch, driver) = channel("notify-with-sender");
+        let notifier = Notifier::new(ch.sender());
+        let receiver = notifier.retrieve(0x11, 0, 4).unwrap();
+        let notify_unique = u64::from_ne_bytes(sent(&driver).1[..8].try_into().unwrap());
+        let (other_ch, _other_driver) = channel("notify-with-sender-other");
+        let other = notifier.with_sender(other_ch.sender());
+        other.retrieve_reply(notify_unique, b"data");
+        assert_eq!(receiver.try_recv().unwrap(), b"data");
+    }
+
+    #[test]
+    fn old_abi() {
+        // Inode and entry invalidation need ABI 7.12, polling only 7.11
+        let (ch, driver) = channel("notify-old-abi");
+        ch.sender().set_proto_minor(11);
+        let notifier = Notifier::new(ch.sender());
+        let err = notifier.inval_inode(0x11, 0, 0).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSYS));
+        let err = notifier.inval_entry(0x11, OsStr::new("abc")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSYS));
+        assert!(driver.take_sent().is_empty());
+        notifier.poll(0x11).unwrap();
+        assert_eq!(sent(&driver).0.error, 1); // FUSE_POLL
+    }
+}
@@ -0,0 +1,1236 @@
+//! Passthrough filesystem
+//!
+//! `PassthroughFs` mirrors a directory of the host: every operation is passed to the
+//! corresponding file of the underlying directory. Inodes hold an `O_PATH` file descriptor of
+//! their host file and operations use the `*at` system calls relative to it, so renames and
+//! removals in the host directory don't confuse the filesystem. Host files with the same
+//! device and inode number (i.e. hard links) share one inode.
+//!
+//! File and directory handles are file descriptors of the opened host files, they can be used
+//! directly by filesystems that wrap a `PassthroughFs`. Wrapping works by delegation: the
+//! wrapper implements `Filesystem`, handles the operations it's interested in and passes all
+//! other operations on to the `PassthroughFs`. Operations that aren't passed on are answered
+//! with the default implementations of `Filesystem`.
+//!
+//! ```
+//! use fuser::passthrough::PassthroughFs;
+//! use fuser::{Errno, Filesystem, ReplyEmpty, ReplyEntry, Request};
+//! use std::ffi::OsStr;
+//!
+//! /// Passthrough filesystem that doesn't allow removing files
+//! struct NoUnlinkFs(PassthroughFs);
+//!
+//! impl Filesystem for NoUnlinkFs {
+//!     fn lookup(&self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+//!         self.0.lookup(req, parent, name, reply);
+//!     }
+//!
+//!     fn forget(&self, req: &Request<'_>, ino: u64, nlookup: u64) {
+//!         self.0.forget(req, ino, nlookup);
+//!     }
+//!
+//!     fn unlink(&self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+//!         reply.error(Errno::EPERM);
+//!     }
+//!
+//!     // All other operations are delegated likewise
+//! }
+//! ```
+//!
+//! Permissions aren't checked by `PassthroughFs` itself, but by the host filesystem against
+//! the credentials of the filesystem process. Mount with `MountOption::DefaultPermissions` to
+//! let the kernel check them against the credentials of the calling process. If the process
+//! runs as root, files are created with the user and group of the request. Modes of created
+//! files are masked by the kernel already, so the umask of the filesystem process should be 0.
+//!
+//! POSIX locks are passed through as open file description locks (see `F_OFD_SETLK` in
+//! fcntl(2)) on the file handle, so they belong to a file handle rather than a lock owner and
+//! are released when the file handle is flushed. Note that a blocking setlk blocks the thread
+//! handling the request until the lock is acquired. bmap, ioctl, poll and the DAX mapping
+//! operations aren't passed through.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs::File;
+use std::io;
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{mem, ptr};
+
+use crate::fuse_abi::consts::{FUSE_DO_READDIRPLUS, FUSE_POSIX_LOCKS, FUSE_READDIRPLUS_AUTO};
+use crate::inode_table::InodeTable;
+use crate::path_fs::UNKNOWN_INO;
+use crate::reply::kind_from_mode;
+use crate::reply::ReplyDirectoryPlus;
+use crate::reply::ReplyLseek;
+use crate::reply::{ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry};
+use crate::reply::{ReplyLock, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr};
+use crate::request::ForgetOne;
+use crate::request::{Request, WriteFlags};
+use crate::{Errno, FileAttr, FileType, Filesystem, KernelConfig, SplicedData, FUSE_ROOT_ID};
+
+/// Returns the value of a result or replies with its error
+macro_rules! try_reply {
+    ($result:expr, $reply:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(err) => {
+                $reply.error(err);
+                return;
+            }
+        }
+    };
+}
+
+/// Returns the result of a system call, or the error it set
+fn check(ret: c_int) -> Result<c_int, Errno> {
+    if ret < 0 {
+        Err(io::Error::last_os_error().into())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Returns the length returned by a system call, or the error it set
+fn check_len(ret: isize) -> Result<usize, Errno> {
+    if ret < 0 {
+        Err(io::Error::last_os_error().into())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// Returns the name as C string
+fn cstr(name: &OsStr) -> Result<CString, Errno> {
+    CString::new(name.as_bytes()).map_err(|_| Errno::EINVAL)
+}
+
+/// Returns the path of the given file descriptor in /proc, which can be used for the system
+/// calls that don't support `O_PATH` file descriptors
+fn proc_path(fd: RawFd) -> CString {
+    CString::new(format!("/proc/self/fd/{}", fd)).unwrap()
+}
+
+/// Returns the attributes of the file of the given file descriptor, without following symlinks
+fn stat(fd: RawFd) -> Result<libc::stat, Errno> {
+    let mut st = unsafe { mem::zeroed() };
+    let flags = libc::AT_EMPTY_PATH | libc::AT_SYMLINK_NOFOLLOW;
+    check(unsafe { libc::fstatat(fd, b"\0".as_ptr().cast(), &mut st, flags) })?;
+    Ok(st)
+}
+
+/// Open the file with the given name in the given directory
+fn open_at(dir: RawFd, name: &OsStr, flags: c_int, mode: u32) -> Result<File, Errno> {
+    let name = cstr(name)?;
+    let fd = check(unsafe { libc::openat(dir, name.as_ptr(), flags | libc::O_CLOEXEC, mode) })?;
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn system_time(secs: i64, nsecs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nsecs as u32)
+    } else {
+        UNIX_EPOCH - Duration::new(secs.unsigned_abs(), 0) + Duration::from_nanos(nsecs as u64)
+    }
+}
+
+#[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+/// Returns the attributes of a host file for the given inode number
+fn file_attr(ino: u64, st: &libc::stat) -> FileAttr {
+    FileAttr {
+        ino,
+        size: st.st_size as u64,
+        blocks: st.st_blocks as u64,
+        atime: system_time(st.st_atime as i64, st.st_atime_nsec as i64),
+        mtime: system_time(st.st_mtime as i64, st.st_mtime_nsec as i64),
+        ctime: system_time(st.st_ctime as i64, st.st_ctime_nsec as i64),
+        crtime: UNIX_EPOCH,
+        kind: kind_from_mode(st.st_mode as u32),
+        perm: (st.st_mode & 0o7777) as u16,
+        nlink: st.st_nlink as u32,
+        uid: st.st_uid,
+        gid: st.st_gid,
+        rdev: st.st_rdev as u32,
+        flags: 0,
+        blksize: st.st_blksize as u32,
+        padding: 0,
+    }
+}
+
+/// Returns the timespec for setting a time with utimensat
+fn timespec(time: Option<SystemTime>, now: bool) -> libc::timespec {
+    let (tv_sec, tv_nsec) = match time {
+        _ if now => (0, libc::UTIME_NOW),
+        None => (0, libc::UTIME_OMIT),
+        Some(time) => match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => (
+                d.as_secs() as libc::time_t,
+                d.subsec_nanos() as libc::c_long,
+            ),
+            Err(err) => {
+                // Before the epoch, the nanoseconds must be positive nevertheless
+                let d = err.duration();
+                match d.subsec_nanos() {
+                    0 => (-(d.as_secs() as libc::time_t), 0),
+                    nsecs => (
+                        -(d.as_secs() as libc::time_t) - 1,
+                        (1_000_000_000 - nsecs) as libc::c_long,
+                    ),
+                }
+            }
+        },
+    };
+    libc::timespec { tv_sec, tv_nsec }
+}
+
+/// Returns the offset and length of a lock for the given range. The kernel marks locks up to
+/// the end of the file with an end of OFFSET_MAX, fcntl with a length of zero.
+fn lock_range(start: u64, end: u64) -> (libc::off_t, libc::off_t) {
+    if end >= i64::MAX as u64 {
+        (start as libc::off_t, 0)
+    } else {
+        (start as libc::off_t, (end - start + 1) as libc::off_t)
+    }
+}
+
+/// Switches the filesystem credentials of the current thread to the ones of a request, for
+/// creating files that belong to the requesting user. Switches back to root when dropped.
+struct Credentials;
+
+impl Credentials {
+    /// Switch to the credentials of the request if the process runs as root
+    fn switch(req: &Request<'_>) -> Option<Credentials> {
+        if unsafe { libc::geteuid() } != 0 {
+            return None;
+        }
+        unsafe {
+            libc::setfsgid(req.gid());
+            libc::setfsuid(req.uid());
+        }
+        Some(Credentials)
+    }
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        unsafe {
+            libc::setfsuid(0);
+            libc::setfsgid(0);
+        }
+    }
+}
+
+/// Number of entries readdir reads from the host directory at once
+const READDIR_BATCH: usize = 128;
+
+/// An entry read from a host directory
+struct HostDirEntry<'a> {
+    ino: u64,
+    offset: i64,
+    kind: FileType,
+    name: &'a OsStr,
+}
+
+/// Read the entries of the open directory after the given offset, until add returns true
+fn read_dir<F>(fd: RawFd, offset: i64, mut add: F) -> Result<(), Errno>
+where
+    F: FnMut(HostDirEntry<'_>) -> bool,
+{
+    check_len(unsafe { libc::lseek(fd, offset, libc::SEEK_SET) } as isize)?;
+    let mut buf = vec![0u8; 8192];
+    loop {
+        let ret = unsafe { libc::syscall(libc::SYS_getdents64, fd, buf.as_mut_ptr(), buf.len()) };
+        let len = check_len(ret as isize)?;
+        if len == 0 {
+            return Ok(());
+        }
+        // struct linux_dirent64 { d_ino: u64, d_off: i64, d_reclen: u16, d_type: u8, d_name }
+        let mut data = &buf[..len];
+        while !data.is_empty() {
+            let mut ino = [0; 8];
+            ino.copy_from_slice(&data[0..8]);
+            let mut off = [0; 8];
+            off.copy_from_slice(&data[8..16]);
+            let reclen = u16::from_ne_bytes([data[16], data[17]]) as usize;
+            let name = &data[19..reclen];
+            let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            let entry = HostDirEntry {
+                ino: u64::from_ne_bytes(ino),
+                offset: i64::from_ne_bytes(off),
+                kind: kind_from_mode(u32::from(data[18]) << 12),
+                name: OsStr::from_bytes(&name[..name_len]),
+            };
+            if add(entry) {
+                return Ok(());
+            }
+            data = &data[reclen..];
+        }
+    }
+}
+
+/// An inode of the filesystem, with an `O_PATH` file descriptor of its host file
+#[derive(Debug)]
+struct Inode {
+    file: File,
+    dev: u64,
+    ino: u64,
+}
+
+impl Inode {
+    fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Inodes known to the kernel and their host device and inode numbers
+#[derive(Debug)]
+struct Inodes {
+    table: InodeTable<Arc<Inode>>,
+    ids: HashMap<(u64, u64), u64>,
+}
+
+impl Inodes {
+    /// Returns the inode for the given host file, adding it if necessary, and increments its
+    /// lookup count. Returns the inode number and its generation.
+    #[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+    fn lookup(&mut self, file: File, st: &libc::stat) -> (u64, u64) {
+        let id = (st.st_dev as u64, st.st_ino as u64);
+        let ino = match self.ids.get(&id) {
+            Some(&ino) => ino,
+            None => {
+                let inode = Inode {
+                    file,
+                    dev: id.0,
+                    ino: id.1,
+                };
+                let (ino, _) = self.table.insert(Arc::new(inode));
+                self.ids.insert(id, ino);
+                ino
+            }
+        };
+        (ino, self.table.lookup(ino).unwrap())
+    }
+
+    fn forget(&mut self, ino: u64, nlookup: u64) {
+        if let Some(inode) = self.table.forget(ino, nlookup) {
+            let id = (inode.dev, inode.ino);
+            if self.ids.get(&id) == Some(&ino) {
+                self.ids.remove(&id);
+            }
+        }
+    }
+}
+
+/// Filesystem that passes all operations to the files of a host directory
+#[derive(Debug)]
+pub struct PassthroughFs {
+    root: PathBuf,
+    inodes: Mutex<Inodes>,
+    ttl: Duration,
+}
+
+impl PassthroughFs {
+    /// Create a filesystem that mirrors the given host directory
+    #[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<PassthroughFs> {
+        let root = root.as_ref().to_path_buf();
+        let path = CString::new(root.as_os_str().as_bytes())?;
+        let flags = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let fd = unsafe { libc::open(path.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        let st = stat(file.as_raw_fd()).map_err(|err| io::Error::from_raw_os_error(err.code()))?;
+        let id = (st.st_dev as u64, st.st_ino as u64);
+        let inode = Inode {
+            file,
+            dev: id.0,
+            ino: id.1,
+        };
+        let mut ids = HashMap::new();
+        ids.insert(id, FUSE_ROOT_ID);
+        Ok(PassthroughFs {
+            root,
+            inodes: Mutex::new(Inodes {
+                table: InodeTable::new(Arc::new(inode)),
+                ids,
+            }),
+            ttl: Duration::from_secs(1),
+        })
+    }
+
+    /// Returns the host directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Set the time the kernel may cache entries and attributes (1 second by default). Files
+    /// changed in the host directory by others may be seen that much later.
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    /// Open the host file of the given inode with the given open flags
+    pub fn open_inode(&self, ino: u64, flags: i32) -> Result<File, Errno> {
+        let inode = self.inode(ino)?;
+        let path = proc_path(inode.fd());
+        let flags = (flags & !libc::O_NOFOLLOW) | libc::O_CLOEXEC;
+        let fd = check(unsafe { libc::open(path.as_ptr(), flags) })?;
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    fn inode(&self, ino: u64) -> Result<Arc<Inode>, Errno> {
+        let inodes = self.inodes.lock().unwrap();
+        inodes.table.get(ino).cloned().ok_or(Errno::ESTALE)
+    }
+
+    /// Returns the attributes of the given inode
+    fn attr(&self, ino: u64) -> Result<FileAttr, Errno> {
+        let inode = self.inode(ino)?;
+        Ok(file_attr(ino, &stat(inode.fd())?))
+    }
+
+    /// Look up a host file and increment its lookup count. Returns its attributes and the
+    /// generation of its inode.
+    fn lookup_entry(&self, parent: u64, name: &OsStr) -> Result<(FileAttr, u64), Errno> {
+        // Don't let the kernel look up the parent of the host directory
+        let name = match name.to_str() {
+            Some("..") if parent == FUSE_ROOT_ID => OsStr::new("."),
+            _ => name,
+        };
+        let dir = self.inode(parent)?;
+        let file = open_at(dir.fd(), name, libc::O_PATH | libc::O_NOFOLLOW, 0)?;
+        let st = stat(file.as_raw_fd())?;
+        let (ino, generation) = self.inodes.lock().unwrap().lookup(file, &st);
+        Ok((file_attr(ino, &st), generation))
+    }
+
+    /// Reply with the entry of a host file, incrementing its lookup count
+    fn entry(&self, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let (attr, generation) = try_reply!(self.lookup_entry(parent, name), reply);
+        reply.entry(&self.ttl, &attr, generation);
+    }
+
+    /// Reply with the entry of a host file that was created with the given function
+    fn create_entry<F>(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        create: F,
+        reply: ReplyEntry,
+    ) where
+        F: FnOnce(RawFd, &CString) -> c_int,
+    {
+        let dir = try_reply!(self.inode(parent), reply);
+        let cname = try_reply!(cstr(name), reply);
+        let credentials = Credentials::switch(req);
+        try_reply!(check(create(dir.fd(), &cname)), reply);
+        drop(credentials);
+        self.entry(parent, name, reply);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn setattr_inner(
+        &self,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: libc::timespec,
+        mtime: libc::timespec,
+        fh: Option<u64>,
+    ) -> Result<FileAttr, Errno> {
+        let inode = self.inode(ino)?;
+        let path = proc_path(inode.fd());
+        if let Some(mode) = mode {
+            let mode = mode as libc::mode_t;
+            check(match fh {
+                Some(fh) => unsafe { libc::fchmod(fh as RawFd, mode) },
+                None => unsafe { libc::chmod(path.as_ptr(), mode) },
+            })?;
+        }
+        if uid.is_some() || gid.is_some() {
+            let flags = libc::AT_EMPTY_PATH | libc::AT_SYMLINK_NOFOLLOW;
+            // An id of -1 is left unchanged
+            let uid = uid.unwrap_or(u32::MAX);
+            let gid = gid.unwrap_or(u32::MAX);
+            check(unsafe { libc::fchownat(inode.fd(), b"\0".as_ptr().cast(), uid, gid, flags) })?;
+        }
+        if let Some(size) = size {
+            let size = size as libc::off_t;
+            check(match fh {
+                Some(fh) => unsafe { libc::ftruncate(fh as RawFd, size) },
+                None => unsafe { libc::truncate(path.as_ptr(), size) },
+            })?;
+        }
+        if atime.tv_nsec != libc::UTIME_OMIT || mtime.tv_nsec != libc::UTIME_OMIT {
+            let times = [atime, mtime];
+            check(match fh {
+                Some(fh) => unsafe { libc::futimens(fh as RawFd, times.as_ptr()) },
+                None => unsafe {
+                    libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), 0)
+                },
+            })?;
+        }
+        Ok(file_attr(ino, &stat(inode.fd())?))
+    }
+
+    fn getxattr_inner(&self, ino: u64, name: Option<&OsStr>, size: u32, reply: ReplyXattr) {
+        let inode = try_reply!(self.inode(ino), reply);
+        let path = proc_path(inode.fd());
+        let name = match name {
+            Some(name) => Some(try_reply!(cstr(name), reply)),
+            None => None,
+        };
+        let mut buf = vec![0u8; size as usize];
+        let ret = unsafe {
+            let value = if size == 0 {
+                ptr::null_mut()
+            } else {
+                buf.as_mut_ptr().cast()
+            };
+            match &name {
+                Some(name) => libc::getxattr(path.as_ptr(), name.as_ptr(), value, buf.len()),
+                None => libc::listxattr(path.as_ptr(), value.cast(), buf.len()),
+            }
+        };
+        let len = try_reply!(check_len(ret), reply);
+        if size == 0 {
+            reply.size(len as u32);
+        } else {
+            reply.data(&buf[..len]);
+        }
+    }
+}
+
+impl Filesystem for PassthroughFs {
+    fn init(&self, _req: &Request<'_>, config: &mut KernelConfig) -> Result<(), Errno> {
+        // Without remote locking, the kernel handles locks itself
+        let _ = config.add_capabilities(FUSE_POSIX_LOCKS);
+        // Let the kernel decide when to read directories with attributes
+        let _ = config.add_capabilities(FUSE_DO_READDIRPLUS | FUSE_READDIRPLUS_AUTO);
+        Ok(())
+    }
+
+    fn lookup(&self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        self.entry(parent, name, reply);
+    }
+
+    fn forget(&self, _req: &Request<'_>, ino: u64, nlookup: u64) {
+        self.inodes.lock().unwrap().forget(ino, nlookup);
+    }
+
+    fn batch_forget(&self, _req: &Request<'_>, nodes: &[ForgetOne]) {
+        let mut inodes = self.inodes.lock().unwrap();
+        for node in nodes {
+            inodes.forget(node.ino(), node.nlookup());
+        }
+    }
+
+    fn getattr(&self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        let attr = try_reply!(self.attr(ino), reply);
+        reply.attr(&self.ttl, &attr);
+    }
+
+    fn setattr(
+        &self,
+        _req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<SystemTime>,
+        atime_now: bool,
+        mtime: Option<SystemTime>,
+        mtime_now: bool,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let atime = timespec(atime, atime_now);
+        let mtime = timespec(mtime, mtime_now);
+        let result = self.setattr_inner(ino, mode, uid, gid, size, atime, mtime, fh);
+        let attr = try_reply!(result, reply);
+        reply.attr(&self.ttl, &attr);
+    }
+
+    fn readlink(&self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        let inode = try_reply!(self.inode(ino), reply);
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        let ret = unsafe {
+            let path = b"\0".as_ptr().cast();
+            libc::readlinkat(inode.fd(), path, buf.as_mut_ptr().cast(), buf.len())
+        };
+        let len = try_reply!(check_len(ret), reply);
+        reply.data(&buf[..len]);
+    }
+
+    fn mknod(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let create = |dir, name: &CString| unsafe {
+            libc::mknodat(dir, name.as_ptr(), mode as libc::mode_t, rdev.into())
+        };
+        self.create_entry(req, parent, name, create, reply);
+    }
+
+    fn mkdir(&self, req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
+        let create = |dir, name: &CString| unsafe {
+            libc::mkdirat(dir, name.as_ptr(), mode as libc::mode_t)
+        };
+        self.create_entry(req, parent, name, create, reply);
+    }
+
+    fn unlink(&self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let dir = try_reply!(self.inode(parent), reply);
+        let name = try_reply!(cstr(name), reply);
+        try_reply!(
+            check(unsafe { libc::unlinkat(dir.fd(), name.as_ptr(), 0) }),
+            reply
+        );
+        reply.ok();
+    }
+
+    fn rmdir(&self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let dir = try_reply!(self.inode(parent), reply);
+        let name = try_reply!(cstr(name), reply);
+        try_reply!(
+            check(unsafe { libc::unlinkat(dir.fd(), name.as_ptr(), libc::AT_REMOVEDIR) }),
+            reply
+        );
+        reply.ok();
+    }
+
+    fn symlink(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        link: &Path,
+        reply: ReplyEntry,
+    ) {
+        let link = try_reply!(cstr(link.as_os_str()), reply);
+        let create =
+            |dir, name: &CString| unsafe { libc::symlinkat(link.as_ptr(), dir, name.as_ptr()) };
+        self.create_entry(req, parent, name, create, reply);
+    }
+
+    fn rename(
+        &self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let dir = try_reply!(self.inode(parent), reply);
+        let newdir = try_reply!(self.inode(newparent), reply);
+        let name = try_reply!(cstr(name), reply);
+        let newname = try_reply!(cstr(newname), reply);
+        let ret = unsafe {
+            if flags == 0 {
+                libc::renameat(dir.fd(), name.as_ptr(), newdir.fd(), newname.as_ptr())
+            } else {
+                libc::syscall(
+                    libc::SYS_renameat2,
+                    dir.fd(),
+                    name.as_ptr(),
+                    newdir.fd(),
+                    newname.as_ptr(),
+                    flags,
+                ) as c_int
+            }
+        };
+        try_reply!(check(ret), reply);
+        reply.ok();
+    }
+
+    fn link(
+        &self,
+        _req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let inode = try_reply!(self.inode(ino), reply);
+        let newdir = try_reply!(self.inode(newparent), reply);
+        let name = try_reply!(cstr(newname), reply);
+        // Linking an O_PATH file descriptor with AT_EMPTY_PATH requires CAP_DAC_READ_SEARCH,
+        // its path in /proc doesn't
+        let path = proc_path(inode.fd());
+        let ret = unsafe {
+            libc::linkat(
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                newdir.fd(),
+                name.as_ptr(),
+                libc::AT_SYMLINK_FOLLOW,
+            )
+        };
+        try_reply!(check(ret), reply);
+        self.entry(newparent, newname, reply);
+    }
+
+    fn open(&self, _req: &Request<'_>, ino: u64, flags: u32, reply: ReplyOpen) {
+        let file = try_reply!(self.open_inode(ino, flags as i32), reply);
+        reply.opened(file.into_raw_fd() as u64, 0);
+    }
+
+    fn read(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        reply: ReplyData,
+    ) {
+        reply.splice_from_fd(fh as RawFd, offset, size as usize);
+    }
+
+    fn write(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: WriteFlags,
+        _flags: u32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let ret = unsafe { libc::pwrite(fh as RawFd, data.as_ptr().cast(), data.len(), offset) };
+        let len = try_reply!(check_len(ret), reply);
+        reply.written(len as u32);
+    }
+
+    fn write_spliced(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        mut data: SplicedData<'_>,
+        _write_flags: WriteFlags,
+        _flags: u32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let result = data
+            .splice_to(fh as RawFd, Some(offset))
+            .map_err(Errno::from);
+        let len = try_reply!(result, reply);
+        reply.written(len as u32);
+    }
+
+    fn flush(&self, _req: &Request<'_>, _ino: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        // POSIX locks are released when their owner closes the file, the closest equivalent
+        // of that is releasing the locks of the file handle
+        let mut lock: libc::flock = unsafe { mem::zeroed() };
+        lock.l_type = libc::F_UNLCK as libc::c_short;
+        lock.l_whence = libc::SEEK_SET as libc::c_short;
+        try_reply!(
+            check(unsafe { libc::fcntl(fh as RawFd, libc::F_OFD_SETLK, &lock) }),
+            reply
+        );
+        // Closing a duplicate reports delayed write errors, like closing the file would
+        let fd = try_reply!(check(unsafe { libc::dup(fh as RawFd) }), reply);
+        try_reply!(check(unsafe { libc::close(fd) }), reply);
+        reply.ok();
+    }
+
+    fn release(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        drop(unsafe { File::from_raw_fd(fh as RawFd) });
+        reply.ok();
+    }
+
+    fn fsync(&self, _req: &Request<'_>, _ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        let ret = match datasync {
+            true => unsafe { libc::fdatasync(fh as RawFd) },
+            false => unsafe { libc::fsync(fh as RawFd) },
+        };
+        try_reply!(check(ret), reply);
+        reply.ok();
+    }
+
+    fn opendir(&self, _req: &Request<'_>, ino: u64, flags: u32, reply: ReplyOpen) {
+        let flags = flags as i32 | libc::O_DIRECTORY;
+        let file = try_reply!(self.open_inode(ino, flags), reply);
+        reply.opened(file.into_raw_fd() as u64, 0);
+    }
+
+    fn readdir(
+        &self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let dev = try_reply!(self.inode(ino), reply).dev;
+        // The entries are read before taking the lock of the inodes, which is only held to
+        // map their inode numbers
+        let mut offset = offset;
+        loop {
+            let mut entries = Vec::with_capacity(READDIR_BATCH);
+            let result = read_dir(fh as RawFd, offset, |entry| {
+                let name = entry.name.to_os_string();
+                entries.push((entry.ino, entry.offset, entry.kind, name));
+                entries.len() == READDIR_BATCH
+            });
+            try_reply!(result, reply);
+            let inodes = self.inodes.lock().unwrap();
+            let full = entries.iter().any(|(host_ino, offset, kind, name)| {
+                // Entries are reported with the inode numbers the kernel knows them by, mount
+                // points (of other devices) aren't found this way
+                let ino = inodes.ids.get(&(dev, *host_ino)).copied();
+                reply.add(ino.unwrap_or(UNKNOWN_INO), *offset, *kind, name)
+            });
+            drop(inodes);
+            match entries.last() {
+                Some(entry) if !full && entries.len() == READDIR_BATCH => offset = entry.1,
+                _ => break,
+            }
+        }
+        reply.ok();
+    }
+
+    fn readdirplus(
+        &self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectoryPlus,
+    ) {
+        let result = read_dir(fh as RawFd, offset, |entry| {
+            match entry.name.as_bytes() {
+                // The kernel doesn't look up "." and ".." when they're added
+                b"." | b".." => {
+                    let mut attr = match self.attr(ino) {
+                        Ok(attr) => attr,
+                        Err(_) => return false,
+                    };
+                    attr.ino = UNKNOWN_INO;
+                    reply.add(UNKNOWN_INO, entry.offset, entry.name, &self.ttl, &attr, 0)
+                }
+                _ => {
+                    let (attr, generation) = match self.lookup_entry(ino, entry.name) {
+                        Ok(entry) => entry,
+                        Err(_) => return false,
+                    };
+                    let full = reply.add(
+                        attr.ino,
+                        entry.offset,
+                        entry.name,
+                        &self.ttl,
+                        &attr,
+                        generation,
+                    );
+                    if full {
+                        // The entry wasn't sent, so the kernel doesn't count the lookup
+                        self.inodes.lock().unwrap().forget(attr.ino, 1);
+                    }
+                    full
+                }
+            }
+        });
+        try_reply!(result, reply);
+        reply.ok();
+    }
+
+    fn releasedir(&self, _req: &Request<'_>, _ino: u64, fh: u64, _flags: u32, reply: ReplyEmpty) {
+        drop(unsafe { File::from_raw_fd(fh as RawFd) });
+        reply.ok();
+    }
+
+    fn fsyncdir(&self, req: &Request<'_>, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        self.fsync(req, ino, fh, datasync, reply);
+    }
+
+    #[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+    fn statfs(&self, _req: &Request<'_>, ino: u64, reply: ReplyStatfs) {
+        let inode = try_reply!(self.inode(ino), reply);
+        let mut st: libc::statvfs = unsafe { mem::zeroed() };
+        try_reply!(check(unsafe { libc::fstatvfs(inode.fd(), &mut st) }), reply);
+        reply.statfs(
+            st.f_blocks as u64,
+            st.f_bfree as u64,
+            st.f_bavail as u64,
+            st.f_files as u64,
+            st.f_ffree as u64,
+            st.f_bsize as u32,
+            st.f_namemax as u32,
+            st.f_frsize as u32,
+        );
+    }
+
+    fn setxattr(
+        &self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: u32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        let inode = try_reply!(self.inode(ino), reply);
+        let path = proc_path(inode.fd());
+        let name = try_reply!(cstr(name), reply);
+        let ret = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                flags as c_int,
+            )
+        };
+        try_reply!(check(ret), reply);
+        reply.ok();
+    }
+
+    fn getxattr(&self, _req: &Request<'_>, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        self.getxattr_inner(ino, Some(name), size, reply);
+    }
+
+    fn listxattr(&self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        self.getxattr_inner(ino, None, size, reply);
+    }
+
+    fn removexattr(&self, _req: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        let inode = try_reply!(self.inode(ino), reply);
+        let path = proc_path(inode.fd());
+        let name = try_reply!(cstr(name), reply);
+        try_reply!(
+            check(unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) }),
+            reply
+        );
+        reply.ok();
+    }
+
+    fn access(&self, _req: &Request<'_>, ino: u64, mask: u32, reply: ReplyEmpty) {
+        let inode = try_reply!(self.inode(ino), reply);
+        let path = proc_path(inode.fd());
+        let ret = unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), mask as c_int, 0) };
+        try_reply!(check(ret), reply);
+        reply.ok();
+    }
+
+    fn create(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+        reply: ReplyCreate,
+    ) {
+        let dir = try_reply!(self.inode(parent), reply);
+        let flags = (flags as i32 | libc::O_CREAT) & !libc::O_NOFOLLOW;
+        let credentials = Credentials::switch(req);
+        let file = try_reply!(open_at(dir.fd(), name, flags, mode), reply);
+        drop(credentials);
+        let (attr, generation) = try_reply!(self.lookup_entry(parent, name), reply);
+        let fh = file.into_raw_fd() as u64;
+        reply.created(&self.ttl, &attr, generation, fh, 0);
+    }
+
+    fn getlk(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: u32,
+        _pid: u32,
+        reply: ReplyLock,
+    ) {
+        let (l_start, l_len) = lock_range(start, end);
+        let mut lock: libc::flock = unsafe { mem::zeroed() };
+        lock.l_type = typ as libc::c_short;
+        lock.l_whence = libc::SEEK_SET as libc::c_short;
+        lock.l_start = l_start;
+        lock.l_len = l_len;
+        let ret = unsafe { libc::fcntl(fh as RawFd, libc::F_OFD_GETLK, &mut lock) };
+        try_reply!(check(ret), reply);
+        let end = match lock.l_len {
+            0 => i64::MAX as u64,
+            len => (lock.l_start + len - 1) as u64,
+        };
+        // The pid of open file description locks is unknown
+        reply.locked(lock.l_start as u64, end, lock.l_type as u32, 0);
+    }
+
+    fn setlk(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: u32,
+        _pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        let (l_start, l_len) = lock_range(start, end);
+        let mut lock: libc::flock = unsafe { mem::zeroed() };
+        lock.l_type = typ as libc::c_short;
+        lock.l_whence = libc::SEEK_SET as libc::c_short;
+        lock.l_start = l_start;
+        lock.l_len = l_len;
+        let cmd = match sleep {
+            true => libc::F_OFD_SETLKW,
+            false => libc::F_OFD_SETLK,
+        };
+        try_reply!(
+            check(unsafe { libc::fcntl(fh as RawFd, cmd, &lock) }),
+            reply
+        );
+        reply.ok();
+    }
+
+    fn fallocate(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        try_reply!(
+            check(unsafe { libc::fallocate(fh as RawFd, mode, offset, length) }),
+            reply
+        );
+        reply.ok();
+    }
+
+    fn lseek(
+        &self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        let ret = unsafe { libc::lseek(fh as RawFd, offset, whence) };
+        let offset = try_reply!(check_len(ret as isize), reply);
+        reply.offset(offset as i64);
+    }
+
+    fn copy_file_range(
+        &self,
+        _req: &Request<'_>,
+        _ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        _ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let mut offset_in = offset_in;
+        let mut offset_out = offset_out;
+        let ret = unsafe {
+            libc::copy_file_range(
+                fh_in as RawFd,
+                &mut offset_in,
+                fh_out as RawFd,
+                &mut offset_out,
+                len as usize,
+                flags,
+            )
+        };
+        let len = try_reply!(check_len(ret), reply);
+        reply.written(len as u32);
+    }
+
+    fn syncfs(&self, _req: &Request<'_>, ino: u64, reply: ReplyEmpty) {
+        // syncfs doesn't accept O_PATH file descriptors
+        let file = try_reply!(self.open_inode(ino, libc::O_RDONLY), reply);
+        try_reply!(check(unsafe { libc::syncfs(file.as_raw_fd()) }), reply);
+        reply.ok();
+    }
+
+    fn tmpfile(&self, req: &Request<'_>, parent: u64, mode: u32, flags: u32, reply: ReplyCreate) {
+        let dir = try_reply!(self.inode(parent), reply);
+        let flags = flags as i32 | libc::O_TMPFILE;
+        let credentials = Credentials::switch(req);
+        let file = try_reply!(open_at(dir.fd(), OsStr::new("."), flags, mode), reply);
+        drop(credentials);
+        // The unnamed file can only be reached through its path in /proc
+        let path = proc_path(file.as_raw_fd());
+        let flags = libc::O_PATH | libc::O_CLOEXEC;
+        let fd = try_reply!(check(unsafe { libc::open(path.as_ptr(), flags) }), reply);
+        let inode_file = unsafe { File::from_raw_fd(fd) };
+        let st = try_reply!(stat(fd), reply);
+        let (ino, generation) = self.inodes.lock().unwrap().lookup(inode_file, &st);
+        let fh = file.into_raw_fd() as u64;
+        reply.created(&self.ttl, &file_attr(ino, &st), generation, fh, 0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PassthroughFs;
+    use crate::test_util::temp_dir;
+    use crate::testing::FakeKernel;
+    use crate::{Errno, FileType, FUSE_ROOT_ID};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn kernel(root: &PathBuf) -> FakeKernel<PassthroughFs> {
+        let mut kernel = FakeKernel::new(PassthroughFs::new(root).unwrap());
+        kernel.init(0).unwrap();
+        kernel
+    }
+
+    #[test]
+    fn read_write() {
+        let root = temp_dir("passthrough-io");
+        fs::write(root.join("a"), b"hello").unwrap();
+        let mut kernel = kernel(&root);
+        let a = kernel.lookup(FUSE_ROOT_ID, "a").unwrap();
+        assert_eq!(a.attr.kind, FileType::RegularFile);
+        assert_eq!(a.attr.size, 5);
+        let fh = kernel.open(a.ino, libc::O_RDWR as u32).unwrap().fh;
+        assert_eq!(kernel.read(a.ino, fh, 1, 10).unwrap(), b"ello");
+        assert_eq!(kernel.write(a.ino, fh, 5, b" world").unwrap(), 6);
+        kernel.release(a.ino, fh).unwrap();
+        assert_eq!(kernel.getattr(a.ino).unwrap().attr.size, 11);
+        assert_eq!(fs::read(root.join("a")).unwrap(), b"hello world");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn lookup_count() {
+        let root = temp_dir("passthrough-lookup");
+        fs::write(root.join("a"), b"").unwrap();
+        fs::hard_link(root.join("a"), root.join("b")).unwrap();
+        let mut kernel = kernel(&root);
+        let a = kernel.lookup(FUSE_ROOT_ID, "a").unwrap();
+        assert_ne!(a.ino, FUSE_ROOT_ID);
+        // Hard links share the inode
+        let b = kernel.lookup(FUSE_ROOT_ID, "b").unwrap();
+        assert_eq!(b.ino, a.ino);
+        assert_eq!(b.attr.nlink, 2);
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "c"), Err(Errno::ENOENT));
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "..").unwrap().ino, FUSE_ROOT_ID);
+        kernel.forget(a.ino, 1);
+        assert!(kernel.getattr(a.ino).is_ok());
+        kernel.forget(a.ino, 1);
+        assert_eq!(kernel.getattr(a.ino).unwrap_err(), Errno::ESTALE);
+        // The inode number is reused with a new generation
+        let a2 = kernel.lookup(FUSE_ROOT_ID, "a").unwrap();
+        assert_eq!(a2.ino, a.ino);
+        assert_eq!(a2.generation, a.generation + 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn directories() {
+        let root = temp_dir("passthrough-dir");
+        fs::write(root.join("a"), b"").unwrap();
+        let mut kernel = kernel(&root);
+        let dir = kernel.mkdir(FUSE_ROOT_ID, "dir", 0o755).unwrap();
+        assert_eq!(dir.attr.kind, FileType::Directory);
+        assert!(root.join("dir").is_dir());
+        let fh = kernel.opendir(FUSE_ROOT_ID).unwrap().fh;
+        let entries = kernel.readdir(FUSE_ROOT_ID, fh, 0, 4096).unwrap();
+        let mut names: Vec<_> = entries
+            .iter()
+            .map(|e| (e.name.to_str().unwrap(), e.kind, e.ino))
+            .collect();
+        names.sort_by_key(|e| e.0);
+        assert_eq!(
+            names,
+            [
+                (".", FileType::Directory, FUSE_ROOT_ID),
+                ("..", FileType::Directory, super::UNKNOWN_INO),
+                ("a", FileType::RegularFile, super::UNKNOWN_INO),
+                ("dir", FileType::Directory, dir.ino),
+            ]
+        );
+        // Reading from the offset of an entry continues after it
+        let rest = kernel
+            .readdir(FUSE_ROOT_ID, fh, entries[1].offset, 4096)
+            .unwrap();
+        assert_eq!(rest, entries[2..]);
+        kernel.releasedir(FUSE_ROOT_ID, fh).unwrap();
+        kernel.unlink(FUSE_ROOT_ID, "a").unwrap();
+        assert!(!root.join("a").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn large_directory() {
+        let root = temp_dir("passthrough-large-dir");
+        for i in 0..300 {
+            fs::write(root.join(format!("file{}", i)), b"").unwrap();
+        }
+        let mut kernel = kernel(&root);
+        let ino = kernel.lookup(FUSE_ROOT_ID, "file200").unwrap().ino;
+        let fh = kernel.opendir(FUSE_ROOT_ID).unwrap().fh;
+        // Every reply holds more entries than readdir reads from the host directory at once,
+        // but not all of them
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        let mut parts = 0;
+        loop {
+            let part = kernel.readdir(FUSE_ROOT_ID, fh, offset, 6000).unwrap();
+            match part.last() {
+                Some(entry) => offset = entry.offset,
+                None => break,
+            }
+            parts += 1;
+            entries.extend(part);
+        }
+        kernel.releasedir(FUSE_ROOT_ID, fh).unwrap();
+        assert_eq!(parts, 2);
+        assert_eq!(entries.len(), 302);
+        let known: Vec<_> = entries.iter().filter(|e| e.ino == ino).collect();
+        assert_eq!(known.len(), 1);
+        assert_eq!(known[0].name, "file200");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,1250 @@
+//! Path-based filesystem API
+//!
+//! `PathFilesystem` is an alternative to the inode-based `Filesystem` trait, similar to the
+//! high-level API of libfuse (`fuse_operations`): every operation gets the path of the file
+//! it operates on and returns its result. `PathAdapter` implements `Filesystem` on top of a
+//! `PathFilesystem`. It assigns inode numbers to paths, keeps track of the kernel's lookup
+//! counts in an `InodeTable` and frees inode numbers when the kernel forgets them. Freed inode
+//! numbers are reused with a new generation.
+//!
+//! Files keep their inode number when they're renamed, so operations on open files get the
+//! new path after a rename. Like libfuse, an open file that is unlinked or replaced by a
+//! rename is renamed to a hidden name (`.fuse_hidden*`) in its directory instead, so that
+//! operations on it still get a path of the file. The hidden file is unlinked when its last
+//! file handle is released. Other operations on inodes that were unlinked or replaced fail
+//! with `ESTALE`.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::warn;
+
+use crate::fuse_abi::consts::{FUSE_DO_READDIRPLUS, FUSE_READDIRPLUS_AUTO};
+use crate::inode_table::InodeTable;
+use crate::reply::ReplyDirectoryPlus;
+use crate::reply::{ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry};
+use crate::reply::{ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr};
+use crate::request::ForgetOne;
+use crate::request::{Request, WriteFlags};
+use crate::{Errno, FileType, Filesystem, KernelConfig, FUSE_ROOT_ID};
+
+pub use crate::fs_types::{AttrOut, OpenOut, SetAttrIn, StatfsOut, XattrOut};
+
+/// Inode number of directory entries that the kernel didn't look up yet, as used by libfuse
+pub(crate) const UNKNOWN_INO: u64 = 0xffff_ffff;
+
+/// An entry returned by readdir
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    /// Name of the entry
+    pub name: OsString,
+    /// Kind of the entry
+    pub kind: FileType,
+}
+
+/// Path-based filesystem trait. Paths are absolute, the root directory of the filesystem
+/// is "/". Methods that look up or create a file return its attributes, the inode number
+/// in them is ignored and replaced by the one assigned by `PathAdapter`. Reasonable default
+/// implementations are provided, like for `Filesystem`.
+#[allow(clippy::too_many_arguments)]
+pub trait PathFilesystem {
+    /// Initialize filesystem (see `Filesystem::init`)
+    fn init(&self, _req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    /// Clean up filesystem
+    fn destroy(&self, _req: &Request<'_>) {}
+
+    /// Get file attributes. Also used to look up directory entries. fh is set if the
+    /// attributes are requested for an open file (e.g. fstat).
+    fn getattr(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _fh: Option<u64>,
+    ) -> Result<AttrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Set file attributes
+    fn setattr(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _attr: SetAttrIn,
+    ) -> Result<AttrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Read symbolic link
+    fn readlink(&self, _req: &Request<'_>, _path: &Path) -> Result<Vec<u8>, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Create file node
+    fn mknod(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _mode: u32,
+        _rdev: u32,
+    ) -> Result<AttrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Create a directory
+    fn mkdir(&self, _req: &Request<'_>, _path: &Path, _mode: u32) -> Result<AttrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Remove a file
+    fn unlink(&self, _req: &Request<'_>, _path: &Path) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Remove a directory
+    fn rmdir(&self, _req: &Request<'_>, _path: &Path) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Create a symbolic link at path, pointing to target
+    fn symlink(&self, _req: &Request<'_>, _path: &Path, _target: &Path) -> Result<AttrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Rename a file (see `Filesystem::rename` for the flags)
+    fn rename(
+        &self,
+        _req: &Request<'_>,
+        _from: &Path,
+        _to: &Path,
+        _flags: u32,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Create a hard link to a file
+    fn link(&self, _req: &Request<'_>, _from: &Path, _to: &Path) -> Result<AttrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Open a file (see `Filesystem::open`)
+    fn open(&self, _req: &Request<'_>, _path: &Path, _flags: u32) -> Result<OpenOut, Errno> {
+        Ok(OpenOut::default())
+    }
+
+    /// Read data
+    fn read(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _fh: u64,
+        _offset: i64,
+        _size: u32,
+    ) -> Result<Vec<u8>, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Write data. Returns the number of bytes written.
+    fn write(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _fh: u64,
+        _offset: i64,
+        _data: &[u8],
+        _write_flags: WriteFlags,
+    ) -> Result<u32, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Flush method (see `Filesystem::flush`)
+    fn flush(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _fh: u64,
+        _lock_owner: u64,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Release an open file
+    fn release(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _fh: u64,
+        _flags: u32,
+    ) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    /// Synchronize file contents
+    fn fsync(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _fh: u64,
+        _datasync: bool,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Open a directory
+    fn opendir(&self, _req: &Request<'_>, _path: &Path, _flags: u32) -> Result<OpenOut, Errno> {
+        Ok(OpenOut::default())
+    }
+
+    /// Read all entries of a directory. The adapter returns them to the kernel in chunks of
+    /// the requested size, so this is called again for each chunk.
+    fn readdir(&self, _req: &Request<'_>, _path: &Path, _fh: u64) -> Result<Vec<DirEntry>, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Release an open directory
+    fn releasedir(&self, _req: &Request<'_>, _path: &Path, _fh: u64) -> Result<(), Errno> {
+        Ok(())
+    }
+
+    /// Synchronize directory contents
+    fn fsyncdir(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _fh: u64,
+        _datasync: bool,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Get file system statistics
+    fn statfs(&self, _req: &Request<'_>, _path: &Path) -> Result<StatfsOut, Errno> {
+        Ok(StatfsOut::default())
+    }
+
+    /// Set an extended attribute
+    fn setxattr(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: u32,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Get an extended attribute. If size is 0, return the size of the value.
+    fn getxattr(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _name: &OsStr,
+        _size: u32,
+    ) -> Result<XattrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// List extended attribute names. If size is 0, return the size of the list.
+    fn listxattr(&self, _req: &Request<'_>, _path: &Path, _size: u32) -> Result<XattrOut, Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Remove an extended attribute
+    fn removexattr(&self, _req: &Request<'_>, _path: &Path, _name: &OsStr) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Check file access permissions
+    fn access(&self, _req: &Request<'_>, _path: &Path, _mask: u32) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+
+    /// Create and open a file
+    fn create(
+        &self,
+        _req: &Request<'_>,
+        _path: &Path,
+        _mode: u32,
+        _flags: u32,
+    ) -> Result<(AttrOut, OpenOut), Errno> {
+        Err(Errno::ENOSYS)
+    }
+}
+
+/// An inode known to the kernel
+#[derive(Debug)]
+struct Node {
+    /// Parent directory and name of the inode (the last ones if it was detached)
+    parent: u64,
+    name: OsString,
+    /// Number of open file handles of the inode
+    open: u64,
+    /// True if the inode was unlinked while it was open and renamed to a hidden name
+    hidden: bool,
+    /// True if the inode was unlinked or replaced by a rename, so it has no path anymore
+    detached: bool,
+}
+
+impl Node {
+    fn new(parent: u64, name: OsString) -> Node {
+        Node {
+            parent,
+            name,
+            open: 0,
+            hidden: false,
+            detached: false,
+        }
+    }
+}
+
+/// Inodes known to the kernel and their paths
+#[derive(Debug)]
+struct Nodes {
+    inodes: InodeTable<Node>,
+    /// Inode numbers by parent directory and name
+    names: HashMap<(u64, OsString), u64>,
+    /// Counter for generating hidden names
+    hidden: u64,
+}
+
+impl Nodes {
+    fn new() -> Nodes {
+        Nodes {
+            inodes: InodeTable::new(Node::new(FUSE_ROOT_ID, OsString::new())),
+            names: HashMap::new(),
+            hidden: 0,
+        }
+    }
+
+    /// Returns the path of the given inode. Fails with ESTALE if the inode or one of its
+    /// parent directories was unlinked.
+    fn path(&self, ino: u64) -> Result<PathBuf, Errno> {
+        let mut names = Vec::new();
+        let mut ino = ino;
+        while ino != FUSE_ROOT_ID {
+            let node = self.inodes.get(ino).ok_or(Errno::ENOENT)?;
+            if node.detached {
+                return Err(Errno::ESTALE);
+            }
+            names.push(&node.name);
+            ino = node.parent;
+        }
+        let mut path = PathBuf::from("/");
+        path.extend(names.iter().rev());
+        Ok(path)
+    }
+
+    /// Returns the path of the given name in the given directory
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<PathBuf, Errno> {
+        Ok(self.path(parent)?.join(name))
+    }
+
+    /// Returns the parent directory of the given inode
+    fn parent(&self, ino: u64) -> Option<u64> {
+        self.inodes.get(ino).map(|node| node.parent)
+    }
+
+    /// Returns the inode number of the given name in the given directory, if it's known
+    fn get(&self, parent: u64, name: &OsStr) -> Option<u64> {
+        self.names.get(&(parent, name.to_os_string())).copied()
+    }
+
+    /// Increment the lookup count of the given name in the given directory, assigning an
+    /// inode number if it isn't known yet. Returns the inode number and generation.
+    fn lookup(&mut self, parent: u64, name: &OsStr) -> (u64, u64) {
+        let key = (parent, name.to_os_string());
+        let ino = match self.names.get(&key) {
+            Some(&ino) => ino,
+            None => {
+                let (ino, _) = self.inodes.insert(Node::new(parent, key.1.clone()));
+                self.names.insert(key, ino);
+                ino
+            }
+        };
+        (ino, self.inodes.lookup(ino).unwrap())
+    }
+
+    /// Decrement the lookup count of the given inode and free it when it reaches zero
+    fn forget(&mut self, ino: u64, nlookup: u64) {
+        if let Some(node) = self.inodes.forget(ino, nlookup) {
+            // Remove the name of the inode, if it still has it
+            let key = (node.parent, node.name);
+            if self.names.get(&key) == Some(&ino) {
+                self.names.remove(&key);
+            }
+        }
+    }
+
+    /// Remove the given name in the given directory, e.g. after it was unlinked. The inode
+    /// is kept until the kernel forgets it, but it has no path anymore.
+    fn unlink(&mut self, parent: u64, name: &OsStr) {
+        if let Some(ino) = self.names.remove(&(parent, name.to_os_string())) {
+            self.detach(ino);
+        }
+    }
+
+    fn detach(&mut self, ino: u64) {
+        if let Some(node) = self.inodes.get_mut(ino) {
+            node.detached = true;
+        }
+    }
+
+    /// Returns true if the inode with the given name in the given directory is open
+    fn is_open(&self, parent: u64, name: &OsStr) -> bool {
+        self.get(parent, name)
+            .and_then(|ino| self.inodes.get(ino))
+            .is_some_and(|node| node.open > 0)
+    }
+
+    /// Returns a new hidden name for the given inode
+    fn hidden_name(&mut self, ino: u64) -> OsString {
+        self.hidden += 1;
+        format!(".fuse_hidden{:08x}{:08x}", ino as u32, self.hidden as u32).into()
+    }
+
+    /// Move the inode with the given name in the given directory to the given hidden name
+    fn hide(&mut self, parent: u64, name: &OsStr, hidden: &OsStr) {
+        if let Some(ino) = self.get(parent, name) {
+            self.rename(parent, name, parent, hidden, false);
+            if let Some(node) = self.inodes.get_mut(ino) {
+                node.hidden = true;
+            }
+        }
+    }
+
+    /// Count a new open file handle of the given inode
+    fn open(&mut self, ino: u64) {
+        if let Some(node) = self.inodes.get_mut(ino) {
+            node.open += 1;
+        }
+    }
+
+    /// Count a released file handle of the given inode. Returns true if the inode is hidden
+    /// and this was its last file handle, the hidden file should be unlinked then.
+    fn release(&mut self, ino: u64) -> bool {
+        let (parent, name) = match self.inodes.get_mut(ino) {
+            Some(node) => {
+                node.open = node.open.saturating_sub(1);
+                if !node.hidden || node.open > 0 {
+                    return false;
+                }
+                (node.parent, node.name.clone())
+            }
+            None => return false,
+        };
+        self.unlink(parent, &name);
+        true
+    }
+
+    /// Move an inode to a new name, replacing the inode that had the new name. If exchange
+    /// is set, the inodes swap their names.
+    fn rename(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        exchange: bool,
+    ) {
+        let source = self.names.remove(&(parent, name.to_os_string()));
+        let target = self.names.remove(&(newparent, newname.to_os_string()));
+        if let Some(ino) = source {
+            self.set_name(ino, newparent, newname);
+        }
+        match (target, exchange) {
+            (Some(ino), true) => self.set_name(ino, parent, name),
+            (Some(ino), false) if Some(ino) != source => self.detach(ino),
+            _ => (),
+        }
+    }
+
+    fn set_name(&mut self, ino: u64, parent: u64, name: &OsStr) {
+        if let Some(node) = self.inodes.get_mut(ino) {
+            node.parent = parent;
+            node.name = name.to_os_string();
+            self.names.insert((parent, name.to_os_string()), ino);
+        }
+    }
+}
+
+/// Returns the value of a result or replies with its error
+macro_rules! try_reply {
+    ($result:expr, $reply:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(err) => {
+                $reply.error(err);
+                return;
+            }
+        }
+    };
+}
+
+/// Adapter that implements `Filesystem` for a `PathFilesystem`
+#[derive(Debug)]
+pub struct PathAdapter<FS: PathFilesystem> {
+    filesystem: FS,
+    nodes: Mutex<Nodes>,
+}
+
+impl<FS: PathFilesystem> PathAdapter<FS> {
+    /// Create an adapter for the given path-based filesystem
+    pub fn new(filesystem: FS) -> PathAdapter<FS> {
+        PathAdapter {
+            filesystem,
+            nodes: Mutex::new(Nodes::new()),
+        }
+    }
+
+    /// Returns the path-based filesystem
+    pub fn filesystem(&self) -> &FS {
+        &self.filesystem
+    }
+
+    fn path(&self, ino: u64) -> Result<PathBuf, Errno> {
+        self.nodes.lock().unwrap().path(ino)
+    }
+
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<PathBuf, Errno> {
+        self.nodes.lock().unwrap().child_path(parent, name)
+    }
+
+    /// Rename the open file with the given name in the given directory to a hidden name
+    /// instead of removing it, so that its file handles keep working
+    fn hide(&self, req: &Request<'_>, parent: u64, name: &OsStr) -> Result<(), Errno> {
+        let path = self.child_path(parent, name)?;
+        let ino = self
+            .nodes
+            .lock()
+            .unwrap()
+            .get(parent, name)
+            .ok_or(Errno::ENOENT)?;
+        // Names that exist already are skipped, like libfuse does
+        for _ in 0..10 {
+            let hidden = self.nodes.lock().unwrap().hidden_name(ino);
+            let hidden_path = path.with_file_name(&hidden);
+            match self.filesystem.getattr(req, &hidden_path, None) {
+                Err(Errno::ENOENT) => {
+                    self.filesystem.rename(req, &path, &hidden_path, 0)?;
+                    self.nodes.lock().unwrap().hide(parent, name, &hidden);
+                    return Ok(());
+                }
+                _ => continue,
+            }
+        }
+        Err(Errno::EBUSY)
+    }
+
+    /// Reply with the given entry, incrementing its lookup count
+    fn entry(&self, parent: u64, name: &OsStr, result: Result<AttrOut, Errno>, reply: ReplyEntry) {
+        let mut out = try_reply!(result, reply);
+        let (ino, generation) = self.nodes.lock().unwrap().lookup(parent, name);
+        out.attr.ino = ino;
+        reply.entry(&out.ttl, &out.attr, generation);
+    }
+
+    /// Returns the directory entries after the given offset
+    fn dir_entries(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> Result<(PathBuf, Vec<DirEntry>), Errno> {
+        let path = self.path(ino)?;
+        let entries = self.filesystem.readdir(req, &path, fh)?;
+        let entries = entries.into_iter().skip(offset.max(0) as usize).collect();
+        Ok((path, entries))
+    }
+}
+
+impl<FS: PathFilesystem> Filesystem for PathAdapter<FS> {
+    fn init(&self, req: &Request<'_>, config: &mut KernelConfig) -> Result<(), Errno> {
+        // Readdirplus is implemented with readdir and getattr, the filesystem may still
+        // remove it in its init method
+        let _ = config.add_capabilities(FUSE_DO_READDIRPLUS | FUSE_READDIRPLUS_AUTO);
+        self.filesystem.init(req, config)
+    }
+
+    fn destroy(&self, req: &Request<'_>) {
+        self.filesystem.destroy(req);
+    }
+
+    fn lookup(&self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let path = try_reply!(self.child_path(parent, name), reply);
+        let result = self.filesystem.getattr(req, &path, None);
+        self.entry(parent, name, result, reply);
+    }
+
+    fn forget(&self, _req: &Request<'_>, ino: u64, nlookup: u64) {
+        self.nodes.lock().unwrap().forget(ino, nlookup);
+    }
+
+    fn batch_forget(&self, _req: &Request<'_>, nodes: &[ForgetOne]) {
+        let mut table = self.nodes.lock().unwrap();
+        for node in nodes {
+            table.forget(node.ino(), node.nlookup());
+        }
+    }
+
+    fn getattr(&self, req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        let path = try_reply!(self.path(ino), reply);
+        let mut out = try_reply!(self.filesystem.getattr(req, &path, None), reply);
+        out.attr.ino = ino;
+        reply.attr(&out.ttl, &out.attr);
+    }
+
+    fn setattr(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<SystemTime>,
+        atime_now: bool,
+        mtime: Option<SystemTime>,
+        mtime_now: bool,
+        fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let path = try_reply!(self.path(ino), reply);
+        let attr = SetAttrIn {
+            mode,
+            uid,
+            gid,
+            size,
+            atime,
+            atime_now,
+            mtime,
+            mtime_now,
+            fh,
+        };
+        let mut out = try_reply!(self.filesystem.setattr(req, &path, attr), reply);
+        out.attr.ino = ino;
+        reply.attr(&out.ttl, &out.attr);
+    }
+
+    fn readlink(&self, req: &Request<'_>, ino: u64, reply: ReplyData) {
+        let path = try_reply!(self.path(ino), reply);
+        let target = try_reply!(self.filesystem.readlink(req, &path), reply);
+        reply.data(&target);
+    }
+
+    fn mknod(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let path = try_reply!(self.child_path(parent, name), reply);
+        let result = self.filesystem.mknod(req, &path, mode, rdev);
+        self.entry(parent, name, result, reply);
+    }
+
+    fn mkdir(&self, req: &Request<'_>, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
+        let path = try_reply!(self.child_path(parent, name), reply);
+        let result = self.filesystem.mkdir(req, &path, mode);
+        self.entry(parent, name, result, reply);
+    }
+
+    fn unlink(&self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if self.nodes.lock().unwrap().is_open(parent, name) {
+            try_reply!(self.hide(req, parent, name), reply);
+            return reply.ok();
+        }
+        let path = try_reply!(self.child_path(parent, name), reply);
+        try_reply!(self.filesystem.unlink(req, &path), reply);
+        self.nodes.lock().unwrap().unlink(parent, name);
+        reply.ok();
+    }
+
+    fn rmdir(&self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let path = try_reply!(self.child_path(parent, name), reply);
+        try_reply!(self.filesystem.rmdir(req, &path), reply);
+        self.nodes.lock().unwrap().unlink(parent, name);
+        reply.ok();
+    }
+
+    fn symlink(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        link: &Path,
+        reply: ReplyEntry,
+    ) {
+        let path = try_reply!(self.child_path(parent, name), reply);
+        let result = self.filesystem.symlink(req, &path, link);
+        self.entry(parent, name, result, reply);
+    }
+
+    fn rename(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        #[cfg(target_os = "linux")]
+        let (exchange, noreplace) = (
+            flags & libc::RENAME_EXCHANGE != 0,
+            flags & libc::RENAME_NOREPLACE != 0,
+        );
+        #[cfg(not(target_os = "linux"))]
+        let (exchange, noreplace) = (false, false);
+        // An open file that is replaced is hidden, like an unlinked one
+        let replace = !exchange && !noreplace;
+        if replace && self.nodes.lock().unwrap().is_open(newparent, newname) {
+            try_reply!(self.hide(req, newparent, newname), reply);
+        }
+        let from = try_reply!(self.child_path(parent, name), reply);
+        let to = try_reply!(self.child_path(newparent, newname), reply);
+        try_reply!(self.filesystem.rename(req, &from, &to, flags), reply);
+        self.nodes
+            .lock()
+            .unwrap()
+            .rename(parent, name, newparent, newname, exchange);
+        reply.ok();
+    }
+
+    fn link(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let from = try_reply!(self.path(ino), reply);
+        let to = try_reply!(self.child_path(newparent, newname), reply);
+        let result = self.filesystem.link(req, &from, &to);
+        self.entry(newparent, newname, result, reply);
+    }
+
+    fn open(&self, req: &Request<'_>, ino: u64, flags: u32, reply: ReplyOpen) {
+        let path = try_reply!(self.path(ino), reply);
+        let out = try_reply!(self.filesystem.open(req, &path, flags), reply);
+        self.nodes.lock().unwrap().open(ino);
+        reply.opened(out.fh, out.flags);
+    }
+
+    fn read(&self, req: &Request<'_>, ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
+        let path = try_reply!(self.path(ino), reply);
+        let data = try_reply!(self.filesystem.read(req, &path, fh, offset, size), reply);
+        reply.data(&data);
+    }
+
+    fn write(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: WriteFlags,
+        _flags: u32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let path = try_reply!(self.path(ino), reply);
+        let result = self
+            .filesystem
+            .write(req, &path, fh, offset, data, write_flags);
+        let written = try_reply!(result, reply);
+        reply.written(written);
+    }
+
+    fn flush(&self, req: &Request<'_>, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        let path = try_reply!(self.path(ino), reply);
+        try_reply!(self.filesystem.flush(req, &path, fh, lock_owner), reply);
+        reply.ok();
+    }
+
+    fn release(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let path = try_reply!(self.path(ino), reply);
+        let result = self.filesystem.release(req, &path, fh, flags);
+        // A file that was unlinked while it was open is removed with its last file handle
+        if self.nodes.lock().unwrap().release(ino) {
+            if let Err(err) = self.filesystem.unlink(req, &path) {
+                warn!("Failed to unlink hidden file {}: {}", path.display(), err);
+            }
+        }
+        try_reply!(result, reply);
+        reply.ok();
+    }
+
+    fn fsync(&self, req: &Request<'_>, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        let path = try_reply!(self.path(ino), reply);
+        try_reply!(self.filesystem.fsync(req, &path, fh, datasync), reply);
+        reply.ok();
+    }
+
+    fn opendir(&self, req: &Request<'_>, ino: u64, flags: u32, reply: ReplyOpen) {
+        let path = try_reply!(self.path(ino), reply);
+        let out = try_reply!(self.filesystem.opendir(req, &path, flags), reply);
+        reply.opened(out.fh, out.flags);
+    }
+
+    fn readdir(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let (_, entries) = try_reply!(self.dir_entries(req, ino, fh, offset), reply);
+        let nodes = self.nodes.lock().unwrap();
+        for (i, entry) in entries.iter().enumerate() {
+            let entry_ino = match entry.name.to_str() {
+                Some(".") => ino,
+                Some("..") => nodes.parent(ino).unwrap_or(FUSE_ROOT_ID),
+                _ => nodes.get(ino, &entry.name).unwrap_or(UNKNOWN_INO),
+            };
+            if reply.add(entry_ino, offset + i as i64 + 1, entry.kind, &entry.name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn readdirplus(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectoryPlus,
+    ) {
+        let (path, entries) = try_reply!(self.dir_entries(req, ino, fh, offset), reply);
+        for (i, entry) in entries.iter().enumerate() {
+            let entry_offset = offset + i as i64 + 1;
+            let entry_path = match entry.name.to_str() {
+                Some(".") => path.clone(),
+                Some("..") => path.parent().unwrap_or(&path).to_path_buf(),
+                _ => path.join(&entry.name),
+            };
+            let mut out = match self.filesystem.getattr(req, &entry_path, None) {
+                Ok(out) => out,
+                Err(_) => continue,
+            };
+            // The kernel doesn't look up "." and ".." when they're added
+            let full = match entry.name.to_str() {
+                Some(".") | Some("..") => {
+                    out.attr.ino = UNKNOWN_INO;
+                    reply.add(
+                        UNKNOWN_INO,
+                        entry_offset,
+                        &entry.name,
+                        &out.ttl,
+                        &out.attr,
+                        0,
+                    )
+                }
+                _ => {
+                    let mut nodes = self.nodes.lock().unwrap();
+                    let (entry_ino, generation) = nodes.lookup(ino, &entry.name);
+                    out.attr.ino = entry_ino;
+                    let full = reply.add(
+                        entry_ino,
+                        entry_offset,
+                        &entry.name,
+                        &out.ttl,
+                        &out.attr,
+                        generation,
+                    );
+                    if full {
+                        // The entry wasn't sent, so the kernel doesn't count the lookup
+                        nodes.forget(entry_ino, 1);
+                    }
+                    full
+                }
+            };
+            if full {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn releasedir(&self, req: &Request<'_>, ino: u64, fh: u64, _flags: u32, reply: ReplyEmpty) {
+        let path = try_reply!(self.path(ino), reply);
+        try_reply!(self.filesystem.releasedir(req, &path, fh), reply);
+        reply.ok();
+    }
+
+    fn fsyncdir(&self, req: &Request<'_>, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        let path = try_reply!(self.path(ino), reply);
+        try_reply!(self.filesystem.fsyncdir(req, &path, fh, datasync), reply);
+        reply.ok();
+    }
+
+    fn statfs(&self, req: &Request<'_>, ino: u64, reply: ReplyStatfs) {
+        let path = try_reply!(self.path(ino), reply);
+        let out = try_reply!(self.filesystem.statfs(req, &path), reply);
+        reply.statfs(
+            out.blocks,
+            out.bfree,
+            out.bavail,
+            out.files,
+            out.ffree,
+            out.bsize,
+            out.namelen,
+            out.frsize,
+        );
+    }
+
+    fn setxattr(
+        &self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: u32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        let path = try_reply!(self.path(ino), reply);
+        try_reply!(
+            self.filesystem.setxattr(req, &path, name, value, flags),
+            reply
+        );
+        reply.ok();
+    }
+
+    fn getxattr(&self, req: &Request<'_>, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let path = try_reply!(self.path(ino), reply);
+        match try_reply!(self.filesystem.getxattr(req, &path, name, size), reply) {
+            XattrOut::Size(size) => reply.size(size),
+            XattrOut::Data(data) => reply.data(&data),
+        }
+    }
+
+    fn listxattr(&self, req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        let path = try_reply!(self.path(ino), reply);
+        match try_reply!(self.filesystem.listxattr(req, &path, size), reply) {
+            XattrOut::Size(size) => reply.size(size),
+            XattrOut::Data(data) => reply.data(&data),
+        }
+    }
+
+    fn removexattr(&self, req: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        let path = try_reply!(self.path(ino), reply);
+        try_reply!(self.filesystem.removexattr(req, &path, name), reply);
+        reply.ok();
+    }
+
+    fn access(&self, req: &Request<'_>, ino: u64, mask: u32, reply: ReplyEmpty) {
+        let path = try_reply!(self.path(ino), reply);
+        try_reply!(self.filesystem.access(req, &path, mask), reply);
+        reply.ok();
+    }
+
+    fn create(
+        &self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+        reply: ReplyCreate,
+    ) {
+        let path = try_reply!(self.child_path(parent, name), reply);
+        let (mut attr, open) = try_reply!(self.filesystem.create(req, &path, mode, flags), reply);
+        let mut nodes = self.nodes.lock().unwrap();
+        let (ino, generation) = nodes.lookup(parent, name);
+        nodes.open(ino);
+        drop(nodes);
+        attr.attr.ino = ino;
+        reply.created(&attr.ttl, &attr.attr, generation, open.fh, open.flags);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AttrOut, DirEntry, OpenOut, PathAdapter, PathFilesystem};
+    use crate::fuse_abi::{fuse_opcode, fuse_rename_in};
+    use crate::reply::as_bytes;
+    use crate::request::{Request, WriteFlags};
+    use crate::testing::FakeKernel;
+    use crate::{Errno, FileAttr, FileType, FUSE_ROOT_ID};
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Filesystem of files (with their data) and directories (without data) by path
+    struct TestFs {
+        files: Mutex<BTreeMap<PathBuf, Option<Vec<u8>>>>,
+    }
+
+    fn attr(kind: FileType, size: u64) -> AttrOut {
+        AttrOut {
+            attr: FileAttr {
+                ino: 0,
+                size,
+                blocks: 0,
+                atime: UNIX_EPOCH,
+                mtime: UNIX_EPOCH,
+                ctime: UNIX_EPOCH,
+                crtime: UNIX_EPOCH,
+                kind,
+                perm: 0o755,
+                nlink: 1,
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                blksize: 0,
+                padding: 0,
+                flags: 0,
+            },
+            ttl: Duration::from_secs(1),
+        }
+    }
+
+    impl PathFilesystem for TestFs {
+        fn getattr(
+            &self,
+            _req: &Request<'_>,
+            path: &Path,
+            _fh: Option<u64>,
+        ) -> Result<AttrOut, Errno> {
+            match self.files.lock().unwrap().get(path) {
+                Some(Some(data)) => Ok(attr(FileType::RegularFile, data.len() as u64)),
+                Some(None) => Ok(attr(FileType::Directory, 0)),
+                None if path == Path::new("/") => Ok(attr(FileType::Directory, 0)),
+                None => Err(Errno::ENOENT),
+            }
+        }
+
+        fn mkdir(&self, _req: &Request<'_>, path: &Path, _mode: u32) -> Result<AttrOut, Errno> {
+            self.files.lock().unwrap().insert(path.to_path_buf(), None);
+            Ok(attr(FileType::Directory, 0))
+        }
+
+        fn unlink(&self, _req: &Request<'_>, path: &Path) -> Result<(), Errno> {
+            self.files.lock().unwrap().remove(path);
+            Ok(())
+        }
+
+        fn rename(
+            &self,
+            _req: &Request<'_>,
+            from: &Path,
+            to: &Path,
+            _flags: u32,
+        ) -> Result<(), Errno> {
+            let mut files = self.files.lock().unwrap();
+            let file = files.remove(from).ok_or(Errno::ENOENT)?;
+            files.insert(to.to_path_buf(), file);
+            Ok(())
+        }
+
+        fn open(&self, _req: &Request<'_>, path: &Path, _flags: u32) -> Result<OpenOut, Errno> {
+            self.files.lock().unwrap().get(path).ok_or(Errno::ENOENT)?;
+            Ok(OpenOut::default())
+        }
+
+        fn write(
+            &self,
+            _req: &Request<'_>,
+            path: &Path,
+            _fh: u64,
+            offset: i64,
+            data: &[u8],
+            _write_flags: WriteFlags,
+        ) -> Result<u32, Errno> {
+            let mut files = self.files.lock().unwrap();
+            let file = files
+                .get_mut(path)
+                .and_then(|f| f.as_mut())
+                .ok_or(Errno::ENOENT)?;
+            file.truncate(offset as usize);
+            file.extend_from_slice(data);
+            Ok(data.len() as u32)
+        }
+
+        fn readdir(
+            &self,
+            _req: &Request<'_>,
+            path: &Path,
+            _fh: u64,
+        ) -> Result<Vec<DirEntry>, Errno> {
+            let files = self.files.lock().unwrap();
+            Ok(files
+                .iter()
+                .filter(|(p, _)| p.parent() == Some(path))
+                .map(|(p, data)| DirEntry {
+                    name: p.file_name().unwrap().to_os_string(),
+                    kind: match data {
+                        Some(_) => FileType::RegularFile,
+                        None => FileType::Directory,
+                    },
+                })
+                .collect())
+        }
+    }
+
+    fn kernel(paths: &[&str]) -> FakeKernel<PathAdapter<TestFs>> {
+        let files = paths
+            .iter()
+            .map(|p| (PathBuf::from(p), Some(Vec::new())))
+            .collect();
+        let mut kernel = FakeKernel::new(PathAdapter::new(TestFs {
+            files: Mutex::new(files),
+        }));
+        kernel.init(0).unwrap();
+        kernel
+    }
+
+    fn rename(kernel: &mut FakeKernel<PathAdapter<TestFs>>, from: &str, to: &str) {
+        let mut arg = as_bytes(
+            &fuse_rename_in {
+                newdir: FUSE_ROOT_ID,
+            },
+            |b| b.concat(),
+        );
+        arg.extend_from_slice(from.as_bytes());
+        arg.push(0);
+        arg.extend_from_slice(to.as_bytes());
+        arg.push(0);
+        let opcode = fuse_opcode::FUSE_RENAME as u32;
+        kernel.request(opcode, FUSE_ROOT_ID, &arg).unwrap();
+    }
+
+    #[test]
+    fn lookup_count() {
+        let mut kernel = kernel(&["/a", "/b"]);
+        let a = kernel.lookup(FUSE_ROOT_ID, "a").unwrap();
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "a").unwrap().ino, a.ino);
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "c"), Err(Errno::ENOENT));
+        // The inode stays until all lookups are forgotten
+        kernel.forget(a.ino, 1);
+        assert!(kernel.getattr(a.ino).is_ok());
+        kernel.forget(a.ino, 1);
+        assert_eq!(kernel.getattr(a.ino).unwrap_err(), Errno::ENOENT);
+        // The inode number is reused with a new generation
+        let b = kernel.lookup(FUSE_ROOT_ID, "b").unwrap();
+        assert_eq!(b.ino, a.ino);
+        assert_eq!(b.generation, a.generation + 1);
+    }
+
+    #[test]
+    fn nested_paths() {
+        let mut kernel = kernel(&[]);
+        let dir = kernel.mkdir(FUSE_ROOT_ID, "dir", 0o755).unwrap();
+        let sub = kernel.mkdir(dir.ino, "sub", 0o755).unwrap();
+        assert_ne!(sub.ino, dir.ino);
+        assert_eq!(sub.attr.kind, FileType::Directory);
+        let files = kernel.filesystem().filesystem().files.lock().unwrap();
+        assert!(files.contains_key(Path::new("/dir/sub")));
+    }
+
+    #[test]
+    fn rename_open_file() {
+        let mut kernel = kernel(&["/a", "/b"]);
+        let a = kernel.lookup(FUSE_ROOT_ID, "a").unwrap();
+        let b = kernel.lookup(FUSE_ROOT_ID, "b").unwrap();
+        assert_ne!(b.ino, a.ino);
+        let fh = kernel.open(a.ino, libc::O_WRONLY as u32).unwrap().fh;
+        // Replace b by a, the open file keeps its inode
+        rename(&mut kernel, "a", "b");
+        kernel.write(a.ino, fh, 0, b"data").unwrap();
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "b").unwrap().ino, a.ino);
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "a"), Err(Errno::ENOENT));
+        let files = kernel.filesystem().filesystem().files.lock().unwrap();
+        assert_eq!(files[Path::new("/b")], Some(b"data".to_vec()));
+        drop(files);
+        kernel.release(a.ino, fh).unwrap();
+    }
+
+    /// Returns the paths of the hidden files of the given kernel's filesystem
+    fn hidden(kernel: &FakeKernel<PathAdapter<TestFs>>) -> Vec<PathBuf> {
+        let files = kernel.filesystem().filesystem().files.lock().unwrap();
+        files
+            .keys()
+            .filter(|p| p.to_str().unwrap().starts_with("/.fuse_hidden"))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn rename_onto_open_file() {
+        let mut kernel = kernel(&["/a", "/b"]);
+        let b = kernel.lookup(FUSE_ROOT_ID, "b").unwrap();
+        let fh = kernel.open(b.ino, libc::O_WRONLY as u32).unwrap().fh;
+        // Replace b by a, the open file b is hidden and writes still go to it
+        rename(&mut kernel, "a", "b");
+        kernel.write(b.ino, fh, 0, b"data").unwrap();
+        let paths = hidden(&kernel);
+        assert_eq!(paths.len(), 1);
+        let files = kernel.filesystem().filesystem().files.lock().unwrap();
+        assert_eq!(files[Path::new("/b")], Some(Vec::new()));
+        assert_eq!(files[&paths[0]], Some(b"data".to_vec()));
+        drop(files);
+        // The hidden file is removed with its last file handle
+        kernel.release(b.ino, fh).unwrap();
+        assert!(hidden(&kernel).is_empty());
+        assert_eq!(kernel.getattr(b.ino).unwrap_err(), Errno::ESTALE);
+    }
+
+    #[test]
+    fn unlink_open_file() {
+        let mut kernel = kernel(&["/a", "/b"]);
+        let a = kernel.lookup(FUSE_ROOT_ID, "a").unwrap();
+        let b = kernel.lookup(FUSE_ROOT_ID, "b").unwrap();
+        let fh = kernel.open(a.ino, libc::O_WRONLY as u32).unwrap().fh;
+        kernel.unlink(FUSE_ROOT_ID, "a").unwrap();
+        kernel.unlink(FUSE_ROOT_ID, "b").unwrap();
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "a"), Err(Errno::ENOENT));
+        // The open file is hidden, the other one has no path anymore
+        kernel.write(a.ino, fh, 0, b"data").unwrap();
+        assert_eq!(hidden(&kernel).len(), 1);
+        assert_eq!(kernel.getattr(b.ino).unwrap_err(), Errno::ESTALE);
+        kernel.release(a.ino, fh).unwrap();
+        assert!(hidden(&kernel).is_empty());
+    }
+
+    #[test]
+    fn readdir() {
+        let mut kernel = kernel(&["/a", "/b"]);
+        let a = kernel.lookup(FUSE_ROOT_ID, "a").unwrap();
+        let entries = kernel.readdir(FUSE_ROOT_ID, 0, 0, 4096).unwrap();
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|e| (e.name.to_str().unwrap(), e.ino))
+            .collect();
+        assert_eq!(entries, [("a", a.ino), ("b", super::UNKNOWN_INO)]);
+        let entries = kernel.readdir(FUSE_ROOT_ID, 0, 1, 4096).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "b");
+        assert_eq!(entries[0].offset, 2);
+    }
+}
@@ -6,28 +6,89 @@
 //! data without cloning the data. A reply *must always* be used (by calling either ok() or
 //! error() exactly once).
 
+use crate::fuse_abi::fuse_direntplus;
 use crate::fuse_abi::fuse_getxattr_out;
 #[cfg(target_os = "macos")]
 use crate::fuse_abi::fuse_getxtimes_out;
+use crate::fuse_abi::fuse_lseek_out;
+use crate::fuse_abi::FUSE_KERNEL_MINOR_VERSION;
+use crate::fuse_abi::{consts::FUSE_IOCTL_RETRY, fuse_ioctl_iovec};
 use crate::fuse_abi::{fuse_attr, fuse_attr_out, fuse_entry_out, fuse_file_lock, fuse_kstatfs};
 use crate::fuse_abi::{fuse_bmap_out, fuse_lk_out, fuse_open_out, fuse_statfs_out, fuse_write_out};
 use crate::fuse_abi::{fuse_dirent, fuse_out_header};
-use libc::{c_int, EIO, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK};
+use crate::fuse_abi::{fuse_ioctl_out, fuse_poll_out};
+use crate::fuse_abi::{FUSE_COMPAT_ATTR_OUT_SIZE, FUSE_COMPAT_ENTRY_OUT_SIZE};
+use libc::{c_int, EIO, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK};
 use log::warn;
 use std::convert::AsRef;
 use std::ffi::OsStr;
 use std::fmt;
 use std::marker::PhantomData;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
 use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
-use std::{mem, ptr, slice};
+use std::{io, mem, ptr, slice};
 
-use crate::{FileAttr, FileType};
+use crate::{Errno, FileAttr, FileType};
 
 /// Generic reply callback to send data
 pub trait ReplySender: Send + 'static {
     /// Send data.
     fn send(&self, data: &[&[u8]]);
+
+    /// Send a successful reply to the given request with up to len bytes of data read from the
+    /// given file at the given offset and return the number of bytes sent. Fails without
+    /// sending anything if the data can't be read. The default implementation reads the data
+    /// into memory and sends it.
+    fn send_from_fd(&self, unique: u64, fd: RawFd, offset: i64, len: usize) -> io::Result<usize> {
+        copy_from_fd(self, unique, fd, offset, len)
+    }
+
+    /// Returns the ABI minor version negotiated with the kernel, which determines the layout
+    /// of some replies. Defaults to the latest version supported by this library.
+    fn proto_minor(&self) -> u32 {
+        FUSE_KERNEL_MINOR_VERSION
+    }
+}
+
+/// Send a reply with data read from a file by copying it through memory (see
+/// `ReplySender::send_from_fd`)
+pub(crate) fn copy_from_fd<S: ReplySender + ?Sized>(
+    sender: &S,
+    unique: u64,
+    fd: RawFd,
+    offset: i64,
+    len: usize,
+) -> io::Result<usize> {
+    let mut data: Vec<u8> = Vec::with_capacity(len);
+    while data.len() < len {
+        let rc = unsafe {
+            libc::pread(
+                fd,
+                data.as_mut_ptr().add(data.len()) as *mut libc::c_void,
+                len - data.len(),
+                offset + data.len() as i64,
+            )
+        };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if rc == 0 {
+            break;
+        }
+        unsafe { data.set_len(data.len() + rc as usize) };
+    }
+    let header = fuse_out_header {
+        len: (mem::size_of::<fuse_out_header>() + data.len()) as u32,
+        error: 0,
+        unique,
+    };
+    as_bytes(&header, |headerbytes| sender.send(&[headerbytes[0], &data]));
+    Ok(data.len())
 }
 
 impl fmt::Debug for Box<dyn ReplySender> {
@@ -43,7 +104,7 @@ pub trait Reply {
 }
 
 /// Serialize an arbitrary type to bytes (memory copy, useful for fuse_*_out types)
-fn as_bytes<T, U, F: FnOnce(&[&[u8]]) -> U>(data: &T, f: F) -> U {
+pub(crate) fn as_bytes<T, U, F: FnOnce(&[&[u8]]) -> U>(data: &T, f: F) -> U {
     let len = mem::size_of::<T>();
     match len {
         0 => f(&[]),
@@ -77,6 +138,21 @@ fn mode_from_kind_and_perm(kind: FileType, perm: u16) -> u32 {
         | perm as u32
 }
 
+// Like above, the casts are only needed on platforms where mode_t is u16
+#[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+/// Returns the file kind of the given mode
+pub(crate) fn kind_from_mode(mode: u32) -> FileType {
+    match mode & S_IFMT as u32 {
+        m if m == S_IFIFO as u32 => FileType::NamedPipe,
+        m if m == S_IFCHR as u32 => FileType::CharDevice,
+        m if m == S_IFBLK as u32 => FileType::BlockDevice,
+        m if m == S_IFDIR as u32 => FileType::Directory,
+        m if m == S_IFLNK as u32 => FileType::Symlink,
+        m if m == S_IFSOCK as u32 => FileType::Socket,
+        _ => FileType::RegularFile,
+    }
+}
+
 /// Returns a fuse_attr from FileAttr
 #[cfg(target_os = "macos")]
 fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
@@ -104,9 +180,7 @@ fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         gid: attr.gid,
         rdev: attr.rdev,
         flags: attr.flags,
-        #[cfg(feature = "abi-7-9")]
         blksize: attr.blksize,
-        #[cfg(feature = "abi-7-9")]
         padding: attr.padding,
     }
 }
@@ -134,9 +208,7 @@ fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         uid: attr.uid,
         gid: attr.gid,
         rdev: attr.rdev,
-        #[cfg(feature = "abi-7-9")]
         blksize: attr.blksize,
-        #[cfg(feature = "abi-7-9")]
         padding: attr.padding,
     }
 }
@@ -185,6 +257,24 @@ impl<T> ReplyRaw<T> {
         });
     }
 
+    /// Reply to a request with data read from the given file. Replies with the error if the
+    /// data can't be read.
+    fn send_from_fd(&mut self, fd: RawFd, offset: i64, len: usize) {
+        let sender = self.sender.take().unwrap();
+        if let Err(err) = sender.send_from_fd(self.unique, fd, offset, len) {
+            self.sender = Some(sender);
+            self.send(Errno::from(err).code(), &[]);
+        }
+    }
+
+    /// Returns the ABI minor version negotiated with the kernel (see
+    /// `ReplySender::proto_minor`)
+    pub(crate) fn proto_minor(&self) -> u32 {
+        self.sender
+            .as_ref()
+            .map_or(FUSE_KERNEL_MINOR_VERSION, |sender| sender.proto_minor())
+    }
+
     /// Reply to a request with the given type
     pub fn ok(mut self, data: &T) {
         as_bytes(data, |bytes| {
@@ -192,9 +282,18 @@ impl<T> ReplyRaw<T> {
         })
     }
 
+    /// Reply to a request with only the first len bytes of the given type. Used for replies
+    /// that older ABI versions expect in a shorter layout.
+    pub(crate) fn ok_truncated(mut self, data: &T, len: usize) {
+        as_bytes(data, |bytes| match bytes.first() {
+            Some(bytes) => self.send(0, &[&bytes[..len.min(bytes.len())]]),
+            None => self.send(0, &[]),
+        })
+    }
+
     /// Reply to a request with the given error code
-    pub fn error(mut self, err: c_int) {
-        self.send(err, &[]);
+    pub fn error(mut self, err: Errno) {
+        self.send(err.code(), &[]);
     }
 }
 
@@ -233,7 +332,7 @@ impl ReplyEmpty {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -260,8 +359,16 @@ impl ReplyData {
         self.reply.send(0, &[data]);
     }
 
+    /// Reply to a request with up to len bytes read from the given file at the given offset
+    /// (less at the end of the file). In splice write mode (FUSE_SPLICE_WRITE), the data is
+    /// moved to the kernel without copying it through userspace, otherwise it's read into
+    /// memory first. Replies with the error if the data can't be read.
+    pub fn splice_from_fd(mut self, fd: RawFd, offset: i64, len: usize) {
+        self.reply.send_from_fd(fd, offset, len);
+    }
+
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -282,22 +389,33 @@ impl Reply for ReplyEntry {
     }
 }
 
+/// Returns a fuse_entry_out for the given entry
+fn fuse_entry_out(ttl: &Duration, attr: &FileAttr, generation: u64) -> fuse_entry_out {
+    fuse_entry_out {
+        nodeid: attr.ino,
+        generation,
+        entry_valid: ttl.as_secs(),
+        attr_valid: ttl.as_secs(),
+        entry_valid_nsec: ttl.subsec_nanos(),
+        attr_valid_nsec: ttl.subsec_nanos(),
+        attr: fuse_attr_from_attr(attr),
+    }
+}
+
 impl ReplyEntry {
     /// Reply to a request with the given entry
     pub fn entry(self, ttl: &Duration, attr: &FileAttr, generation: u64) {
-        self.reply.ok(&fuse_entry_out {
-            nodeid: attr.ino,
-            generation,
-            entry_valid: ttl.as_secs(),
-            attr_valid: ttl.as_secs(),
-            entry_valid_nsec: ttl.subsec_nanos(),
-            attr_valid_nsec: ttl.subsec_nanos(),
-            attr: fuse_attr_from_attr(attr),
-        });
+        let entry = fuse_entry_out(ttl, attr, generation);
+        // Before ABI 7.9, the attributes have no block size
+        if self.reply.proto_minor() < 9 {
+            self.reply.ok_truncated(&entry, FUSE_COMPAT_ENTRY_OUT_SIZE);
+        } else {
+            self.reply.ok(&entry);
+        }
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -321,16 +439,22 @@ impl Reply for ReplyAttr {
 impl ReplyAttr {
     /// Reply to a request with the given attribute
     pub fn attr(self, ttl: &Duration, attr: &FileAttr) {
-        self.reply.ok(&fuse_attr_out {
+        let attr = fuse_attr_out {
             attr_valid: ttl.as_secs(),
             attr_valid_nsec: ttl.subsec_nanos(),
             dummy: 0,
             attr: fuse_attr_from_attr(attr),
-        });
+        };
+        // Before ABI 7.9, the attributes have no block size
+        if self.reply.proto_minor() < 9 {
+            self.reply.ok_truncated(&attr, FUSE_COMPAT_ATTR_OUT_SIZE);
+        } else {
+            self.reply.ok(&attr);
+        }
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -369,7 +493,7 @@ impl ReplyXTimes {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -401,7 +525,7 @@ impl ReplyOpen {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -429,7 +553,7 @@ impl ReplyWrite {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -481,7 +605,7 @@ impl ReplyStatfs {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -504,27 +628,36 @@ impl Reply for ReplyCreate {
 
 impl ReplyCreate {
     /// Reply to a request with the given entry
-    pub fn created(self, ttl: &Duration, attr: &FileAttr, generation: u64, fh: u64, flags: u32) {
-        self.reply.ok(&(
-            fuse_entry_out {
-                nodeid: attr.ino,
-                generation,
-                entry_valid: ttl.as_secs(),
-                attr_valid: ttl.as_secs(),
-                entry_valid_nsec: ttl.subsec_nanos(),
-                attr_valid_nsec: ttl.subsec_nanos(),
-                attr: fuse_attr_from_attr(attr),
-            },
-            fuse_open_out {
-                fh,
-                open_flags: flags,
-                padding: 0,
-            },
-        ));
+    pub fn created(
+        mut self,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+        fh: u64,
+        flags: u32,
+    ) {
+        let entry = fuse_entry_out(ttl, attr, generation);
+        let open = fuse_open_out {
+            fh,
+            open_flags: flags,
+            padding: 0,
+        };
+        // Before ABI 7.9, the attributes have no block size and the open flags follow the
+        // shorter entry
+        if self.reply.proto_minor() < 9 {
+            as_bytes(&entry, |entry| {
+                as_bytes(&open, |open| {
+                    self.reply
+                        .send(0, &[&entry[0][..FUSE_COMPAT_ENTRY_OUT_SIZE], open[0]]);
+                })
+            });
+        } else {
+            self.reply.ok(&(entry, open));
+        }
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -559,7 +692,7 @@ impl ReplyLock {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -587,7 +720,128 @@ impl ReplyBmap {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Ioctl Reply
+///
+#[derive(Debug)]
+pub struct ReplyIoctl {
+    reply: ReplyRaw<fuse_ioctl_out>,
+}
+
+impl Reply for ReplyIoctl {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyIoctl {
+        ReplyIoctl {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+impl ReplyIoctl {
+    /// Reply to a request with the given result and output data
+    pub fn ioctl(mut self, result: i32, data: &[u8]) {
+        let ioctl = fuse_ioctl_out {
+            result,
+            flags: 0,
+            in_iovs: 0,
+            out_iovs: 0,
+        };
+        as_bytes(&ioctl, |bytes| {
+            self.reply.send(0, &[bytes[0], data]);
+        });
+    }
+
+    /// Reply to an unrestricted ioctl request by asking the kernel to retry it with the given
+    /// input and output buffers. Buffers are given as (address, length) pairs in the memory of
+    /// the calling process, e.g. derived from the ioctl's arg. The kernel sends the request
+    /// again with the data of the input buffers and the size of the output buffers.
+    pub fn retry(mut self, in_iovs: &[(u64, u64)], out_iovs: &[(u64, u64)]) {
+        let ioctl = fuse_ioctl_out {
+            result: 0,
+            flags: FUSE_IOCTL_RETRY,
+            in_iovs: in_iovs.len() as u32,
+            out_iovs: out_iovs.len() as u32,
+        };
+        let mut iovs = Vec::with_capacity(
+            (in_iovs.len() + out_iovs.len()) * mem::size_of::<fuse_ioctl_iovec>(),
+        );
+        for &(base, len) in in_iovs.iter().chain(out_iovs) {
+            as_bytes(&fuse_ioctl_iovec { base, len }, |bytes| {
+                iovs.extend_from_slice(bytes[0])
+            });
+        }
+        as_bytes(&ioctl, |bytes| {
+            self.reply.send(0, &[bytes[0], &iovs]);
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: Errno) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Poll Reply
+///
+#[derive(Debug)]
+pub struct ReplyPoll {
+    reply: ReplyRaw<fuse_poll_out>,
+}
+
+impl Reply for ReplyPoll {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyPoll {
+        ReplyPoll {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+impl ReplyPoll {
+    /// Reply to a request with the given poll events
+    pub fn poll(self, revents: u32) {
+        self.reply.ok(&fuse_poll_out {
+            revents,
+            padding: 0,
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: Errno) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Lseek Reply
+///
+#[derive(Debug)]
+pub struct ReplyLseek {
+    reply: ReplyRaw<fuse_lseek_out>,
+}
+
+impl Reply for ReplyLseek {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyLseek {
+        ReplyLseek {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+impl ReplyLseek {
+    /// Reply to a request with the resulting file offset
+    pub fn offset(self, offset: i64) {
+        self.reply.ok(&fuse_lseek_out {
+            offset: offset as u64,
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -644,7 +898,72 @@ impl ReplyDirectory {
     }
 
     /// Reply to a request with the given error code
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// DirectoryPlus reply
+///
+#[derive(Debug)]
+pub struct ReplyDirectoryPlus {
+    reply: ReplyRaw<()>,
+    data: Vec<u8>,
+}
+
+impl ReplyDirectoryPlus {
+    /// Creates a new ReplyDirectoryPlus with a specified buffer size.
+    pub fn new<S: ReplySender>(unique: u64, sender: S, size: usize) -> ReplyDirectoryPlus {
+        ReplyDirectoryPlus {
+            reply: Reply::new(unique, sender),
+            data: Vec::with_capacity(size),
+        }
+    }
+
+    /// Add an entry with its attributes to the directory reply buffer. Returns true if the
+    /// buffer is full. Like with `ReplyEntry`, the kernel caches the entry and attributes for
+    /// the given ttl and increments the lookup count of the inode (unless it's "." or "..").
+    /// A transparent offset value can be provided for each entry. The kernel uses these
+    /// value to request the next entries in further readdirplus calls
+    pub fn add<T: AsRef<OsStr>>(
+        &mut self,
+        ino: u64,
+        offset: i64,
+        name: T,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+    ) -> bool {
+        let name = name.as_ref().as_bytes();
+        let entlen = mem::size_of::<fuse_direntplus>() + name.len();
+        let entsize = (entlen + mem::size_of::<u64>() - 1) & !(mem::size_of::<u64>() - 1); // 64bit align
+        if self.data.len() + entsize > self.data.capacity() {
+            return true;
+        }
+        let direntplus = fuse_direntplus {
+            entry_out: fuse_entry_out(ttl, attr, generation),
+            dirent: fuse_dirent {
+                ino,
+                off: offset as u64,
+                namelen: name.len() as u32,
+                typ: mode_from_kind_and_perm(attr.kind, 0) >> 12,
+            },
+        };
+        let newlen = self.data.len() + entsize;
+        as_bytes(&direntplus, |bytes| self.data.extend_from_slice(bytes[0]));
+        self.data.extend_from_slice(name);
+        self.data.resize(newlen, 0);
+        false
+    }
+
+    /// Reply to a request with the filled directory buffer
+    pub fn ok(mut self) {
+        self.reply.send(0, &[&self.data]);
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -677,7 +996,7 @@ impl ReplyXattr {
     }
 
     /// Reply to a request with the given error code.
-    pub fn error(self, err: c_int) {
+    pub fn error(self, err: Errno) {
         self.reply.error(err);
     }
 }
@@ -685,12 +1004,17 @@ impl ReplyXattr {
 #[cfg(test)]
 mod test {
     use super::as_bytes;
+    use super::ReplyDirectoryPlus;
+    use super::ReplyLseek;
     #[cfg(target_os = "macos")]
     use super::ReplyXTimes;
     use super::ReplyXattr;
     use super::{Reply, ReplyAttr, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyRaw};
     use super::{ReplyBmap, ReplyCreate, ReplyDirectory, ReplyLock, ReplyStatfs, ReplyWrite};
-    use crate::{FileAttr, FileType};
+    use super::{ReplyIoctl, ReplyPoll};
+    use crate::test_util::temp_path;
+    use crate::{Errno, FileAttr, FileType};
+    use std::os::unix::io::AsRawFd;
     use std::sync::mpsc::{channel, Sender};
     use std::thread;
     use std::time::{Duration, UNIX_EPOCH};
@@ -760,6 +1084,23 @@ mod test {
         }
     }
 
+    /// Sender that asserts the sent data like `AssertSender`, with an older negotiated ABI
+    /// version
+    struct CompatSender {
+        proto_minor: u32,
+        expected: Vec<Vec<u8>>,
+    }
+
+    impl super::ReplySender for CompatSender {
+        fn send(&self, data: &[&[u8]]) {
+            assert_eq!(self.expected, data);
+        }
+
+        fn proto_minor(&self) -> u32 {
+            self.proto_minor
+        }
+    }
+
     #[test]
     fn reply_raw() {
         let data = Data {
@@ -789,7 +1130,7 @@ mod test {
             ]],
         };
         let reply: ReplyRaw<Data> = Reply::new(0xdeadbeef, sender);
-        reply.error(66);
+        reply.error(Errno::new(66).unwrap());
     }
 
     #[test]
@@ -819,6 +1160,35 @@ mod test {
         reply.data(&[0xde, 0xad, 0xbe, 0xef]);
     }
 
+    #[test]
+    fn reply_data_from_fd() {
+        let path = temp_path("reply");
+        std::fs::write(&path, [0x00, 0x00, 0xde, 0xad, 0xbe, 0xef]).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // The data is clamped at the end of the file
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0xde, 0xad, 0xbe, 0xef],
+            ],
+        };
+        let reply: ReplyData = Reply::new(0xdeadbeef, sender);
+        reply.splice_from_fd(file.as_raw_fd(), 2, 8);
+        // Errors reading the file are sent as error replies
+        let sender = AssertSender {
+            expected: vec![vec![
+                0x10, 0x00, 0x00, 0x00, 0xf7, 0xff, 0xff, 0xff, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00,
+            ]],
+        };
+        let reply: ReplyData = Reply::new(0xdeadbeef, sender);
+        reply.splice_from_fd(-1, 0, 4);
+    }
+
     #[test]
     fn reply_entry() {
         let mut expected = if cfg!(target_os = "macos") {
@@ -862,13 +1232,12 @@ mod test {
             ]
         };
 
-        if cfg!(feature = "abi-7-9") {
-            expected[1].extend(vec![0xbb, 0x00, 0x00, 0x00, 0xcc, 0x00, 0x00, 0x00]);
-        }
+        // Before ABI 7.9, the attributes end before the block size
+        let mut compat = expected.clone();
+        compat[0][0] = (compat[0].len() + compat[1].len()) as u8;
+        expected[1].extend(vec![0xbb, 0x00, 0x00, 0x00, 0xcc, 0x00, 0x00, 0x00]);
         expected[0][0] = (expected[0].len() + expected[1].len()) as u8;
 
-        let sender = AssertSender { expected };
-        let reply: ReplyEntry = Reply::new(0xdeadbeef, sender);
         let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
         let ttl = Duration::new(0x8765, 0x4321);
         let attr = FileAttr {
@@ -889,6 +1258,14 @@ mod test {
             blksize: 0xbb,
             padding: 0xcc,
         };
+        let sender = AssertSender { expected };
+        let reply: ReplyEntry = Reply::new(0xdeadbeef, sender);
+        reply.entry(&ttl, &attr, 0xaa);
+        let sender = CompatSender {
+            proto_minor: 8,
+            expected: compat,
+        };
+        let reply: ReplyEntry = Reply::new(0xdeadbeef, sender);
         reply.entry(&ttl, &attr, 0xaa);
     }
 
@@ -931,13 +1308,12 @@ mod test {
             ]
         };
 
-        if cfg!(feature = "abi-7-9") {
-            expected[1].extend(vec![0xbb, 0x00, 0x00, 0x00, 0xcc, 0x00, 0x00, 0x00]);
-        }
+        // Before ABI 7.9, the attributes end before the block size
+        let mut compat = expected.clone();
+        compat[0][0] = (compat[0].len() + compat[1].len()) as u8;
+        expected[1].extend(vec![0xbb, 0x00, 0x00, 0x00, 0xcc, 0x00, 0x00, 0x00]);
         expected[0][0] = (expected[0].len() + expected[1].len()) as u8;
 
-        let sender = AssertSender { expected };
-        let reply: ReplyAttr = Reply::new(0xdeadbeef, sender);
         let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
         let ttl = Duration::new(0x8765, 0x4321);
         let attr = FileAttr {
@@ -958,6 +1334,14 @@ mod test {
             blksize: 0xbb,
             padding: 0xcc,
         };
+        let sender = AssertSender { expected };
+        let reply: ReplyAttr = Reply::new(0xdeadbeef, sender);
+        reply.attr(&ttl, &attr);
+        let sender = CompatSender {
+            proto_minor: 8,
+            expected: compat,
+        };
+        let reply: ReplyAttr = Reply::new(0xdeadbeef, sender);
         reply.attr(&ttl, &attr);
     }
 
@@ -1082,17 +1466,18 @@ mod test {
             ]
         };
 
-        if cfg!(feature = "abi-7-9") {
-            let insert_at = expected[1].len() - 16;
-            expected[1].splice(
-                insert_at..insert_at,
-                vec![0xdd, 0x00, 0x00, 0x00, 0xee, 0x00, 0x00, 0x00],
-            );
-        }
+        // Before ABI 7.9, the open flags follow the shorter attributes right away
+        let insert_at = expected[1].len() - 16;
+        let mut compat = expected.clone();
+        let open = compat[1].split_off(insert_at);
+        compat.push(open);
+        compat[0][0] = (compat[0].len() + compat[1].len() + compat[2].len()) as u8;
+        expected[1].splice(
+            insert_at..insert_at,
+            vec![0xdd, 0x00, 0x00, 0x00, 0xee, 0x00, 0x00, 0x00],
+        );
         expected[0][0] = (expected[0].len() + expected[1].len()) as u8;
 
-        let sender = AssertSender { expected };
-        let reply: ReplyCreate = Reply::new(0xdeadbeef, sender);
         let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
         let ttl = Duration::new(0x8765, 0x4321);
         let attr = FileAttr {
@@ -1113,6 +1498,14 @@ mod test {
             blksize: 0xdd,
             padding: 0xee,
         };
+        let sender = AssertSender { expected };
+        let reply: ReplyCreate = Reply::new(0xdeadbeef, sender);
+        reply.created(&ttl, &attr, 0xaa, 0xbb, 0xcc);
+        let sender = CompatSender {
+            proto_minor: 8,
+            expected: compat,
+        };
+        let reply: ReplyCreate = Reply::new(0xdeadbeef, sender);
         reply.created(&ttl, &attr, 0xaa, 0xbb, 0xcc);
     }
 
@@ -1149,6 +1542,77 @@ mod test {
         reply.bmap(0x1234);
     }
 
+    #[test]
+    fn reply_ioctl() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0xde, 0xad, 0xbe, 0xef],
+            ],
+        };
+        let reply: ReplyIoctl = Reply::new(0xdeadbeef, sender);
+        reply.ioctl(0x1234, &[0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn reply_ioctl_retry() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+            ],
+        };
+        let reply: ReplyIoctl = Reply::new(0xdeadbeef, sender);
+        reply.retry(&[(0x1000, 0x20)], &[]);
+    }
+
+    #[test]
+    fn reply_poll() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ],
+        };
+        let reply: ReplyPoll = Reply::new(0xdeadbeef, sender);
+        reply.poll(0x05);
+    }
+
+    #[test]
+    fn reply_lseek() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ],
+        };
+        let reply: ReplyLseek = Reply::new(0xdeadbeef, sender);
+        reply.offset(0x1000);
+    }
+
     #[test]
     fn reply_directory() {
         let sender = AssertSender {
@@ -1172,6 +1636,57 @@ mod test {
         reply.ok();
     }
 
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn reply_directory_plus() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0xb0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0xbb, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xaa, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x65, 0x87, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x65, 0x87,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x43, 0x00, 0x00, 0x21, 0x43, 0x00,
+                    0x00, 0xbb, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0x33, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34,
+                    0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0x56, 0x00,
+                    0x00, 0x78, 0x56, 0x00, 0x00, 0x78, 0x56, 0x00, 0x00, 0xa4, 0x81, 0x00, 0x00,
+                    0x55, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00, 0x77, 0x00, 0x00, 0x00, 0x88,
+                    0x00, 0x00, 0x00, 0xbb, 0x00, 0x00, 0x00, 0xcc, 0x00, 0x00, 0x00, 0xbb, 0xaa,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x05, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c,
+                    0x6f, 0x00, 0x00, 0x00,
+                ],
+            ],
+        };
+        let mut reply = ReplyDirectoryPlus::new(0xdeadbeef, sender, 4096);
+        let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
+        let ttl = Duration::new(0x8765, 0x4321);
+        let attr = FileAttr {
+            ino: 0xaabb,
+            size: 0x22,
+            blocks: 0x33,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 0x55,
+            uid: 0x66,
+            gid: 0x77,
+            rdev: 0x88,
+            flags: 0x99,
+            blksize: 0xbb,
+            padding: 0xcc,
+        };
+        reply.add(0xaabb, 1, "hello", &ttl, &attr, 0xaa);
+        reply.ok();
+    }
+
     impl super::ReplySender for Sender<()> {
         fn send(&self, _: &[&[u8]]) {
             Sender::send(self, ()).unwrap()
@@ -7,30 +7,246 @@
 
 use crate::fuse_abi::consts::*;
 use crate::fuse_abi::*;
-use libc::{EIO, ENOSYS, EPROTO};
 use log::{debug, error, warn};
-use std::convert::TryFrom;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::channel::ChannelSender;
+use crate::errno::Errno;
+use crate::kernel_config::KernelConfig;
 use crate::ll;
-use crate::reply::{Reply, ReplyDirectory, ReplyEmpty, ReplyRaw};
-use crate::session::{Session, MAX_WRITE_SIZE};
+use crate::notify::Notifier;
+use crate::notify::PollHandle;
+use crate::reply::ReplyDirectoryPlus;
+use crate::reply::{Reply, ReplyDirectory, ReplyEmpty, ReplyRaw, ReplySender};
+use crate::session::Session;
+use crate::splice::{SpliceMode, SplicedData};
+use crate::stats::{opcode_name, SessionStats};
 use crate::Filesystem;
 
-/// We generally support async reads
-#[cfg(all(not(target_os = "macos"), not(feature = "abi-7-10")))]
-const INIT_FLAGS: u32 = FUSE_ASYNC_READ;
-#[cfg(all(not(target_os = "macos"), feature = "abi-7-10"))]
-const INIT_FLAGS: u32 = FUSE_ASYNC_READ | FUSE_BIG_WRITES;
-// TODO: Add FUSE_EXPORT_SUPPORT
+/// Tracks the requests that are currently processed by the filesystem, so that interrupts
+/// sent by the kernel can be delivered to them.
+#[derive(Debug, Default)]
+pub(crate) struct InterruptTracker {
+    state: Mutex<InterruptState>,
+}
+
+#[derive(Debug, Default)]
+struct InterruptState {
+    /// Interrupt flags of in-flight requests by unique id
+    requests: HashMap<u64, Arc<AtomicBool>>,
+    /// Unique ids of requests that were interrupted before they were registered. With
+    /// multiple workers, an interrupt may be received before the request it refers to is
+    /// dispatched on another worker.
+    pending: HashSet<u64>,
+    /// Unique ids of the most recently completed requests, to ignore interrupts that arrive
+    /// after the reply was sent
+    completed: VecDeque<u64>,
+}
+
+/// Number of completed requests to remember for ignoring late interrupts
+const COMPLETED_REQUESTS: usize = 1024;
+
+impl InterruptTracker {
+    /// Register a new in-flight request. If an interrupt for this request arrived before,
+    /// the request is marked as interrupted right away.
+    fn register(&self, unique: u64, interrupted: &Arc<AtomicBool>) {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.remove(&unique) {
+            interrupted.store(true, Ordering::SeqCst);
+        }
+        state.requests.insert(unique, interrupted.clone());
+    }
+
+    /// Mark the in-flight request with the given unique id as interrupted. If the request
+    /// isn't registered yet, the interrupt is remembered until it is. Interrupts of recently
+    /// completed requests are ignored.
+    fn interrupt(&self, unique: u64) {
+        let mut state = self.state.lock().unwrap();
+        match state.requests.get(&unique) {
+            Some(interrupted) => interrupted.store(true, Ordering::SeqCst),
+            None if state.completed.contains(&unique) => (),
+            None => {
+                state.pending.insert(unique);
+            }
+        }
+    }
+
+    /// Remove a completed request
+    fn complete(&self, unique: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.requests.remove(&unique).is_some() {
+            if state.completed.len() == COMPLETED_REQUESTS {
+                state.completed.pop_front();
+            }
+            state.completed.push_back(unique);
+        }
+    }
+}
+
+/// Reply sender that marks the request as completed and records its statistics when sending
+/// its reply
+#[derive(Debug)]
+struct RequestSender {
+    ch: ChannelSender,
+    unique: u64,
+    opcode: u32,
+    received: Instant,
+    interrupts: Arc<InterruptTracker>,
+    stats: SessionStats,
+}
+
+impl ReplySender for RequestSender {
+    fn send(&self, data: &[&[u8]]) {
+        self.interrupts.complete(self.unique);
+        ReplySender::send(&self.ch, data);
+        // The first slice is the reply header, followed by the data
+        let error = i32::from_ne_bytes([data[0][4], data[0][5], data[0][6], data[0][7]]);
+        let len = self.bytes_read(data[1..].iter().map(|d| d.len()).sum());
+        self.stats
+            .replied(self.opcode, error, len, self.received.elapsed());
+    }
+
+    fn send_from_fd(&self, unique: u64, fd: RawFd, offset: i64, len: usize) -> io::Result<usize> {
+        // If reading fails, nothing has been sent and the request is replied to with an error
+        let sent = self.ch.send_from_fd(unique, fd, offset, len)?;
+        self.interrupts.complete(self.unique);
+        self.stats.replied(
+            self.opcode,
+            0,
+            self.bytes_read(sent),
+            self.received.elapsed(),
+        );
+        Ok(sent)
+    }
+    fn proto_minor(&self) -> u32 {
+        self.ch.proto_minor()
+    }
+}
+
+impl RequestSender {
+    /// Returns the number of bytes read by the reply with the given data length. Only the
+    /// data of read replies is counted, other replies just contain the operation's result.
+    fn bytes_read(&self, len: usize) -> usize {
+        if self.opcode == fuse_opcode::FUSE_READ as u32 {
+            len
+        } else {
+            0
+        }
+    }
+}
 
-/// On macOS, we additionally support case insensitiveness, volume renames and xtimes
-/// TODO: we should eventually let the filesystem implementation decide which flags to set
-#[cfg(target_os = "macos")]
-const INIT_FLAGS: u32 = FUSE_ASYNC_READ | FUSE_CASE_INSENSITIVE | FUSE_VOL_RENAME | FUSE_XTIMES;
-// TODO: Add FUSE_EXPORT_SUPPORT and FUSE_BIG_WRITES (requires ABI 7.10)
+/// Token to check whether the kernel interrupted a request, e.g. because the process
+/// that triggered the request received a signal. A filesystem may abort the operation
+/// and reply with `EINTR` if it sees the request interrupted. The token can be sent to
+/// other threads along with the request's reply.
+#[derive(Clone, Debug)]
+pub struct InterruptToken {
+    interrupted: Arc<AtomicBool>,
+}
+
+impl InterruptToken {
+    /// Returns true if the kernel interrupted the request
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+}
+
+/// An inode to forget in a batch forget. The lookup count of the inode is decreased by
+/// the given number of lookups, like with a single forget.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct ForgetOne {
+    inner: fuse_forget_one,
+}
+
+impl ForgetOne {
+    /// Returns the inode to forget
+    pub fn ino(&self) -> u64 {
+        self.inner.nodeid
+    }
+
+    /// Returns the number of lookups to forget
+    pub fn nlookup(&self) -> u64 {
+        self.inner.nlookup
+    }
+
+    /// Borrow the nodes of a batch forget request as ForgetOne
+    fn from_nodes(nodes: &[fuse_forget_one]) -> &[ForgetOne] {
+        // Safety: ForgetOne is a transparent wrapper of fuse_forget_one
+        unsafe { &*(nodes as *const [fuse_forget_one] as *const [ForgetOne]) }
+    }
+}
+
+/// A range of the DAX window to unmap in a removemapping request.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct RemoveMappingOne {
+    inner: fuse_removemapping_one,
+}
+
+impl RemoveMappingOne {
+    /// Returns the offset of the range in the DAX window
+    pub fn moffset(&self) -> u64 {
+        self.inner.moffset
+    }
+
+    /// Returns the length of the range
+    pub fn length(&self) -> u64 {
+        self.inner.len
+    }
+
+    /// Borrow the mappings of a removemapping request as RemoveMappingOne
+    fn from_mappings(mappings: &[fuse_removemapping_one]) -> &[RemoveMappingOne] {
+        // Safety: RemoveMappingOne is a transparent wrapper of fuse_removemapping_one
+        unsafe { &*(mappings as *const [fuse_removemapping_one] as *const [RemoveMappingOne]) }
+    }
+}
+
+/// Flags of a write request (see FUSE_WRITE_* in `consts`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteFlags(u32);
+
+impl WriteFlags {
+    /// Create write flags from raw FUSE_WRITE_* flags
+    pub fn from_bits(bits: u32) -> WriteFlags {
+        WriteFlags(bits)
+    }
+
+    /// Returns the raw FUSE_WRITE_* flags
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if this is a delayed write of cached pages (FUSE_WRITE_CACHE) rather
+    /// than a write by a process. This only happens in writeback cache mode, the file handle
+    /// is guessed by the kernel then.
+    pub fn is_cache(&self) -> bool {
+        self.0 & FUSE_WRITE_CACHE != 0
+    }
+
+    /// Returns true if the suid and sgid bits of the file should be cleared
+    /// (FUSE_WRITE_KILL_SUIDGID, sent if FUSE_HANDLE_KILLPRIV_V2 is used)
+    pub fn kill_suidgid(&self) -> bool {
+        self.0 & FUSE_WRITE_KILL_SUIDGID != 0
+    }
+}
+
+/// Returns the write flags, the open flags and the lock owner (if valid) of a write request
+pub(crate) fn write_args(arg: &fuse_write_in) -> (WriteFlags, u32, Option<u64>) {
+    let write_flags = WriteFlags::from_bits(arg.write_flags);
+    let (flags, lock_owner) = match arg.write_flags & FUSE_WRITE_LOCKOWNER {
+        0 => (arg.flags, None),
+        _ => (arg.flags, Some(arg.lock_owner)),
+    };
+    (write_flags, flags, lock_owner)
+}
 
 /// Request data structure
 #[derive(Debug)]
@@ -41,75 +257,104 @@ pub struct Request<'a> {
     data: &'a [u8],
     /// Parsed request
     request: ll::Request<'a>,
+    /// Tracker of in-flight requests of the session
+    interrupts: Arc<InterruptTracker>,
+    /// Set when the kernel interrupts this request
+    interrupted: Arc<AtomicBool>,
+    /// Statistics of the session
+    stats: SessionStats,
+    /// Time the request was received
+    received: Instant,
+    /// Payload of a write request that was received in splice read mode
+    spliced: Mutex<Option<SplicedData<'a>>>,
 }
 
 impl<'a> Request<'a> {
-    /// Create a new request from the given data
-    pub fn new(ch: ChannelSender, data: &'a [u8]) -> Option<Request<'a>> {
-        let request = match ll::Request::try_from(data) {
+    /// Create a new request from the given data. A request that can't be parsed is replied to
+    /// with an error right away (ENOSYS for unknown operations, EIO for malformed requests) and
+    /// None is returned. Fails only if the data is corrupt, i.e. if not even the header of the
+    /// request can be read.
+    pub(crate) fn new(
+        ch: ChannelSender,
+        data: &'a [u8],
+        interrupts: &Arc<InterruptTracker>,
+        stats: &SessionStats,
+    ) -> io::Result<Option<Request<'a>>> {
+        let received = Instant::now();
+        let request = match ll::Request::parse(data, ch.proto_minor()) {
             Ok(request) => request,
             Err(err) => {
-                // FIXME: Reply with ENOSYS?
-                error!("{}", err);
-                return None;
+                stats.invalid();
+                let header = match err.header {
+                    Some(header) => header,
+                    None => {
+                        error!("{}", err);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()));
+                    }
+                };
+                warn!("{}", err);
+                stats.received(header.opcode, 0);
+                if let Some(errno) = err.reply_error() {
+                    let sender = RequestSender {
+                        ch,
+                        unique: header.unique,
+                        opcode: header.opcode,
+                        received,
+                        interrupts: interrupts.clone(),
+                        stats: stats.clone(),
+                    };
+                    ReplyEmpty::new(header.unique, sender).error(errno);
+                }
+                return Ok(None);
             }
         };
+        let bytes_written = match request.operation() {
+            ll::Operation::Write { arg, .. } => arg.size as usize,
+            _ => 0,
+        };
+        stats.received(request.opcode(), bytes_written);
+
+        Ok(Some(Self {
+            ch,
+            data,
+            request,
+            interrupts: interrupts.clone(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+            stats: stats.clone(),
+            received,
+            spliced: Mutex::new(None),
+        }))
+    }
 
-        Some(Self { ch, data, request })
+    /// Attach the payload of a write request that was left in the pipe it was received with
+    pub(crate) fn with_payload(mut self, data: SplicedData<'a>) -> Request<'a> {
+        self.spliced = Mutex::new(Some(data));
+        self
     }
 
     /// Dispatch request to the given filesystem.
     /// This calls the appropriate filesystem operation method for the
-    /// request and sends back the returned reply to the kernel
-    pub fn dispatch<FS: Filesystem>(&self, se: &mut Session<FS>) {
+    /// request and sends back the returned reply to the kernel. A panic in the filesystem
+    /// method is caught and the request is replied to with EIO then. Returns false if the
+    /// filesystem panicked.
+    pub fn dispatch<FS: Filesystem>(&self, se: &mut Session<FS>) -> bool {
         debug!("{}", self.request);
 
-        match self.request.operation() {
+        self.catch_panic(|| match self.request.operation() {
             // Filesystem initialization
             ll::Operation::Init { arg } => {
-                let reply: ReplyRaw<fuse_init_out> = self.reply();
-                // We don't support ABI versions before 7.6
-                if arg.major < 7 || (arg.major == 7 && arg.minor < 6) {
-                    error!("Unsupported FUSE ABI version {}.{}", arg.major, arg.minor);
-                    reply.error(EPROTO);
-                    return;
-                }
-                // Remember ABI version supported by kernel
-                se.proto_major = arg.major;
-                se.proto_minor = arg.minor;
-                // Call filesystem init method and give it a chance to return an error
-                let res = se.filesystem.init(self);
-                if let Err(err) = res {
-                    reply.error(err);
-                    return;
+                if let Some(splice) = self.init(&se.filesystem, arg) {
+                    // Remember the negotiated ABI version
+                    se.proto_major = arg.major;
+                    se.proto_minor = arg.minor.min(FUSE_KERNEL_MINOR_VERSION);
+                    se.initialized = true;
+                    se.set_splice(splice);
                 }
-                // Reply with our desired version and settings. If the kernel supports a
-                // larger major version, it'll re-send a matching init message. If it
-                // supports only lower major versions, we replied with an error above.
-                let init = fuse_init_out {
-                    major: FUSE_KERNEL_VERSION,
-                    minor: FUSE_KERNEL_MINOR_VERSION,
-                    max_readahead: arg.max_readahead, // accept any readahead size
-                    flags: arg.flags & INIT_FLAGS, // use features given in INIT_FLAGS and reported as capable
-                    #[cfg(not(feature = "abi-7-13"))]
-                    unused: 0,
-                    #[cfg(feature = "abi-7-13")]
-                    max_background: 16, // TODO: this is just a placeholder. Make it configurable
-                    #[cfg(feature = "abi-7-13")]
-                    congestion_threshold: 32, // TODO: this is just a placeholder. Make it configurable
-                    max_write: MAX_WRITE_SIZE as u32, // use a max write size that fits into the session's buffer
-                };
-                debug!(
-                    "INIT response: ABI {}.{}, flags {:#x}, max readahead {}, max write {}",
-                    init.major, init.minor, init.flags, init.max_readahead, init.max_write
-                );
-                se.initialized = true;
-                reply.ok(&init);
             }
             // Any operation is invalid before initialization
             _ if !se.initialized => {
                 warn!("Ignoring FUSE operation before init: {}", self.request);
-                self.reply::<ReplyEmpty>().error(EIO);
+                self.reply::<ReplyEmpty>().error(Errno::EIO);
             }
             // Filesystem destroyed
             ll::Operation::Destroy => {
@@ -120,25 +365,167 @@ impl<'a> Request<'a> {
             // Any operation is invalid after destroy
             _ if se.destroyed => {
                 warn!("Ignoring FUSE operation after destroy: {}", self.request);
-                self.reply::<ReplyEmpty>().error(EIO);
+                self.reply::<ReplyEmpty>().error(Errno::EIO);
             }
 
-            ll::Operation::Interrupt { .. } => {
-                // TODO: handle FUSE_INTERRUPT
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+            _ => self.dispatch_operation(&se.filesystem, &se.notifier),
+        })
+    }
+
+    /// Dispatch request to the given filesystem of an already initialized session.
+    /// Unlike `dispatch`, this doesn't need mutable access to the session and can be
+    /// used by multiple worker threads concurrently. Returns false if the filesystem panicked.
+    pub(crate) fn dispatch_concurrent<FS: Filesystem>(
+        &self,
+        fs: &FS,
+        destroyed: &AtomicBool,
+        notifier: &Notifier,
+    ) -> bool {
+        debug!("{}", self.request);
+
+        self.catch_panic(|| match self.request.operation() {
+            // The session is initialized before any worker threads are started
+            ll::Operation::Init { .. } => {
+                warn!("Ignoring repeated FUSE init: {}", self.request);
+                self.reply::<ReplyEmpty>().error(Errno::EIO);
+            }
+            // Filesystem destroyed
+            ll::Operation::Destroy => {
+                fs.destroy(self);
+                destroyed.store(true, Ordering::SeqCst);
+                self.reply::<ReplyEmpty>().ok();
+            }
+            // Any operation is invalid after destroy
+            _ if destroyed.load(Ordering::SeqCst) => {
+                warn!("Ignoring FUSE operation after destroy: {}", self.request);
+                self.reply::<ReplyEmpty>().error(Errno::EIO);
+            }
+
+            _ => self.dispatch_operation(fs, notifier),
+        })
+    }
+
+    /// Dispatch request to the given filesystem without a session, e.g. while replaying a
+    /// capture. Like `dispatch`, but the initialization state is kept by the caller.
+    pub(crate) fn dispatch_standalone<FS: Filesystem>(
+        &self,
+        fs: &FS,
+        initialized: &mut bool,
+        destroyed: &AtomicBool,
+        notifier: &Notifier,
+    ) -> bool {
+        match self.request.operation() {
+            ll::Operation::Init { arg } if !*initialized => {
+                debug!("{}", self.request);
+                self.catch_panic(|| *initialized = self.init(fs, arg).is_some())
+            }
+            _ if !*initialized => {
+                debug!("{}", self.request);
+                warn!("Ignoring FUSE operation before init: {}", self.request);
+                self.reply::<ReplyEmpty>().error(Errno::EIO);
+                true
+            }
+            _ => self.dispatch_concurrent(fs, destroyed, notifier),
+        }
+    }
+
+    /// Run the given dispatch of this request and catch a panic unwinding out of the
+    /// filesystem. The reply is dropped while unwinding, which replies with EIO (unless the
+    /// filesystem replied before panicking). Returns false if the filesystem panicked.
+    fn catch_panic<F: FnOnce()>(&self, f: F) -> bool {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(()) => true,
+            Err(payload) => {
+                let message = match payload.downcast_ref::<&str>() {
+                    Some(message) => *message,
+                    None => match payload.downcast_ref::<String>() {
+                        Some(message) => message.as_str(),
+                        None => "Box<dyn Any>",
+                    },
+                };
+                error!(
+                    "Filesystem panicked in {} (unique {}, ino {:#018x}): {}",
+                    opcode_name(self.request.opcode()),
+                    self.request.unique(),
+                    self.request.nodeid(),
+                    message
+                );
+                false
+            }
+        }
+    }
+
+    /// Initialize the given filesystem and reply to the init request. Returns the splice
+    /// mode to use if the filesystem was initialized successfully.
+    fn init<FS: Filesystem>(&self, fs: &FS, arg: &fuse_init_in) -> Option<SpliceMode> {
+        let reply: ReplyRaw<fuse_init_out> = self.reply();
+        // We don't support ABI versions before 7.6
+        if arg.major < 7 || (arg.major == 7 && arg.minor < 6) {
+            error!("Unsupported FUSE ABI version {}.{}", arg.major, arg.minor);
+            reply.error(Errno::EPROTO);
+            return None;
+        }
+        // Requests and replies are laid out as of the lower of the kernel's and our ABI version
+        self.ch
+            .set_proto_minor(arg.minor.min(FUSE_KERNEL_MINOR_VERSION));
+        // Call filesystem init method and give it a chance to return an error and
+        // to negotiate the capabilities and limits offered by the kernel
+        let mut config = KernelConfig::new(arg.flags, arg.max_readahead);
+        if let Err(err) = fs.init(self, &mut config) {
+            reply.error(err);
+            return None;
+        }
+        // Reply with our desired version and settings. If the kernel supports a
+        // larger major version, it'll re-send a matching init message. If it
+        // supports only lower major versions, we replied with an error above.
+        let init = config.init_out();
+        debug!(
+            "INIT response: ABI {}.{}, flags {:#x}, max readahead {}, max write {}",
+            init.major, init.minor, init.flags, init.max_readahead, init.max_write
+        );
+        // Kernels before ABI 7.23 reject init replies longer than they know
+        if arg.minor < 23 {
+            reply.ok_truncated(&init, FUSE_COMPAT_22_INIT_OUT_SIZE);
+        } else {
+            reply.ok(&init);
+        }
+        Some(SpliceMode::new(
+            config.requested_capabilities(),
+            init.max_write,
+        ))
+    }
+
+    /// Call the filesystem method of a regular filesystem operation. Init and destroy
+    /// modify the session state and must be handled by the caller.
+    fn dispatch_operation<FS: Filesystem>(&self, fs: &FS, notifier: &Notifier) {
+        match self.request.operation() {
+            // Operations without a reply can't be interrupted
+            ll::Operation::Interrupt { .. } | ll::Operation::Forget { .. } => (),
+            ll::Operation::NotifyReply { .. } => (),
+            ll::Operation::BatchForget { .. } => (),
+            _ => self
+                .interrupts
+                .register(self.request.unique(), &self.interrupted),
+        }
+
+        match self.request.operation() {
+            ll::Operation::Init { .. } | ll::Operation::Destroy => {
+                unreachable!("Init and destroy must be dispatched by the session")
+            }
+
+            ll::Operation::Interrupt { arg } => {
+                // Interrupts are not replied to
+                self.interrupts.interrupt(arg.unique);
             }
 
             ll::Operation::Lookup { name } => {
-                se.filesystem
-                    .lookup(self, self.request.nodeid(), &name, self.reply());
+                fs.lookup(self, self.request.nodeid(), &name, self.reply());
             }
             ll::Operation::Forget { arg } => {
-                se.filesystem
-                    .forget(self, self.request.nodeid(), arg.nlookup); // no reply
+                fs.forget(self, self.request.nodeid(), arg.nlookup); // no reply
             }
             ll::Operation::GetAttr => {
-                se.filesystem
-                    .getattr(self, self.request.nodeid(), self.reply());
+                fs.getattr(self, self.request.nodeid(), self.reply());
             }
             ll::Operation::SetAttr { arg } => {
                 let mode = match arg.valid & FATTR_MODE {
@@ -210,7 +597,7 @@ impl<'a> Request<'a> {
                     (None, None, None, None)
                 }
                 let (crtime, chgtime, bkuptime, flags) = get_macos_setattr(arg);
-                se.filesystem.setattr(
+                fs.setattr(
                     self,
                     self.request.nodeid(),
                     mode,
@@ -230,11 +617,10 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::ReadLink => {
-                se.filesystem
-                    .readlink(self, self.request.nodeid(), self.reply());
+                fs.readlink(self, self.request.nodeid(), self.reply());
             }
             ll::Operation::MkNod { arg, name } => {
-                se.filesystem.mknod(
+                fs.mknod(
                     self,
                     self.request.nodeid(),
                     &name,
@@ -244,19 +630,16 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::MkDir { arg, name } => {
-                se.filesystem
-                    .mkdir(self, self.request.nodeid(), &name, arg.mode, self.reply());
+                fs.mkdir(self, self.request.nodeid(), &name, arg.mode, self.reply());
             }
             ll::Operation::Unlink { name } => {
-                se.filesystem
-                    .unlink(self, self.request.nodeid(), &name, self.reply());
+                fs.unlink(self, self.request.nodeid(), &name, self.reply());
             }
             ll::Operation::RmDir { name } => {
-                se.filesystem
-                    .rmdir(self, self.request.nodeid(), &name, self.reply());
+                fs.rmdir(self, self.request.nodeid(), &name, self.reply());
             }
             ll::Operation::SymLink { name, link } => {
-                se.filesystem.symlink(
+                fs.symlink(
                     self,
                     self.request.nodeid(),
                     &name,
@@ -265,17 +648,18 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Rename { arg, name, newname } => {
-                se.filesystem.rename(
+                fs.rename(
                     self,
                     self.request.nodeid(),
                     &name,
                     arg.newdir,
                     &newname,
+                    0,
                     self.reply(),
                 );
             }
             ll::Operation::Link { arg, name } => {
-                se.filesystem.link(
+                fs.link(
                     self,
                     arg.oldnodeid,
                     self.request.nodeid(),
@@ -284,11 +668,10 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Open { arg } => {
-                se.filesystem
-                    .open(self, self.request.nodeid(), arg.flags, self.reply());
+                fs.open(self, self.request.nodeid(), arg.flags, self.reply());
             }
             ll::Operation::Read { arg } => {
-                se.filesystem.read(
+                fs.read(
                     self,
                     self.request.nodeid(),
                     arg.fh,
@@ -298,19 +681,38 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::Write { arg, data } => {
-                assert!(data.len() == arg.size as usize);
-                se.filesystem.write(
-                    self,
-                    self.request.nodeid(),
-                    arg.fh,
-                    arg.offset as i64,
-                    data,
-                    arg.write_flags,
-                    self.reply(),
-                );
+                let (write_flags, flags, lock_owner) = write_args(arg);
+                let spliced = self.spliced.lock().unwrap().take();
+                if let Some(spliced) = spliced {
+                    assert!(spliced.len() == arg.size as usize);
+                    fs.write_spliced(
+                        self,
+                        self.request.nodeid(),
+                        arg.fh,
+                        arg.offset as i64,
+                        spliced,
+                        write_flags,
+                        flags,
+                        lock_owner,
+                        self.reply(),
+                    );
+                } else {
+                    assert!(data.len() == arg.size as usize);
+                    fs.write(
+                        self,
+                        self.request.nodeid(),
+                        arg.fh,
+                        arg.offset as i64,
+                        data,
+                        write_flags,
+                        flags,
+                        lock_owner,
+                        self.reply(),
+                    );
+                }
             }
             ll::Operation::Flush { arg } => {
-                se.filesystem.flush(
+                fs.flush(
                     self,
                     self.request.nodeid(),
                     arg.fh,
@@ -323,7 +725,7 @@ impl<'a> Request<'a> {
                     0 => false,
                     _ => true,
                 };
-                se.filesystem.release(
+                fs.release(
                     self,
                     self.request.nodeid(),
                     arg.fh,
@@ -338,42 +740,32 @@ impl<'a> Request<'a> {
                     0 => false,
                     _ => true,
                 };
-                se.filesystem
-                    .fsync(self, self.request.nodeid(), arg.fh, datasync, self.reply());
+                fs.fsync(self, self.request.nodeid(), arg.fh, datasync, self.reply());
             }
             ll::Operation::OpenDir { arg } => {
-                se.filesystem
-                    .opendir(self, self.request.nodeid(), arg.flags, self.reply());
+                fs.opendir(self, self.request.nodeid(), arg.flags, self.reply());
             }
             ll::Operation::ReadDir { arg } => {
-                se.filesystem.readdir(
+                fs.readdir(
                     self,
                     self.request.nodeid(),
                     arg.fh,
                     arg.offset as i64,
-                    ReplyDirectory::new(self.request.unique(), self.ch, arg.size as usize),
+                    ReplyDirectory::new(self.request.unique(), self.sender(), arg.size as usize),
                 );
             }
             ll::Operation::ReleaseDir { arg } => {
-                se.filesystem.releasedir(
-                    self,
-                    self.request.nodeid(),
-                    arg.fh,
-                    arg.flags,
-                    self.reply(),
-                );
+                fs.releasedir(self, self.request.nodeid(), arg.fh, arg.flags, self.reply());
             }
             ll::Operation::FSyncDir { arg } => {
                 let datasync = match arg.fsync_flags & 1 {
                     0 => false,
                     _ => true,
                 };
-                se.filesystem
-                    .fsyncdir(self, self.request.nodeid(), arg.fh, datasync, self.reply());
+                fs.fsyncdir(self, self.request.nodeid(), arg.fh, datasync, self.reply());
             }
             ll::Operation::StatFs => {
-                se.filesystem
-                    .statfs(self, self.request.nodeid(), self.reply());
+                fs.statfs(self, self.request.nodeid(), self.reply());
             }
             ll::Operation::SetXAttr { arg, name, value } => {
                 assert!(value.len() == arg.size as usize);
@@ -387,7 +779,7 @@ impl<'a> Request<'a> {
                 fn get_position(_arg: &fuse_setxattr_in) -> u32 {
                     0
                 }
-                se.filesystem.setxattr(
+                fs.setxattr(
                     self,
                     self.request.nodeid(),
                     name,
@@ -398,23 +790,19 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::GetXAttr { arg, name } => {
-                se.filesystem
-                    .getxattr(self, self.request.nodeid(), name, arg.size, self.reply());
+                fs.getxattr(self, self.request.nodeid(), name, arg.size, self.reply());
             }
             ll::Operation::ListXAttr { arg } => {
-                se.filesystem
-                    .listxattr(self, self.request.nodeid(), arg.size, self.reply());
+                fs.listxattr(self, self.request.nodeid(), arg.size, self.reply());
             }
             ll::Operation::RemoveXAttr { name } => {
-                se.filesystem
-                    .removexattr(self, self.request.nodeid(), name, self.reply());
+                fs.removexattr(self, self.request.nodeid(), name, self.reply());
             }
             ll::Operation::Access { arg } => {
-                se.filesystem
-                    .access(self, self.request.nodeid(), arg.mask, self.reply());
+                fs.access(self, self.request.nodeid(), arg.mask, self.reply());
             }
             ll::Operation::Create { arg, name } => {
-                se.filesystem.create(
+                fs.create(
                     self,
                     self.request.nodeid(),
                     &name,
@@ -424,7 +812,7 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::GetLk { arg } => {
-                se.filesystem.getlk(
+                fs.getlk(
                     self,
                     self.request.nodeid(),
                     arg.fh,
@@ -437,7 +825,7 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::SetLk { arg } => {
-                se.filesystem.setlk(
+                fs.setlk(
                     self,
                     self.request.nodeid(),
                     arg.fh,
@@ -451,7 +839,7 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::SetLkW { arg } => {
-                se.filesystem.setlk(
+                fs.setlk(
                     self,
                     self.request.nodeid(),
                     arg.fh,
@@ -465,7 +853,7 @@ impl<'a> Request<'a> {
                 );
             }
             ll::Operation::BMap { arg } => {
-                se.filesystem.bmap(
+                fs.bmap(
                     self,
                     self.request.nodeid(),
                     arg.blocksize,
@@ -474,45 +862,145 @@ impl<'a> Request<'a> {
                 );
             }
 
-            #[cfg(feature = "abi-7-11")]
-            ll::Operation::IoCtl { arg: _, data: _ } => {
-                // TODO: handle FUSE_IOCTL
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+            ll::Operation::IoCtl { arg, data } => {
+                let in_size = (arg.in_size as usize).min(data.len());
+                fs.ioctl(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.flags,
+                    arg.cmd,
+                    arg.arg,
+                    &data[..in_size],
+                    arg.out_size,
+                    self.reply(),
+                );
             }
-            #[cfg(feature = "abi-7-11")]
-            ll::Operation::Poll { arg: _ } => {
-                // TODO: handle FUSE_POLL
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+            ll::Operation::Poll { arg } => {
+                // The kernel only waits for a notification if it requested one
+                let ph = if arg.flags & FUSE_POLL_SCHEDULE_NOTIFY != 0 {
+                    Some(PollHandle::new(arg.kh, notifier.clone()))
+                } else {
+                    None
+                };
+                fs.poll(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    ph,
+                    arg.events,
+                    arg.flags,
+                    self.reply(),
+                );
+            }
+            ll::Operation::NotifyReply { arg, data } => {
+                // Data retrieved by a retrieve notification, sent with the notification's
+                // unique id. This isn't a request, so there's no reply.
+                let size = (arg.size as usize).min(data.len());
+                notifier.retrieve_reply(self.request.unique(), &data[..size]);
+            }
+            ll::Operation::BatchForget { nodes, .. } => {
+                fs.batch_forget(self, ForgetOne::from_nodes(nodes)); // no reply
+            }
+            ll::Operation::FAllocate { arg } => {
+                fs.fallocate(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    arg.length as i64,
+                    arg.mode as i32,
+                    self.reply(),
+                );
             }
-            #[cfg(feature = "abi-7-15")]
-            ll::Operation::NotifyReply { data: _ } => {
-                // TODO: handle FUSE_NOTIFY_REPLY
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+            ll::Operation::ReadDirPlus { arg } => {
+                fs.readdirplus(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    ReplyDirectoryPlus::new(
+                        self.request.unique(),
+                        self.sender(),
+                        arg.size as usize,
+                    ),
+                );
             }
-            #[cfg(feature = "abi-7-16")]
-            ll::Operation::BatchForget { arg: _, nodes: _ } => {
-                // TODO: handle FUSE_BATCH_FORGET
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+            ll::Operation::Rename2 { arg, name, newname } => {
+                fs.rename(
+                    self,
+                    self.request.nodeid(),
+                    name,
+                    arg.newdir,
+                    newname,
+                    arg.flags,
+                    self.reply(),
+                );
             }
-            #[cfg(feature = "abi-7-19")]
-            ll::Operation::FAllocate { arg: _ } => {
-                // TODO: handle FUSE_FALLOCATE
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+            ll::Operation::Lseek { arg } => {
+                fs.lseek(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.offset as i64,
+                    arg.whence as i32,
+                    self.reply(),
+                );
             }
-            #[cfg(feature = "abi-7-21")]
-            ll::Operation::ReadDirPlus { arg: _ } => {
-                // TODO: handle FUSE_READDIRPLUS
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+            ll::Operation::CopyFileRange { arg } => {
+                fs.copy_file_range(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh_in,
+                    arg.off_in as i64,
+                    arg.nodeid_out,
+                    arg.fh_out,
+                    arg.off_out as i64,
+                    arg.len,
+                    arg.flags as u32,
+                    self.reply(),
+                );
+            }
+            ll::Operation::SetupMapping { arg } => {
+                fs.setupmapping(
+                    self,
+                    self.request.nodeid(),
+                    arg.fh,
+                    arg.foffset,
+                    arg.len,
+                    arg.flags,
+                    arg.moffset,
+                    self.reply(),
+                );
+            }
+            ll::Operation::RemoveMapping { mappings, .. } => {
+                fs.removemapping(
+                    self,
+                    self.request.nodeid(),
+                    RemoveMappingOne::from_mappings(mappings),
+                    self.reply(),
+                );
+            }
+            ll::Operation::SyncFs { .. } => {
+                fs.syncfs(self, self.request.nodeid(), self.reply());
+            }
+            ll::Operation::TmpFile { arg } => {
+                fs.tmpfile(
+                    self,
+                    self.request.nodeid(),
+                    arg.mode,
+                    arg.flags,
+                    self.reply(),
+                );
             }
 
             #[cfg(target_os = "macos")]
             ll::Operation::SetVolName { name } => {
-                se.filesystem.setvolname(self, name, self.reply());
+                fs.setvolname(self, name, self.reply());
             }
             #[cfg(target_os = "macos")]
             ll::Operation::GetXTimes => {
-                se.filesystem
-                    .getxtimes(self, self.request.nodeid(), self.reply());
+                fs.getxtimes(self, self.request.nodeid(), self.reply());
             }
             #[cfg(target_os = "macos")]
             ll::Operation::Exchange {
@@ -520,7 +1008,7 @@ impl<'a> Request<'a> {
                 oldname,
                 newname,
             } => {
-                se.filesystem.exchange(
+                fs.exchange(
                     self,
                     arg.olddir,
                     &oldname,
@@ -531,10 +1019,9 @@ impl<'a> Request<'a> {
                 );
             }
 
-            #[cfg(feature = "abi-7-12")]
             ll::Operation::CuseInit { arg: _ } => {
                 // TODO: handle CUSE_INIT
-                self.reply::<ReplyEmpty>().error(ENOSYS);
+                self.reply::<ReplyEmpty>().error(Errno::ENOSYS);
             }
         }
     }
@@ -542,7 +1029,34 @@ impl<'a> Request<'a> {
     /// Create a reply object for this request that can be passed to the filesystem
     /// implementation and makes sure that a request is replied exactly once
     fn reply<T: Reply>(&self) -> T {
-        Reply::new(self.request.unique(), self.ch)
+        Reply::new(self.request.unique(), self.sender())
+    }
+
+    /// Create a reply sender for this request that marks the request as completed
+    fn sender(&self) -> RequestSender {
+        RequestSender {
+            ch: self.ch.clone(),
+            unique: self.request.unique(),
+            opcode: self.request.opcode(),
+            received: self.received,
+            interrupts: self.interrupts.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+
+    /// Returns true if the kernel interrupted this request. Interrupts are only noticed
+    /// while the session loop is receiving requests, i.e. if the filesystem replies from
+    /// another thread or runs in a multithreaded session.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    /// Returns a token to check whether the kernel interrupted this request, which can
+    /// be sent to another thread along with the reply.
+    pub fn interrupt_token(&self) -> InterruptToken {
+        InterruptToken {
+            interrupted: self.interrupted.clone(),
+        }
     }
 
     /// Returns the unique identifier of this request
@@ -573,3 +1087,238 @@ impl<'a> Request<'a> {
         self.request.pid()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::write_args;
+    use super::{InterruptTracker, Request};
+    use crate::fuse_abi::consts::{
+        FUSE_POLL_SCHEDULE_NOTIFY, FUSE_WRITE_CACHE, FUSE_WRITE_LOCKOWNER,
+    };
+    use crate::fuse_abi::{fuse_fallocate_in, fuse_poll_in, fuse_write_in, FUSE_ROOT_ID};
+    use crate::fuse_abi::{fuse_in_header, fuse_interrupt_in, fuse_opcode};
+    use crate::notify::{Notifier, PollHandle};
+    use crate::reply::as_bytes;
+    use crate::stats::SessionStats;
+    use crate::test_util::{channel, out_header};
+    use crate::{Errno, Filesystem, ReplyEmpty, ReplyEntry, ReplyPoll};
+    use std::ffi::OsStr;
+    use std::mem;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn interrupt_in_flight() {
+        let tracker = InterruptTracker::default();
+        let interrupted = Arc::new(AtomicBool::new(false));
+        tracker.register(1, &interrupted);
+        tracker.interrupt(1);
+        assert!(interrupted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn interrupt_before_request() {
+        let tracker = InterruptTracker::default();
+        tracker.interrupt(1);
+        // Other requests may be registered before the interrupted one
+        let other = Arc::new(AtomicBool::new(false));
+        tracker.register(3, &other);
+        let interrupted = Arc::new(AtomicBool::new(false));
+        tracker.register(1, &interrupted);
+        assert!(interrupted.load(Ordering::SeqCst));
+        assert!(!other.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn interrupt_completed_request() {
+        let tracker = InterruptTracker::default();
+        let interrupted = Arc::new(AtomicBool::new(false));
+        tracker.register(1, &interrupted);
+        tracker.complete(1);
+        tracker.interrupt(1);
+        assert!(!interrupted.load(Ordering::SeqCst));
+        assert!(tracker.state.lock().unwrap().pending.is_empty());
+    }
+
+    /// Filesystem that fails lookups with EINTR if they were interrupted
+    struct InterruptFs;
+
+    impl Filesystem for InterruptFs {
+        fn lookup(&self, req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEntry) {
+            if req.is_interrupted() {
+                reply.error(Errno::EINTR);
+            } else {
+                reply.error(Errno::ENOENT);
+            }
+        }
+    }
+
+    /// Returns a raw request with the given header fields and argument
+    fn request(opcode: fuse_opcode, unique: u64, arg: &[u8]) -> Vec<u8> {
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + arg.len()) as u32,
+            opcode: opcode as u32,
+            unique,
+            nodeid: FUSE_ROOT_ID,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+            padding: 0,
+        };
+        let mut data = as_bytes(&header, |d| d.concat());
+        data.extend_from_slice(arg);
+        data
+    }
+
+    #[test]
+    fn interrupt_on_other_channel() {
+        // Two channel clones of a multithreaded session share the interrupt tracker
+        let tracker = Arc::new(InterruptTracker::default());
+        let stats = SessionStats::default();
+        let destroyed = AtomicBool::new(false);
+        let (ch1, driver1) = channel("interrupt-1");
+        let (ch2, driver2) = channel("interrupt-2");
+        let notifier = Notifier::new(ch1.sender());
+
+        // The interrupt is received on the first clone before the lookup it refers to is
+        // received on the second one
+        let arg = fuse_interrupt_in { unique: 2 };
+        let data = request(
+            fuse_opcode::FUSE_INTERRUPT,
+            3,
+            &as_bytes(&arg, |d| d.concat()),
+        );
+        let req = Request::new(ch1.sender(), &data, &tracker, &stats)
+            .unwrap()
+            .unwrap();
+        assert!(req.dispatch_concurrent(&InterruptFs, &destroyed, &notifier));
+        let data = request(fuse_opcode::FUSE_LOOKUP, 2, b"name\0");
+        let req = Request::new(ch2.sender(), &data, &tracker, &stats)
+            .unwrap()
+            .unwrap();
+        assert!(req.dispatch_concurrent(&InterruptFs, &destroyed, &notifier));
+
+        // Only the lookup is replied to, on the clone it was received on
+        assert!(driver1.take_sent().is_empty());
+        let replies = driver2.take_sent();
+        assert_eq!(replies.len(), 1);
+        let header = out_header(&replies[0]);
+        assert_eq!(header.unique, 2);
+        assert_eq!(header.error, -libc::EINTR);
+    }
+
+    /// Filesystem that records the arguments of fallocate
+    #[derive(Default)]
+    struct FallocateFs(Mutex<Option<(u64, u64, i64, i64, i32)>>);
+
+    impl Filesystem for FallocateFs {
+        fn fallocate(
+            &self,
+            _req: &Request<'_>,
+            ino: u64,
+            fh: u64,
+            offset: i64,
+            length: i64,
+            mode: i32,
+            reply: ReplyEmpty,
+        ) {
+            *self.0.lock().unwrap() = Some((ino, fh, offset, length, mode));
+            reply.ok();
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn dispatch_fallocate() {
+        let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+        let arg = fuse_fallocate_in {
+            fh: 0x2a,
+            offset: 0x1000,
+            length: 0x2000,
+            mode: mode as u32,
+            padding: 0,
+        };
+        let data = request(
+            fuse_opcode::FUSE_FALLOCATE,
+            1,
+            &as_bytes(&arg, |d| d.concat()),
+        );
+        let (ch, driver) = channel("fallocate");
+        let notifier = Notifier::new(ch.sender());
+        let tracker = Arc::new(InterruptTracker::default());
+        let req = Request::new(ch.sender(), &data, &tracker, &SessionStats::default())
+            .unwrap()
+            .unwrap();
+        let fs = FallocateFs::default();
+        assert!(req.dispatch_concurrent(&fs, &AtomicBool::new(false), &notifier));
+        let args = fs.0.lock().unwrap().take();
+        assert_eq!(args, Some((FUSE_ROOT_ID, 0x2a, 0x1000, 0x2000, mode)));
+        assert_eq!(driver.take_sent().len(), 1);
+    }
+
+    /// Filesystem that records the kernel poll handle of poll requests
+    #[derive(Default)]
+    struct PollFs(Mutex<Vec<Option<u64>>>);
+
+    impl Filesystem for PollFs {
+        fn poll(
+            &self,
+            _req: &Request<'_>,
+            _ino: u64,
+            _fh: u64,
+            ph: Option<PollHandle>,
+            _events: u32,
+            _flags: u32,
+            reply: ReplyPoll,
+        ) {
+            self.0.lock().unwrap().push(ph.map(|ph| ph.kh()));
+            reply.poll(0);
+        }
+    }
+
+    #[test]
+    fn dispatch_poll() {
+        let fs = PollFs::default();
+        let (ch, _driver) = channel("poll");
+        let notifier = Notifier::new(ch.sender());
+        let tracker = Arc::new(InterruptTracker::default());
+        // A poll handle is only given if the kernel waits for a notification
+        for (unique, flags) in [(1, 0), (2, FUSE_POLL_SCHEDULE_NOTIFY)].iter() {
+            let mut arg: fuse_poll_in = unsafe { mem::zeroed() };
+            arg.fh = 0x2a;
+            arg.kh = 0x1234;
+            arg.flags = *flags;
+            let data = request(
+                fuse_opcode::FUSE_POLL,
+                *unique,
+                &as_bytes(&arg, |d| d.concat()),
+            );
+            let req = Request::new(ch.sender(), &data, &tracker, &SessionStats::default())
+                .unwrap()
+                .unwrap();
+            assert!(req.dispatch_concurrent(&fs, &AtomicBool::new(false), &notifier));
+        }
+        assert_eq!(*fs.0.lock().unwrap(), vec![None, Some(0x1234)]);
+    }
+
+    #[test]
+    fn write_flags() {
+        let mut arg = fuse_write_in {
+            fh: 1,
+            offset: 0,
+            size: 0,
+            write_flags: FUSE_WRITE_CACHE,
+            lock_owner: 0x1234,
+            flags: 0o2,
+            padding: 0,
+        };
+        let (write_flags, flags, lock_owner) = write_args(&arg);
+        assert!(write_flags.is_cache());
+        assert_eq!(flags, 0o2);
+        assert_eq!(lock_owner, None);
+        arg.write_flags = FUSE_WRITE_LOCKOWNER;
+        let (write_flags, _, lock_owner) = write_args(&arg);
+        assert!(!write_flags.is_cache());
+        assert_eq!(lock_owner, Some(0x1234));
+    }
+}
@@ -5,17 +5,24 @@
 //! filesystem is mounted, the session loop receives, dispatches and replies to kernel requests
 //! for filesystem operations under its mount point.
 
-use libc::{EAGAIN, EINTR, ENODEV, ENOENT};
+use libc::{EAGAIN, EINTR, ENODEV, ENOENT, ENOTCONN};
 use log::{error, info};
 #[cfg(feature = "libfuse")]
 use std::ffi::OsStr;
-use std::io;
 use std::path::{Path, PathBuf};
-use std::{fmt, ptr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::{fmt, fs, io, ptr};
 use thread_scoped::{scoped, JoinGuard};
 
-use crate::channel::{self, Channel};
-use crate::request::Request;
+use crate::capture::Recorder;
+use crate::channel::{self, Channel, ChannelClone, Mount};
+use crate::notify::Notifier;
+use crate::request::{InterruptTracker, Request};
+use crate::signal;
+use crate::splice::{Pipe, SpliceMode, SplicedData};
+use crate::stats::SessionStats;
 use crate::Filesystem;
 #[cfg(not(feature = "libfuse"))]
 use crate::MountOption;
@@ -27,7 +34,19 @@ pub const MAX_WRITE_SIZE: usize = 16 * 1024 * 1024;
 
 /// Size of the buffer for reading a request from the kernel. Since the kernel may send
 /// up to MAX_WRITE_SIZE bytes in a write request, we use that value plus some extra space.
-const BUFFER_SIZE: usize = MAX_WRITE_SIZE + 4096;
+pub(crate) const BUFFER_SIZE: usize = MAX_WRITE_SIZE + 4096;
+
+/// Reason why a session loop ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionExit {
+    /// The filesystem was unmounted externally, e.g. with `fusermount -u`
+    Unmounted,
+    /// The filesystem was unmounted with a `SessionUnmounter`
+    ShutdownRequested,
+    /// The connection to the kernel driver was aborted while the filesystem is still mounted,
+    /// e.g. through the abort file in `/sys/fs/fuse/connections`
+    ConnectionAborted,
+}
 
 /// The session data structure
 #[derive(Debug)]
@@ -44,6 +63,16 @@ pub struct Session<FS: Filesystem> {
     pub initialized: bool,
     /// True if the filesystem was destroyed (destroy operation done)
     pub destroyed: bool,
+    /// Requests in flight, for delivering interrupts
+    interrupts: Arc<InterruptTracker>,
+    /// Handle for sending notifications to the kernel driver
+    pub(crate) notifier: Notifier,
+    /// Statistics of the received requests
+    stats: SessionStats,
+    /// True if a panic in the filesystem should end the session
+    abort_on_panic: bool,
+    /// The mounted filesystem, shared with unmounters
+    state: Arc<MountState>,
 }
 
 impl<FS: Filesystem> Session<FS> {
@@ -51,14 +80,7 @@ impl<FS: Filesystem> Session<FS> {
     #[cfg(feature = "libfuse")]
     pub fn new(filesystem: FS, mountpoint: &Path, options: &[&OsStr]) -> io::Result<Session<FS>> {
         info!("Mounting {}", mountpoint.display());
-        Channel::new(mountpoint, options).map(|ch| Session {
-            filesystem,
-            ch,
-            proto_major: 0,
-            proto_minor: 0,
-            initialized: false,
-            destroyed: false,
-        })
+        Channel::new(mountpoint, options).and_then(|ch| Session::with_channel(filesystem, ch))
     }
 
     /// Create a new session by mounting the given filesystem to the given mountpoint
@@ -69,13 +91,24 @@ impl<FS: Filesystem> Session<FS> {
         options: &[MountOption],
     ) -> io::Result<Session<FS>> {
         info!("Mounting {}", mountpoint.display());
-        Channel::new2(mountpoint, options).map(|ch| Session {
+        Channel::new2(mountpoint, options).and_then(|ch| Session::with_channel(filesystem, ch))
+    }
+
+    /// Create a new session for the given filesystem that communicates through the given
+    /// channel
+    fn with_channel(filesystem: FS, ch: Channel) -> io::Result<Session<FS>> {
+        Ok(Session {
             filesystem,
+            state: Arc::new(MountState::new(&ch)?),
+            notifier: Notifier::new(ch.sender()),
             ch,
             proto_major: 0,
             proto_minor: 0,
             initialized: false,
             destroyed: false,
+            interrupts: Arc::new(InterruptTracker::default()),
+            stats: SessionStats::default(),
+            abort_on_panic: false,
         })
     }
 
@@ -84,44 +117,295 @@ impl<FS: Filesystem> Session<FS> {
         &self.ch.mountpoint()
     }
 
+    /// Returns a handle for sending notifications to the kernel driver, e.g. to invalidate
+    /// cached data. The handle can be sent to other threads and used while the session runs.
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// Returns a handle to the statistics of the requests this session receives. The handle
+    /// can be sent to other threads and read while the session runs.
+    pub fn stats(&self) -> SessionStats {
+        self.stats.clone()
+    }
+
+    /// Record all requests received and replies sent by this session with the given recorder
+    /// (see `capture`). Must be called before running the session, notifiers obtained before
+    /// aren't recorded. Splice mode is disabled while recording.
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.ch.set_recorder(recorder);
+        self.notifier = self.notifier.with_sender(self.ch.sender());
+    }
+
+    /// Returns a handle for unmounting the filesystem, which ends the session loop. The handle
+    /// can be cloned and sent to other threads.
+    pub fn unmounter(&self) -> SessionUnmounter {
+        SessionUnmounter::new(&self.state)
+    }
+
+    /// Set whether a panic in a filesystem method ends the session. By default, the request
+    /// the filesystem panicked on is replied to with EIO and the session keeps serving other
+    /// requests. If enabled, the session loop returns an error instead (and multithreaded
+    /// sessions unmount the filesystem to stop their other workers).
+    pub fn set_abort_on_panic(&mut self, abort: bool) {
+        self.abort_on_panic = abort;
+    }
+
+    /// Set the splice mode negotiated during initialization
+    pub(crate) fn set_splice(&mut self, splice: SpliceMode) {
+        self.ch.set_splice(splice);
+    }
+
     /// Run the session loop that receives kernel requests and dispatches them to method
     /// calls into the filesystem. This read-dispatch-loop is non-concurrent to prevent
     /// having multiple buffers (which take up much memory), but the filesystem methods
-    /// may run concurrent by spawning threads.
-    pub fn run(&mut self) -> io::Result<()> {
+    /// may run concurrent by spawning threads. Returns why the session ended once the
+    /// filesystem is unmounted or the connection to the kernel driver is closed.
+    pub fn run(&mut self) -> io::Result<SessionExit> {
         // Buffer for receiving requests from the kernel. Only one is allocated and
         // it is reused immediately after dispatching to conserve memory and allocations.
         let mut buffer: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+        // Pipe for receiving requests in splice read mode, which is enabled during init
+        let mut pipe: Option<Pipe> = None;
+        let mut pipe_pending = true;
         loop {
             // Read the next request from the given channel to kernel driver
             // The kernel driver makes sure that we get exactly one request per read
-            match self.ch.receive(&mut buffer) {
-                Ok(()) => match Request::new(self.ch.sender(), &buffer) {
-                    // Dispatch request
-                    Some(req) => req.dispatch(self),
-                    // Quit loop on illegal request
-                    None => break,
-                },
+            let result = match &pipe {
+                Some(pipe) => self.ch.receive_splice(&mut buffer, pipe),
+                None => self.ch.receive(&mut buffer).map(|()| 0),
+            };
+            match result {
+                Ok(payload) => {
+                    // Quit loop on corrupt request
+                    match Request::new(self.ch.sender(), &buffer, &self.interrupts, &self.stats)? {
+                        // Dispatch request
+                        Some(req) => {
+                            let req = with_payload(req, pipe.as_ref(), payload);
+                            if !req.dispatch(self) && self.abort_on_panic {
+                                return Err(filesystem_panicked());
+                            }
+                        }
+                        // Invalid request, which has been replied to with an error already
+                        None => discard_payload(pipe.as_ref(), payload),
+                    }
+                }
                 Err(err) => match err.raw_os_error() {
-                    // Operation interrupted. Accordingly to FUSE, this is safe to retry
-                    Some(ENOENT) => continue,
-                    // Interrupted system call, retry
-                    Some(EINTR) => continue,
-                    // Explicitly try again
-                    Some(EAGAIN) => continue,
                     // Filesystem was unmounted, quit the loop
                     Some(ENODEV) => break,
+                    // Retry on recoverable errors
+                    _ if is_retryable(&err) => continue,
                     // Unhandled error
                     _ => return Err(err),
                 },
             }
+            if pipe_pending && self.initialized {
+                pipe = self.ch.splice().read_pipe();
+                pipe_pending = false;
+            }
+        }
+        Ok(self.state.exit_reason())
+    }
+}
+
+/// The mounted filesystem of a session, which is shared with its unmounters
+#[derive(Debug)]
+pub(crate) struct MountState {
+    /// Handle for unmounting the filesystem
+    mount: Mount,
+    /// Set by unmounters before unmounting the filesystem
+    shutdown: AtomicBool,
+}
+
+impl MountState {
+    /// Create the state of the filesystem mounted by the given channel
+    pub(crate) fn new(ch: &Channel) -> io::Result<MountState> {
+        Ok(MountState {
+            mount: ch.mount()?,
+            shutdown: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns why the session ended after the connection to the kernel driver was closed
+    pub(crate) fn exit_reason(&self) -> SessionExit {
+        if self.shutdown.load(Ordering::SeqCst) {
+            return SessionExit::ShutdownRequested;
+        }
+        // An aborted connection leaves the mountpoint unusable until it's unmounted
+        match fs::metadata(self.mount.mountpoint()) {
+            Err(err) if err.raw_os_error() == Some(ENOTCONN) => SessionExit::ConnectionAborted,
+            _ => SessionExit::Unmounted,
         }
-        Ok(())
+    }
+}
+
+impl<FS: Filesystem + Send + Sync> Session<FS> {
+    /// Run the session loop with the given number of worker threads. Each worker receives
+    /// kernel requests on its own clone of the channel and dispatches them to method calls
+    /// into the filesystem, so a slow filesystem operation only blocks the worker that is
+    /// running it. Every worker allocates its own request buffer (of `MAX_WRITE_SIZE` plus
+    /// some extra space). The calling thread is used as one of the workers and this method
+    /// returns after all workers stopped, i.e. once the filesystem is unmounted, with the
+    /// reason why the session ended.
+    pub fn run_multithreaded(&mut self, n_threads: usize) -> io::Result<SessionExit> {
+        let mut buffer: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+        // Initialization modifies the session, so it's handled before starting any workers
+        while !self.initialized {
+            match self.ch.receive(&mut buffer) {
+                Ok(()) => {
+                    match Request::new(self.ch.sender(), &buffer, &self.interrupts, &self.stats)? {
+                        Some(req) => {
+                            if !req.dispatch(self) && self.abort_on_panic {
+                                return Err(filesystem_panicked());
+                            }
+                        }
+                        None => continue,
+                    }
+                }
+                Err(err) => match err.raw_os_error() {
+                    Some(ENODEV) => return Ok(self.state.exit_reason()),
+                    _ if is_retryable(&err) => continue,
+                    _ => return Err(err),
+                },
+            }
+        }
+
+        // Every worker receives on its own channel clone, including the one that runs
+        // on this thread. Requests must be replied to on the clone they were received on.
+        let clones = (0..n_threads.max(1))
+            .map(|_| self.ch.clone_channel())
+            .collect::<io::Result<Vec<ChannelClone>>>()?;
+        let destroyed = AtomicBool::new(false);
+        let filesystem = &self.filesystem;
+        let destroyed_ref = &destroyed;
+        let interrupts = &self.interrupts;
+        let notifier = &self.notifier;
+        let stats = &self.stats;
+        // Workers unmount the filesystem to stop the others if the session should abort
+        let abort = if self.abort_on_panic {
+            Some(&self.state.mount)
+        } else {
+            None
+        };
+        let mut clones = clones.into_iter();
+        let local = clones.next().unwrap();
+        let result = thread::scope(|scope| {
+            let workers: Vec<_> = clones
+                .map(|ch| {
+                    scope.spawn(move || {
+                        run_worker(
+                            filesystem,
+                            &ch,
+                            destroyed_ref,
+                            interrupts,
+                            notifier,
+                            stats,
+                            abort,
+                        )
+                    })
+                })
+                .collect();
+            let result = run_worker(
+                filesystem,
+                &local,
+                destroyed_ref,
+                interrupts,
+                notifier,
+                stats,
+                abort,
+            );
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|_| Err(filesystem_panicked())))
+                .fold(result, |acc, res| acc.and(res))
+        });
+        self.destroyed = destroyed.load(Ordering::SeqCst);
+        result.map(|()| self.state.exit_reason())
+    }
+}
+
+/// Session loop of a worker thread of a multithreaded session. If a mount to abort is given,
+/// a panic in the filesystem unmounts it and ends the worker with an error.
+fn run_worker<FS: Filesystem>(
+    filesystem: &FS,
+    ch: &ChannelClone,
+    destroyed: &AtomicBool,
+    interrupts: &Arc<InterruptTracker>,
+    notifier: &Notifier,
+    stats: &SessionStats,
+    abort: Option<&Mount>,
+) -> io::Result<()> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+    let pipe = ch.splice().read_pipe();
+    loop {
+        let result = match &pipe {
+            Some(pipe) => ch.receive_splice(&mut buffer, pipe),
+            None => ch.receive(&mut buffer).map(|()| 0),
+        };
+        match result {
+            Ok(payload) => match Request::new(ch.sender(), &buffer, interrupts, stats)? {
+                Some(req) => {
+                    let req = with_payload(req, pipe.as_ref(), payload);
+                    if !req.dispatch_concurrent(filesystem, destroyed, notifier) {
+                        if let Some(mount) = abort {
+                            // Other workers stop once the filesystem is unmounted
+                            if let Err(err) = mount.unmount() {
+                                let mountpoint = mount.mountpoint().display();
+                                error!("Failed to unmount {}: {}", mountpoint, err);
+                            }
+                            return Err(filesystem_panicked());
+                        }
+                    }
+                }
+                None => discard_payload(pipe.as_ref(), payload),
+            },
+            Err(err) => match err.raw_os_error() {
+                Some(ENODEV) => break,
+                _ if is_retryable(&err) => continue,
+                _ => return Err(err),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Attach the payload of a write request that was left in the pipe in splice read mode
+fn with_payload<'a>(req: Request<'a>, pipe: Option<&'a Pipe>, len: usize) -> Request<'a> {
+    match pipe {
+        Some(pipe) if len > 0 => req.with_payload(SplicedData::new(pipe, len)),
+        _ => req,
+    }
+}
+
+/// Discard the payload of an invalid write request that was left in the pipe in splice read
+/// mode, since the pipe is reused for the next request
+fn discard_payload(pipe: Option<&Pipe>, len: usize) {
+    if let Some(pipe) = pipe {
+        drop(SplicedData::new(pipe, len));
+    }
+}
+
+/// Returns the error that ends a session that aborts when the filesystem panics
+fn filesystem_panicked() -> io::Error {
+    io::Error::other("Filesystem panicked")
+}
+
+/// Returns true if receiving a request failed with an error that is safe to retry
+pub(crate) fn is_retryable(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        // Operation interrupted. Accordingly to FUSE, this is safe to retry
+        Some(ENOENT) => true,
+        // Interrupted system call, retry
+        Some(EINTR) => true,
+        // Explicitly try again
+        Some(EAGAIN) => true,
+        _ => false,
     }
 }
 
 impl<'a, FS: Filesystem + Send + 'a> Session<FS> {
-    /// Run the session loop in a background thread
+    /// Run the session loop in a background thread. Filesystems that are `'static` can use
+    /// the safe `spawn2` instead.
     /// # Safety
     ///
     /// This interface is inherently unsafe if the BackgroundSession is allowed to leak without being
@@ -131,9 +415,53 @@ impl<'a, FS: Filesystem + Send + 'a> Session<FS> {
     }
 }
 
-impl<FS: Filesystem> Drop for Session<FS> {
-    fn drop(&mut self) {
-        info!("Unmounted {}", self.mountpoint().display());
+impl<FS: Filesystem + Send + 'static> Session<FS> {
+    /// Run the session loop in a background thread. Unlike `spawn`, this is safe, since the
+    /// session is moved to the thread. The filesystem is returned by `SessionHandle::join`
+    /// once the session ended. If the thread can't be spawned, the error contains the
+    /// filesystem.
+    pub fn spawn2(self) -> Result<SessionHandle<FS>, SessionError<FS>> {
+        let mountpoint = self.mountpoint().to_path_buf();
+        let notifier = self.notifier.clone();
+        let stats = self.stats.clone();
+        let unmounter = self.unmounter();
+        // The session is passed to the thread through a shared slot, so that it can be taken
+        // back if spawning the thread fails
+        let slot = Arc::new(Mutex::new(Some(self)));
+        let thread_slot = slot.clone();
+        let spawned = thread::Builder::new()
+            .name("fuser".to_string())
+            .spawn(move || {
+                let mut se = thread_slot.lock().unwrap().take().unwrap();
+                drop(thread_slot);
+                let result = se.run();
+                // Dropping the rest of the session closes the channel
+                let Session { filesystem, .. } = se;
+                match result {
+                    Ok(_) => Ok(filesystem),
+                    Err(error) => Err(SessionError {
+                        error,
+                        filesystem: Some(filesystem),
+                    }),
+                }
+            });
+        match spawned {
+            Ok(thread) => Ok(SessionHandle {
+                mountpoint,
+                thread: Some(thread),
+                notifier,
+                stats,
+                unmounter,
+            }),
+            Err(error) => {
+                let se = slot.lock().unwrap().take().unwrap();
+                let Session { filesystem, .. } = se;
+                Err(SessionError {
+                    error,
+                    filesystem: Some(filesystem),
+                })
+            }
+        }
     }
 }
 
@@ -142,9 +470,12 @@ pub struct BackgroundSession<'a> {
     /// Path of the mounted filesystem
     pub mountpoint: PathBuf,
     /// Thread guard of the background session
-    pub guard: JoinGuard<'a, io::Result<()>>,
+    pub guard: JoinGuard<'a, io::Result<SessionExit>>,
     fuse_session: *mut libc::c_void,
     fd: libc::c_int,
+    notifier: Notifier,
+    stats: SessionStats,
+    unmounter: SessionUnmounter,
 }
 
 impl<'a> BackgroundSession<'a> {
@@ -162,6 +493,9 @@ impl<'a> BackgroundSession<'a> {
         // Take the fuse_session, so that we can unmount it
         let fuse_session = se.ch.fuse_session;
         let fd = se.ch.fd;
+        let notifier = se.notifier.clone();
+        let stats = se.stats.clone();
+        let unmounter = se.unmounter();
         se.ch.fuse_session = ptr::null_mut();
         let guard = scoped(move || {
             let mut se = se;
@@ -172,8 +506,26 @@ impl<'a> BackgroundSession<'a> {
             guard,
             fuse_session,
             fd,
+            notifier,
+            stats,
+            unmounter,
         })
     }
+
+    /// Returns a handle for sending notifications to the kernel driver
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// Returns a handle to the statistics of the session
+    pub fn stats(&self) -> SessionStats {
+        self.stats.clone()
+    }
+
+    /// Returns a handle for unmounting the filesystem before this handle is dropped
+    pub fn unmounter(&self) -> SessionUnmounter {
+        self.unmounter.clone()
+    }
 }
 
 impl<'a> Drop for BackgroundSession<'a> {
@@ -188,6 +540,171 @@ impl<'a> Drop for BackgroundSession<'a> {
     }
 }
 
+/// Error of a session running in a background thread, see `Session::spawn2`. It can be
+/// converted into the `io::Error` it contains.
+#[derive(Debug)]
+pub struct SessionError<FS> {
+    /// The error that ended the session, or prevented running it
+    pub error: io::Error,
+    /// The filesystem, unless it was lost because the session thread panicked or the session
+    /// is still running
+    pub filesystem: Option<FS>,
+}
+
+impl<FS> fmt::Display for SessionError<FS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<FS: fmt::Debug> std::error::Error for SessionError<FS> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<FS> From<SessionError<FS>> for io::Error {
+    fn from(err: SessionError<FS>) -> io::Error {
+        err.error
+    }
+}
+
+/// Handle of a session running in a background thread, see `Session::spawn2`. If the handle
+/// is dropped, the filesystem is unmounted and the session thread is joined.
+#[derive(Debug)]
+pub struct SessionHandle<FS> {
+    mountpoint: PathBuf,
+    thread: Option<JoinHandle<Result<FS, SessionError<FS>>>>,
+    notifier: Notifier,
+    stats: SessionStats,
+    unmounter: SessionUnmounter,
+}
+
+impl<FS> SessionHandle<FS> {
+    /// Returns the path of the mounted filesystem
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Returns a handle for sending notifications to the kernel driver
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    /// Returns a handle to the statistics of the session
+    pub fn stats(&self) -> SessionStats {
+        self.stats.clone()
+    }
+
+    /// Returns a handle for unmounting the filesystem, e.g. from another thread while
+    /// waiting in `join`
+    pub fn unmounter(&self) -> SessionUnmounter {
+        self.unmounter.clone()
+    }
+
+    /// Wait for the session to end, i.e. until the filesystem is unmounted, and return the
+    /// filesystem. Fails with the error that ended the session loop, which contains the
+    /// filesystem unless the session thread panicked.
+    pub fn join(mut self) -> Result<FS, SessionError<FS>> {
+        let thread = self.thread.take().unwrap();
+        match thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(SessionError {
+                error: io::Error::other("Session thread panicked"),
+                filesystem: None,
+            }),
+        }
+    }
+
+    /// Unmount the filesystem, wait for the session to end and return the filesystem
+    pub fn unmount_and_join(self) -> Result<FS, SessionError<FS>> {
+        if let Err(error) = self.unmount() {
+            return Err(SessionError {
+                error,
+                filesystem: None,
+            });
+        }
+        self.join()
+    }
+
+    /// Unmount the filesystem, unless the session has ended already (e.g. because the
+    /// filesystem was unmounted externally)
+    fn unmount(&self) -> io::Result<()> {
+        match self.unmounter.unmount() {
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(()),
+            result => result,
+        }
+    }
+}
+
+impl<FS> Drop for SessionHandle<FS> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            match self.unmount() {
+                // Unmounting ends the session loop and the thread
+                Ok(()) => drop(thread.join()),
+                Err(err) => error!("Failed to unmount {}: {}", self.mountpoint.display(), err),
+            }
+        }
+    }
+}
+
+/// Handle for unmounting the filesystem of a session from any thread, which ends the session
+/// loop with `SessionExit::ShutdownRequested`. It can be obtained from a `Session` or
+/// `BackgroundSession`, cloned and sent to other threads.
+#[derive(Clone, Debug)]
+pub struct SessionUnmounter {
+    mountpoint: PathBuf,
+    state: Weak<MountState>,
+}
+
+impl SessionUnmounter {
+    /// Create an unmounter for the given mounted filesystem of a session. It doesn't keep the
+    /// session's connection to the kernel driver open after the session ended.
+    pub(crate) fn new(state: &Arc<MountState>) -> SessionUnmounter {
+        SessionUnmounter {
+            mountpoint: state.mount.mountpoint().to_path_buf(),
+            state: Arc::downgrade(state),
+        }
+    }
+
+    /// Returns the path of the mounted filesystem
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Returns true until the session has ended
+    pub(crate) fn is_active(&self) -> bool {
+        self.state.strong_count() > 0
+    }
+
+    /// Unmount the filesystem. The session loop ends once the kernel driver released the
+    /// filesystem, i.e. after files that are still open on the lazily unmounted filesystem
+    /// are closed. Fails with `ErrorKind::NotConnected` if the session has ended or the
+    /// filesystem isn't mounted anymore.
+    pub fn unmount(&self) -> io::Result<()> {
+        let state = self
+            .state
+            .upgrade()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Session has ended"))?;
+        info!("Unmounting {}", self.mountpoint.display());
+        state.shutdown.store(true, Ordering::SeqCst);
+        let result = state.mount.unmount();
+        if result.is_err() {
+            state.shutdown.store(false, Ordering::SeqCst);
+        }
+        result
+    }
+
+    /// Unmount the filesystem on the next SIGINT, SIGTERM or SIGHUP, e.g. to stop a
+    /// foreground session with Ctrl-C. This installs handlers for these signals, which are
+    /// shared by all sessions unmounted on signals. After the first signal, all these
+    /// sessions are unmounted and the default handling of the signals is restored.
+    pub fn unmount_on_signals(&self) -> io::Result<()> {
+        signal::unmount_on_signals(self.clone())
+    }
+}
+
 // replace with #[derive(Debug)] if Debug ever gets implemented for
 // thread_scoped::JoinGuard
 impl<'a> fmt::Debug for BackgroundSession<'a> {
@@ -199,3 +716,229 @@ impl<'a> fmt::Debug for BackgroundSession<'a> {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Session;
+    #[cfg(not(feature = "libfuse"))]
+    use super::SessionExit;
+    use crate::channel::Channel;
+    use crate::fuse_abi::*;
+    use crate::reply::as_bytes;
+    #[cfg(not(feature = "libfuse"))]
+    use crate::test_util::{mount, temp_dir};
+    #[cfg(not(feature = "libfuse"))]
+    use crate::KernelConfig;
+    use crate::{Errno, Filesystem, ReplyEntry, Request};
+    use libc::{c_int, c_void};
+    #[cfg(not(feature = "libfuse"))]
+    use std::ffi::CString;
+    use std::ffi::OsStr;
+    #[cfg(not(feature = "libfuse"))]
+    use std::fs;
+    #[cfg(not(feature = "libfuse"))]
+    use std::os::unix::ffi::OsStrExt;
+    #[cfg(not(feature = "libfuse"))]
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Barrier;
+    use std::{io, mem, thread};
+
+    /// Filesystem that replies to lookups only once the given number of lookups are running
+    struct BarrierFs(Barrier);
+
+    impl Filesystem for BarrierFs {
+        fn lookup(&self, _req: &Request<'_>, _parent: u64, _name: &OsStr, reply: ReplyEntry) {
+            self.0.wait();
+            reply.error(Errno::ENOENT);
+        }
+    }
+
+    /// Send a raw request to the session through the given socket
+    fn send(fd: c_int, opcode: fuse_opcode, unique: u64, arg: &[u8]) {
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + arg.len()) as u32,
+            opcode: opcode as u32,
+            unique,
+            nodeid: FUSE_ROOT_ID,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+            padding: 0,
+        };
+        let mut data = as_bytes(&header, |d| d.concat());
+        data.extend_from_slice(arg);
+        let rc = unsafe { libc::write(fd, data.as_ptr() as *const c_void, data.len()) };
+        assert_eq!(rc, data.len() as isize);
+    }
+
+    /// Receive the header of a reply from the session through the given socket
+    fn receive(fd: c_int) -> fuse_out_header {
+        let mut data = vec![0u8; 4096];
+        let rc = unsafe { libc::read(fd, data.as_mut_ptr() as *mut c_void, data.len()) };
+        assert!(rc >= mem::size_of::<fuse_out_header>() as isize, "No reply");
+        unsafe { (data.as_ptr() as *const fuse_out_header).read_unaligned() }
+    }
+
+    #[test]
+    fn multithreaded() {
+        const WORKERS: usize = 4;
+        // A socket stands in for the kernel driver. It can't be cloned like a FUSE device,
+        // so the workers receive on duplicates of it.
+        let mut fds = [0; 2];
+        let rc =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+        assert_eq!(rc, 0);
+        let (driver, device) = (fds[0], fds[1]);
+        let timeout = libc::timeval {
+            tv_sec: 10,
+            tv_usec: 0,
+        };
+        unsafe {
+            libc::setsockopt(
+                driver,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            );
+        }
+        // The mountpoint doesn't exist, so that dropping the channel doesn't unmount anything
+        let mountpoint = std::env::temp_dir().join("fuser-multithreaded-not-mounted");
+        let ch = Channel::from_fd(&mountpoint, device);
+        let session = thread::spawn(move || {
+            let mut se = Session::with_channel(BarrierFs(Barrier::new(WORKERS)), ch).unwrap();
+            se.run_multithreaded(WORKERS)
+        });
+
+        let mut init: fuse_init_in = unsafe { mem::zeroed() };
+        init.major = FUSE_KERNEL_VERSION;
+        init.minor = FUSE_KERNEL_MINOR_VERSION;
+        send(
+            driver,
+            fuse_opcode::FUSE_INIT,
+            1,
+            as_bytes(&init, |d| d.concat()).as_slice(),
+        );
+        assert_eq!(receive(driver).error, 0);
+
+        // Every lookup blocks until all of them are running, one on each worker
+        for unique in 2..2 + WORKERS as u64 {
+            send(driver, fuse_opcode::FUSE_LOOKUP, unique, b"name\0");
+        }
+        let mut uniques: Vec<u64> = (0..WORKERS)
+            .map(|_| {
+                let reply = receive(driver);
+                assert_eq!(reply.error, -libc::ENOENT);
+                reply.unique
+            })
+            .collect();
+        uniques.sort_unstable();
+        assert_eq!(uniques, (2..2 + WORKERS as u64).collect::<Vec<_>>());
+
+        // The fake driver hangs up instead of unmounting, which ends the workers with an error
+        unsafe { libc::close(driver) };
+        let err = session.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[cfg(not(feature = "libfuse"))]
+    fn unmounter() {
+        let mut se = match mount("unmounter") {
+            Some(se) => se,
+            None => return,
+        };
+        let mountpoint = se.mountpoint().to_path_buf();
+        let unmounter = se.unmounter();
+        let session = thread::spawn(move || se.run());
+        unmounter.unmount().unwrap();
+        let exit = session.join().unwrap().unwrap();
+        assert_eq!(exit, SessionExit::ShutdownRequested);
+        // The session ended, so its mountpoint isn't unmounted again
+        assert!(!unmounter.is_active());
+        let err = unmounter.unmount().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        fs::remove_dir(&mountpoint).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "libfuse"))]
+    fn unmounter_after_external_unmount() {
+        let mut se = match mount("unmounter-external") {
+            Some(se) => se,
+            None => return,
+        };
+        let mountpoint = se.mountpoint().to_path_buf();
+        let unmounter = se.unmounter();
+        let mnt = CString::new(mountpoint.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::umount2(mnt.as_ptr(), 0) }, 0);
+        // The session didn't end yet, but the unmounter sees that the filesystem is gone
+        assert!(unmounter.is_active());
+        let err = unmounter.unmount().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        assert_eq!(se.run().unwrap(), SessionExit::Unmounted);
+        drop(se);
+        fs::remove_dir(&mountpoint).unwrap();
+    }
+
+    /// Filesystem that records whether it was initialized
+    #[cfg(not(feature = "libfuse"))]
+    #[derive(Debug, Default)]
+    struct InitFs(AtomicBool);
+
+    #[cfg(not(feature = "libfuse"))]
+    impl Filesystem for InitFs {
+        fn init(&self, _req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), Errno> {
+            self.0.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "libfuse"))]
+    fn spawn_mount2() {
+        let mountpoint = temp_dir("spawn-mount2");
+        let handle = match crate::spawn_mount2(InitFs::default(), &mountpoint, &[]) {
+            Ok(handle) => handle,
+            Err(_) => return fs::remove_dir(&mountpoint).unwrap(),
+        };
+        assert_eq!(handle.mountpoint(), mountpoint.canonicalize().unwrap());
+        // Any access to the filesystem waits until the session replied to init
+        let _ = fs::metadata(&mountpoint);
+        let filesystem = handle.unmount_and_join().unwrap();
+        assert!(filesystem.0.load(Ordering::SeqCst));
+        fs::remove_dir(&mountpoint).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "libfuse"))]
+    fn session_handle_drop() {
+        let handle = match mount("session-handle-drop") {
+            Some(se) => se.spawn2().unwrap(),
+            None => return,
+        };
+        let mountpoint = handle.mountpoint().to_path_buf();
+        let unmounter = handle.unmounter();
+        // Dropping the handle unmounts the filesystem and waits for the session to end
+        drop(handle);
+        assert!(!unmounter.is_active());
+        let err = unmounter.unmount().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        fs::remove_dir(&mountpoint).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "libfuse"))]
+    fn session_handle_external_unmount() {
+        let handle = match mount("session-handle-external") {
+            Some(se) => se.spawn2().unwrap(),
+            None => return,
+        };
+        let mountpoint = handle.mountpoint().to_path_buf();
+        let mnt = CString::new(mountpoint.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::umount2(mnt.as_ptr(), 0) }, 0);
+        // The session ends by itself, which isn't an error when unmounting it
+        assert!(handle.unmount_and_join().is_ok());
+        fs::remove_dir(&mountpoint).unwrap();
+    }
+}
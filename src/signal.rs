@@ -0,0 +1,167 @@
+//! Unmounting on signals
+//!
+//! Signal handlers may only do very little safely, so the handler installed here just writes
+//! the signal number to a pipe. A helper thread reads it and unmounts the registered sessions.
+
+use libc::{c_int, c_void};
+use log::{error, info};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::{io, mem, ptr, thread};
+
+use crate::session::SessionUnmounter;
+
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+use libc::__errno as errno_location;
+#[cfg(target_os = "linux")]
+use libc::__errno_location as errno_location;
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "dragonfly"))]
+use libc::__error as errno_location;
+
+/// Signals that unmount the registered sessions
+const SIGNALS: [c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// Write end of the pipe to the helper thread, or -1 if no handlers are installed
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Sessions to unmount on the next signal
+static UNMOUNTERS: Mutex<Vec<SessionUnmounter>> = Mutex::new(Vec::new());
+
+/// Signal handler that passes the signal on to the helper thread
+extern "C" fn handle_signal(signal: c_int) {
+    // The interrupted code may check errno after the handler returned, so the write must
+    // not change it
+    let errno = unsafe { *errno_location() };
+    let byte = signal as u8;
+    unsafe {
+        libc::write(
+            PIPE.load(Ordering::SeqCst),
+            &byte as *const u8 as *const c_void,
+            1,
+        );
+        *errno_location() = errno;
+    }
+}
+
+/// Unmount the session of the given unmounter on the next SIGINT, SIGTERM or SIGHUP. The
+/// signal handlers are installed with the first registered session. Once a signal arrived,
+/// the default signal handling is restored and all registered sessions are unmounted.
+pub(crate) fn unmount_on_signals(unmounter: SessionUnmounter) -> io::Result<()> {
+    let mut unmounters = UNMOUNTERS.lock().unwrap();
+    if PIPE.load(Ordering::SeqCst) < 0 {
+        install()?;
+    }
+    // Sessions that ended in the meantime don't need to be unmounted anymore
+    unmounters.retain(SessionUnmounter::is_active);
+    unmounters.push(unmounter);
+    Ok(())
+}
+
+/// Create the pipe and the helper thread and install the signal handlers
+fn install() -> io::Result<()> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+    let spawned = thread::Builder::new()
+        .name("fuser-signals".to_string())
+        .spawn(move || wait_for_signal(read_fd, write_fd));
+    if let Err(err) = spawned {
+        close_pipe(read_fd, write_fd);
+        return Err(err);
+    }
+    PIPE.store(write_fd, Ordering::SeqCst);
+    set_handlers(handle_signal as extern "C" fn(c_int) as libc::sighandler_t)
+}
+
+/// Set the handler of all signals that unmount the registered sessions
+fn set_handlers(handler: libc::sighandler_t) -> io::Result<()> {
+    for &signal in SIGNALS.iter() {
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = handler;
+        // The session loop continues receiving requests after the handler returned
+        action.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut action.sa_mask) };
+        if unsafe { libc::sigaction(signal, &action, ptr::null_mut()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Helper thread that waits for the first signal and unmounts the registered sessions then
+fn wait_for_signal(read_fd: c_int, write_fd: c_int) {
+    let mut byte = 0u8;
+    loop {
+        let rc = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut c_void, 1) };
+        if rc == 1 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if rc < 0 && err.kind() == io::ErrorKind::Interrupted {
+            continue;
+        }
+        error!("Failed to wait for signals: {}", err);
+        return;
+    }
+    info!("Received signal {}, unmounting", byte);
+    let mut unmounters = UNMOUNTERS.lock().unwrap();
+    // Another signal terminates the process as usual, e.g. if unmounting hangs
+    if let Err(err) = set_handlers(libc::SIG_DFL) {
+        error!("Failed to restore signal handlers: {}", err);
+    }
+    PIPE.store(-1, Ordering::SeqCst);
+    close_pipe(read_fd, write_fd);
+    for unmounter in unmounters.drain(..).filter(SessionUnmounter::is_active) {
+        if let Err(err) = unmounter.unmount() {
+            error!(
+                "Failed to unmount {}: {}",
+                unmounter.mountpoint().display(),
+                err
+            );
+        }
+    }
+}
+
+fn close_pipe(read_fd: c_int, write_fd: c_int) {
+    unsafe {
+        libc::close(read_fd);
+        libc::close(write_fd);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(not(feature = "libfuse"))]
+    #[test]
+    fn unmount_on_signals() {
+        use super::UNMOUNTERS;
+        use crate::test_util::mount;
+        use crate::SessionExit;
+        use std::{fs, mem, ptr, thread};
+
+        let (mut se, ended) = match (mount("signals"), mount("signals-ended")) {
+            (Some(se), Some(ended)) => (se, ended),
+            _ => return,
+        };
+        let mountpoint = se.mountpoint().to_path_buf();
+        let ended_mountpoint = ended.mountpoint().to_path_buf();
+        ended.unmounter().unmount_on_signals().unwrap();
+        drop(ended);
+        // Unmounters of sessions that ended are dropped when another one is registered
+        se.unmounter().unmount_on_signals().unwrap();
+        assert_eq!(UNMOUNTERS.lock().unwrap().len(), 1);
+
+        let session = thread::spawn(move || se.run());
+        assert_eq!(unsafe { libc::raise(libc::SIGTERM) }, 0);
+        let exit = session.join().unwrap().unwrap();
+        assert_eq!(exit, SessionExit::ShutdownRequested);
+        assert!(UNMOUNTERS.lock().unwrap().is_empty());
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        unsafe { libc::sigaction(libc::SIGTERM, ptr::null(), &mut action) };
+        assert_eq!(action.sa_sigaction, libc::SIG_DFL);
+        fs::remove_dir(&mountpoint).unwrap();
+        fs::remove_dir(&ended_mountpoint).unwrap();
+    }
+}
@@ -0,0 +1,471 @@
+//! Zero-copy data transfer with splice
+//!
+//! In splice read mode (FUSE_SPLICE_READ), requests are moved from the kernel driver into a
+//! pipe and only their headers and arguments are copied into the request buffer. The payload
+//! of write requests stays in the pipe and can be spliced to another file without copying it
+//! through userspace. In splice write mode (FUSE_SPLICE_WRITE), data replies that are read
+//! from a file are moved to the kernel driver through a pipe the same way. Splicing is only
+//! supported on Linux.
+
+use libc::{c_int, c_void};
+use log::{error, warn};
+use std::cell::RefCell;
+use std::os::unix::io::RawFd;
+use std::{fmt, io, mem};
+
+#[cfg(target_os = "linux")]
+use crate::fuse_abi::consts::{FUSE_SPLICE_MOVE, FUSE_SPLICE_READ, FUSE_SPLICE_WRITE};
+use crate::fuse_abi::{fuse_in_header, fuse_opcode, fuse_out_header, fuse_write_in};
+use crate::reply::as_bytes;
+
+/// Splice mode of a channel, enabled by requesting the FUSE_SPLICE_* capabilities during init
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SpliceMode {
+    /// Receive requests through a pipe
+    read: bool,
+    /// Send data replies from files through a pipe
+    write: bool,
+    /// Flags for splicing, i.e. SPLICE_F_MOVE if pages should be moved instead of copied
+    flags: u32,
+    /// Size of the pipe for receiving requests
+    pipe_size: usize,
+}
+
+impl SpliceMode {
+    /// Returns the splice mode for the given capabilities and maximum size of writes
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(capabilities: u32, max_write: u32) -> SpliceMode {
+        SpliceMode {
+            read: capabilities & FUSE_SPLICE_READ != 0,
+            write: capabilities & FUSE_SPLICE_WRITE != 0,
+            flags: match capabilities & FUSE_SPLICE_MOVE {
+                0 => 0,
+                _ => libc::SPLICE_F_MOVE,
+            },
+            // Write requests have a page of headers and arguments in front of the data
+            pipe_size: max_write as usize + 4096,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn new(_capabilities: u32, _max_write: u32) -> SpliceMode {
+        SpliceMode::default()
+    }
+
+    /// Returns a new pipe for receiving requests in splice read mode. Returns None if splice
+    /// read mode isn't enabled or if no pipe of the required size can be created.
+    pub(crate) fn read_pipe(&self) -> Option<Pipe> {
+        if !self.read {
+            return None;
+        }
+        Pipe::new(self.pipe_size)
+            .map_err(|err| warn!("Failed to create pipe for splicing, copying data: {}", err))
+            .ok()
+    }
+
+    /// Returns the flags for sending replies with splice, or None if splice write mode isn't
+    /// enabled
+    pub(crate) fn write_flags(&self) -> Option<u32> {
+        if self.write {
+            Some(self.flags)
+        } else {
+            None
+        }
+    }
+}
+
+/// A pipe for moving data with splice
+pub(crate) struct Pipe {
+    read_fd: c_int,
+    write_fd: c_int,
+    size: usize,
+}
+
+impl Pipe {
+    /// Create a new pipe that can hold at least the given number of bytes
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(size: usize) -> io::Result<Pipe> {
+        let mut fds: [c_int; 2] = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut pipe = Pipe {
+            read_fd: fds[0],
+            write_fd: fds[1],
+            size: 0,
+        };
+        let current = unsafe { libc::fcntl(pipe.write_fd, libc::F_GETPIPE_SZ) };
+        if current < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        pipe.size = current as usize;
+        if pipe.size < size {
+            // The size is rounded up by the kernel. Unprivileged processes can't exceed
+            // /proc/sys/fs/pipe-max-size.
+            let rc = unsafe { libc::fcntl(pipe.write_fd, libc::F_SETPIPE_SZ, size as c_int) };
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            pipe.size = rc as usize;
+        }
+        Ok(pipe)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn new(_size: usize) -> io::Result<Pipe> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+
+    /// Receive a request from the kernel driver via this pipe. The header and arguments of the
+    /// request are read into the given buffer. Returns the length of the payload of a write
+    /// request, which is left in the pipe. In that case, the length in the header is adjusted
+    /// to the data in the buffer, so that the request can be parsed without its payload.
+    pub(crate) fn receive(&self, fd: c_int, buffer: &mut Vec<u8>) -> io::Result<usize> {
+        let len = splice(fd, None, self.write_fd, None, self.size, 0)?;
+        let header_len = mem::size_of::<fuse_in_header>();
+        let write_len = header_len + mem::size_of::<fuse_write_in>();
+        buffer.clear();
+        self.read(buffer, len.min(header_len))?;
+        if len >= write_len && buffer[4..8] == (fuse_opcode::FUSE_WRITE as u32).to_ne_bytes() {
+            self.read(buffer, write_len - header_len)?;
+            buffer[0..4].copy_from_slice(&(write_len as u32).to_ne_bytes());
+            Ok(len - write_len)
+        } else {
+            self.read(buffer, len - buffer.len())?;
+            Ok(0)
+        }
+    }
+
+    /// Read exactly the given number of bytes from the pipe and append them to the buffer
+    fn read(&self, buffer: &mut Vec<u8>, count: usize) -> io::Result<()> {
+        buffer.reserve(count);
+        let end = buffer.len() + count;
+        while buffer.len() < end {
+            let rc = unsafe {
+                libc::read(
+                    self.read_fd,
+                    buffer.as_mut_ptr().add(buffer.len()) as *mut c_void,
+                    end - buffer.len(),
+                )
+            };
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if rc == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            unsafe { buffer.set_len(buffer.len() + rc as usize) };
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pipe {{ size: {} }}", self.size)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+/// Payload of a write request that was received in splice read mode. The data is still in
+/// the pipe it was received with, and can be moved to a file without copying it with
+/// `splice_to` or be read into memory with `read_to_vec`. Data that isn't consumed is
+/// discarded when this is dropped.
+pub struct SplicedData<'a> {
+    pipe: &'a Pipe,
+    len: usize,
+}
+
+impl<'a> SplicedData<'a> {
+    pub(crate) fn new(pipe: &'a Pipe, len: usize) -> SplicedData<'a> {
+        SplicedData { pipe, len }
+    }
+
+    /// Returns the number of bytes that are left in the pipe
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if all data has been consumed
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Move the data to the given file at the given offset, or at the file's current position
+    /// if offset is None. Returns the number of bytes moved, which is less than the length of
+    /// the data only if the file doesn't accept more (e.g. if the disk is full).
+    pub fn splice_to(&mut self, fd: RawFd, offset: Option<i64>) -> io::Result<usize> {
+        let mut offset = offset;
+        let mut moved = 0;
+        while self.len > 0 {
+            let n = splice(self.pipe.read_fd, None, fd, offset.as_mut(), self.len, 0)?;
+            if n == 0 {
+                break;
+            }
+            self.len -= n;
+            moved += n;
+        }
+        Ok(moved)
+    }
+
+    /// Read the data into memory
+    pub fn read_to_vec(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.len);
+        self.pipe.read(&mut data, self.len)?;
+        self.len = 0;
+        Ok(data)
+    }
+}
+
+impl<'a> fmt::Debug for SplicedData<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SplicedData {{ len: {} }}", self.len)
+    }
+}
+
+impl<'a> Drop for SplicedData<'a> {
+    fn drop(&mut self) {
+        // The pipe is reused for the next request, so unconsumed data must be discarded
+        if self.len > 0 {
+            if let Err(err) = self.read_to_vec() {
+                error!("Failed to discard spliced data: {}", err);
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// Pipe for sending replies in splice write mode, one per thread since replies can be
+    /// sent from any thread
+    static REPLY_PIPE: RefCell<Option<Pipe>> = const { RefCell::new(None) };
+}
+
+/// Send a successful reply with len bytes of data read from the given file at the given offset
+/// to the kernel driver, moving the data through a pipe. Returns the number of bytes sent, or
+/// None if the reply couldn't be spliced (e.g. if no pipe of the required size can be created),
+/// nothing has been sent then. Fails without sending anything if the data can't be read from
+/// the file.
+pub(crate) fn send_from_fd(
+    ch_fd: c_int,
+    unique: u64,
+    fd: RawFd,
+    offset: i64,
+    len: usize,
+    flags: u32,
+) -> io::Result<Option<usize>> {
+    let header_len = mem::size_of::<fuse_out_header>();
+    REPLY_PIPE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        if !matches!(cell.as_ref(), Some(pipe) if pipe.size >= header_len + len) {
+            *cell = match Pipe::new(header_len + len) {
+                Ok(pipe) => Some(pipe),
+                Err(err) => {
+                    warn!("Failed to create pipe for splicing, copying data: {}", err);
+                    return Ok(None);
+                }
+            };
+        }
+        let pipe = cell.as_ref().unwrap();
+        let mut header = fuse_out_header {
+            len: (header_len + len) as u32,
+            error: 0,
+            unique,
+        };
+        let rc = as_bytes(&header, |bytes| unsafe {
+            libc::write(
+                pipe.write_fd,
+                bytes[0].as_ptr() as *const c_void,
+                header_len,
+            )
+        });
+        if rc != header_len as isize {
+            *cell = None;
+            return Ok(None);
+        }
+        let mut spliced = 0;
+        let mut offset = offset;
+        while spliced < len {
+            match splice(
+                fd,
+                Some(&mut offset),
+                pipe.write_fd,
+                None,
+                len - spliced,
+                flags,
+            ) {
+                Ok(0) => break,
+                Ok(n) => spliced += n,
+                Err(err) => {
+                    // The pipe still contains the header
+                    *cell = None;
+                    return Err(err);
+                }
+            }
+        }
+        if spliced < len {
+            // End of file, the header's length doesn't match the data anymore. Read the
+            // data back from the pipe and send it with an adjusted header.
+            let mut data = Vec::new();
+            pipe.read(&mut data, header_len + spliced)?;
+            header.len = (header_len + spliced) as u32;
+            as_bytes(&header, |bytes| {
+                data[..header_len].copy_from_slice(bytes[0])
+            });
+            let rc = unsafe { libc::write(ch_fd, data.as_ptr() as *const c_void, data.len()) };
+            if rc < 0 {
+                error!("Failed to send FUSE reply: {}", io::Error::last_os_error());
+            }
+            return Ok(Some(spliced));
+        }
+        match splice(pipe.read_fd, None, ch_fd, None, header_len + len, flags) {
+            Ok(n) if n == header_len + len => (),
+            Ok(_) => {
+                error!("Failed to send FUSE reply: short splice");
+                *cell = None;
+            }
+            Err(err) => {
+                error!("Failed to send FUSE reply: {}", err);
+                *cell = None;
+            }
+        }
+        Ok(Some(len))
+    })
+}
+
+/// Move up to len bytes from one file descriptor to another, one of which must be a pipe.
+/// Offsets are used for (and updated on) the side that is not a pipe.
+#[cfg(target_os = "linux")]
+fn splice(
+    fd_in: c_int,
+    off_in: Option<&mut i64>,
+    fd_out: c_int,
+    off_out: Option<&mut i64>,
+    len: usize,
+    flags: u32,
+) -> io::Result<usize> {
+    let off_in = off_in.map_or(std::ptr::null_mut(), |offset| offset as *mut i64);
+    let off_out = off_out.map_or(std::ptr::null_mut(), |offset| offset as *mut i64);
+    let rc = unsafe { libc::splice(fd_in, off_in, fd_out, off_out, len, flags) };
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(rc as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn splice(
+    _fd_in: c_int,
+    _off_in: Option<&mut i64>,
+    _fd_out: c_int,
+    _off_out: Option<&mut i64>,
+    _len: usize,
+    _flags: u32,
+) -> io::Result<usize> {
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::{send_from_fd, Pipe, SplicedData};
+    use crate::fuse_abi::{fuse_in_header, fuse_opcode, fuse_out_header, fuse_write_in};
+    use crate::test_util::temp_path;
+    use libc::c_void;
+    use std::fs::File;
+    use std::io::Read;
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    fn write_all(fd: i32, data: &[u8]) {
+        let rc = unsafe { libc::write(fd, data.as_ptr() as *const c_void, data.len()) };
+        assert_eq!(rc, data.len() as isize);
+    }
+
+    #[test]
+    fn receive_write() {
+        // A pipe stands in for the kernel driver
+        let device = Pipe::new(4096).unwrap();
+        let args_len = mem::size_of::<fuse_in_header>() + mem::size_of::<fuse_write_in>();
+        let mut request = vec![0; args_len];
+        request[0..4].copy_from_slice(&(args_len as u32 + 5).to_ne_bytes());
+        request[4..8].copy_from_slice(&(fuse_opcode::FUSE_WRITE as u32).to_ne_bytes());
+        request.extend_from_slice(b"hello");
+        write_all(device.write_fd, &request);
+
+        let pipe = Pipe::new(4096).unwrap();
+        let mut buffer = Vec::new();
+        assert_eq!(pipe.receive(device.read_fd, &mut buffer).unwrap(), 5);
+        assert_eq!(buffer.len(), args_len);
+        assert_eq!(buffer[0..4], (args_len as u32).to_ne_bytes());
+
+        let path = temp_path("splice-write");
+        let file = File::create(&path).unwrap();
+        let mut data = SplicedData::new(&pipe, 5);
+        assert_eq!(data.splice_to(file.as_raw_fd(), Some(2)).unwrap(), 5);
+        assert!(data.is_empty());
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, b"\0\0hello");
+    }
+
+    #[test]
+    fn receive_other() {
+        let device = Pipe::new(4096).unwrap();
+        let header_len = mem::size_of::<fuse_in_header>();
+        let mut request = vec![0; header_len];
+        request[0..4].copy_from_slice(&(header_len as u32 + 6).to_ne_bytes());
+        request[4..8].copy_from_slice(&(fuse_opcode::FUSE_LOOKUP as u32).to_ne_bytes());
+        request.extend_from_slice(b"hello\0");
+        write_all(device.write_fd, &request);
+
+        let pipe = Pipe::new(4096).unwrap();
+        let mut buffer = Vec::new();
+        assert_eq!(pipe.receive(device.read_fd, &mut buffer).unwrap(), 0);
+        assert_eq!(buffer, request);
+    }
+
+    #[test]
+    fn send_file() {
+        let path = temp_path("splice-read");
+        std::fs::write(&path, b"hello").unwrap();
+        let mut file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header_len = mem::size_of::<fuse_out_header>();
+
+        // A pipe stands in for the kernel driver
+        let device = Pipe::new(4096).unwrap();
+        let fd = file.as_raw_fd();
+        assert_eq!(
+            send_from_fd(device.write_fd, 0xdeadbeef, fd, 1, 3, 0).unwrap(),
+            Some(3)
+        );
+        let mut reply = Vec::new();
+        device.read(&mut reply, header_len + 3).unwrap();
+        assert_eq!(reply[0..4], (header_len as u32 + 3).to_ne_bytes());
+        assert_eq!(reply[8..16], 0xdeadbeef_u64.to_ne_bytes());
+        assert_eq!(&reply[header_len..], b"ell");
+
+        // The reply is shortened at the end of the file
+        assert_eq!(
+            send_from_fd(device.write_fd, 0xdeadbeef, fd, 1, 10, 0).unwrap(),
+            Some(4)
+        );
+        let mut reply = Vec::new();
+        device.read(&mut reply, header_len + 4).unwrap();
+        assert_eq!(reply[0..4], (header_len as u32 + 4).to_ne_bytes());
+        assert_eq!(&reply[header_len..], b"ello");
+
+        // The file offset isn't changed
+        let mut data = String::new();
+        file.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hello");
+    }
+}
@@ -0,0 +1,225 @@
+//! Session statistics
+//!
+//! A session counts the requests it receives per operation, along with the errors they were
+//! replied to with, the amount of data read and written and the time it took to reply to them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::errno::Errno;
+use crate::fuse_abi::fuse_opcode;
+
+/// Number of buckets of a latency histogram
+const LATENCY_BUCKETS: usize = 24;
+
+/// Histogram of the time from receiving requests to sending their replies. Bucket i counts
+/// latencies below 2^i microseconds (and at least 2^(i-1) microseconds), the last bucket counts
+/// all latencies that are longer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS],
+    total: Duration,
+}
+
+impl LatencyHistogram {
+    /// Add a latency to the histogram
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros();
+        let bucket = (0..LATENCY_BUCKETS - 1)
+            .find(|&i| micros < 1 << i)
+            .unwrap_or(LATENCY_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.total += latency;
+    }
+
+    /// Returns the number of recorded latencies
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the sum of all recorded latencies
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Returns the mean latency, or None if no latencies were recorded
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                (self.total.as_nanos() / count as u128) as u64,
+            )),
+        }
+    }
+
+    /// Returns the upper bound (exclusive) and the count of every bucket. The upper bound of
+    /// the last bucket is None.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.buckets.iter().enumerate().map(|(i, &count)| {
+            let bound = if i < LATENCY_BUCKETS - 1 {
+                Some(Duration::from_micros(1 << i))
+            } else {
+                None
+            };
+            (bound, count)
+        })
+    }
+
+    /// Returns an upper bound of the given quantile (between 0 and 1) of the recorded
+    /// latencies, e.g. 0.99 for the 99th percentile. Returns None if no latencies were
+    /// recorded or if the quantile falls into the last bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, bucket_count) in self.buckets() {
+            seen += bucket_count;
+            if seen >= rank {
+                return bound;
+            }
+        }
+        None
+    }
+}
+
+/// Statistics of one kind of operation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperationStats {
+    /// Number of requests received
+    pub requests: u64,
+    /// Number of error replies per error
+    pub errors: BTreeMap<Errno, u64>,
+    /// Bytes of data sent in successful replies to read requests
+    pub bytes_read: u64,
+    /// Bytes of data received in write requests
+    pub bytes_written: u64,
+    /// Time from receiving requests to sending their replies. Operations without a reply
+    /// (e.g. forget) aren't recorded.
+    pub latency: LatencyHistogram,
+}
+
+/// Handle to the statistics of a session. It's cheap to clone and can be sent to other
+/// threads, e.g. to regularly export the statistics to a monitoring system while the session
+/// is running.
+#[derive(Clone, Debug, Default)]
+pub struct SessionStats {
+    operations: Arc<Mutex<HashMap<u32, OperationStats>>>,
+    invalid_requests: Arc<AtomicU64>,
+}
+
+impl SessionStats {
+    /// Returns a snapshot of the statistics of every operation that was received at least
+    /// once, by name of the operation (e.g. "LOOKUP" or "WRITE")
+    pub fn snapshot(&self) -> BTreeMap<String, OperationStats> {
+        let operations = self.operations.lock().unwrap();
+        operations
+            .iter()
+            .map(|(&opcode, stats)| (opcode_name(opcode), stats.clone()))
+            .collect()
+    }
+
+    /// Returns the number of requests that couldn't be parsed, e.g. because their operation is
+    /// unknown to this version of the library. Such requests are answered with an error and
+    /// also counted in the statistics of their opcode (see `snapshot`).
+    pub fn invalid_requests(&self) -> u64 {
+        self.invalid_requests.load(Ordering::Relaxed)
+    }
+
+    /// Record a request that couldn't be parsed
+    pub(crate) fn invalid(&self) {
+        self.invalid_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a received request with the given number of bytes written
+    pub(crate) fn received(&self, opcode: u32, bytes_written: usize) {
+        let mut operations = self.operations.lock().unwrap();
+        let stats = operations.entry(opcode).or_default();
+        stats.requests += 1;
+        stats.bytes_written += bytes_written as u64;
+    }
+
+    /// Record a reply with the given error (0 or a negative errno as sent to the kernel) and
+    /// number of bytes read
+    pub(crate) fn replied(&self, opcode: u32, error: i32, bytes_read: usize, latency: Duration) {
+        let mut operations = self.operations.lock().unwrap();
+        let stats = operations.entry(opcode).or_default();
+        match Errno::new(-error) {
+            Some(errno) => *stats.errors.entry(errno).or_default() += 1,
+            None => stats.bytes_read += bytes_read as u64,
+        }
+        stats.latency.record(latency);
+    }
+}
+
+/// Returns the name of the given opcode without the FUSE_ prefix
+pub(crate) fn opcode_name(opcode: u32) -> String {
+    match fuse_opcode::try_from(opcode) {
+        Ok(opcode) => {
+            let name = format!("{:?}", opcode);
+            name.trim_start_matches("FUSE_").to_string()
+        }
+        Err(_) => format!("UNKNOWN({})", opcode),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LatencyHistogram, SessionStats};
+    use crate::errno::Errno;
+    use crate::fuse_abi::fuse_opcode;
+    use std::time::Duration;
+
+    #[test]
+    fn latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.quantile(0.5), None);
+        histogram.record(Duration::from_micros(0));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_micros(5));
+        histogram.record(Duration::from_secs(100));
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(25_000_002)));
+        assert_eq!(histogram.quantile(0.25), Some(Duration::from_micros(1)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(4)));
+        assert_eq!(histogram.quantile(0.75), Some(Duration::from_micros(8)));
+        assert_eq!(histogram.quantile(1.0), None);
+        let buckets: Vec<_> = histogram.buckets().filter(|&(_, n)| n > 0).collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (Some(Duration::from_micros(1)), 1),
+                (Some(Duration::from_micros(4)), 1),
+                (Some(Duration::from_micros(8)), 1),
+                (None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        let stats = SessionStats::default();
+        let read = fuse_opcode::FUSE_READ as u32;
+        let write = fuse_opcode::FUSE_WRITE as u32;
+        stats.received(read, 0);
+        stats.received(read, 0);
+        stats.received(write, 4096);
+        stats.replied(read, 0, 100, Duration::from_micros(10));
+        stats.replied(read, -libc::EIO, 0, Duration::from_micros(10));
+        stats.replied(write, 0, 0, Duration::from_micros(10));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["READ"].requests, 2);
+        assert_eq!(snapshot["READ"].bytes_read, 100);
+        assert_eq!(snapshot["READ"].errors[&Errno::EIO], 1);
+        assert_eq!(snapshot["READ"].latency.count(), 2);
+        assert_eq!(snapshot["WRITE"].bytes_written, 4096);
+        assert!(snapshot["WRITE"].errors.is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+//! Helpers shared by the unit tests
+
+use libc::{c_int, c_void};
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+
+use crate::channel::Channel;
+use crate::fuse_abi::fuse_out_header;
+#[cfg(not(feature = "libfuse"))]
+use crate::{Filesystem, Session};
+
+/// A socket standing in for the kernel driver, which receives everything sent through the
+/// channel it was created with
+#[derive(Debug)]
+pub(crate) struct FakeDriver {
+    fd: c_int,
+}
+
+impl FakeDriver {
+    /// Returns all messages sent to the driver that weren't taken yet
+    pub(crate) fn take_sent(&self) -> Vec<Vec<u8>> {
+        let mut sent = Vec::new();
+        let mut data = vec![0u8; 4096];
+        loop {
+            let rc = unsafe {
+                libc::recv(
+                    self.fd,
+                    data.as_mut_ptr() as *mut c_void,
+                    data.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if rc < 0 {
+                return sent;
+            }
+            sent.push(data[..rc as usize].to_vec());
+        }
+    }
+}
+
+impl Drop for FakeDriver {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Returns a channel that communicates with the returned fake driver instead of the kernel
+pub(crate) fn channel(name: &str) -> (Channel, FakeDriver) {
+    let mut fds = [0; 2];
+    let rc = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+    assert_eq!(rc, 0);
+    // The mountpoint doesn't exist, so that dropping the channel doesn't unmount anything
+    let mountpoint = std::env::temp_dir().join(format!("fuser-{}-not-mounted", name));
+    (
+        Channel::from_fd(&mountpoint, fds[1]),
+        FakeDriver { fd: fds[0] },
+    )
+}
+
+/// Returns the header of the given message sent to the driver
+pub(crate) fn out_header(data: &[u8]) -> fuse_out_header {
+    assert!(data.len() >= mem::size_of::<fuse_out_header>());
+    unsafe { (data.as_ptr() as *const fuse_out_header).read_unaligned() }
+}
+
+/// Returns a path in the temporary directory that is unique to the given name and this process
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("fuser-{}-{}", name, std::process::id()))
+}
+
+/// Returns a new empty directory at `temp_path(name)`
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let path = temp_path(name);
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir(&path).unwrap();
+    path
+}
+
+/// Filesystem that answers all requests with the default implementations of `Filesystem`
+#[cfg(not(feature = "libfuse"))]
+#[derive(Debug)]
+pub(crate) struct NullFs;
+
+#[cfg(not(feature = "libfuse"))]
+impl Filesystem for NullFs {}
+
+/// Mount a `NullFs` at a new directory `temp_path(name)`. Returns None if mounting isn't
+/// possible, e.g. without permission to mount FUSE filesystems, so that tests which need a
+/// mounted filesystem can be skipped.
+#[cfg(not(feature = "libfuse"))]
+pub(crate) fn mount(name: &str) -> Option<Session<NullFs>> {
+    let mountpoint = temp_dir(name);
+    match Session::new2(NullFs, &mountpoint, &[]) {
+        Ok(se) => Some(se),
+        Err(_) => {
+            let _ = fs::remove_dir(&mountpoint);
+            None
+        }
+    }
+}
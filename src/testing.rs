@@ -0,0 +1,727 @@
+//! In-process testing of filesystems
+//!
+//! `FakeKernel` drives a filesystem like the kernel driver would, but without mounting it: it
+//! builds raw requests, dispatches them to the filesystem the same way a session does and
+//! decodes the replies into typed structs. This allows unit testing a filesystem with plain
+//! `cargo test` on systems without FUSE.
+//!
+//! ```
+//! use fuser::testing::FakeKernel;
+//! use fuser::{Errno, Filesystem, FUSE_ROOT_ID};
+//!
+//! struct EmptyFs;
+//!
+//! impl Filesystem for EmptyFs {}
+//!
+//! let mut kernel = FakeKernel::new(EmptyFs);
+//! kernel.init(0).unwrap();
+//! assert_eq!(kernel.lookup(FUSE_ROOT_ID, "hello").unwrap_err(), Errno::ENOSYS);
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::capture::Recorder;
+use crate::channel::ChannelSender;
+use crate::fuse_abi::*;
+use crate::notify::Notifier;
+use crate::reply::{as_bytes, kind_from_mode};
+use crate::request::{InterruptTracker, Request};
+use crate::stats::SessionStats;
+use crate::{Errno, FileAttr, FileType, Filesystem};
+
+/// Reply to a lookup or to a request that created an inode
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Inode number
+    pub ino: u64,
+    /// Generation number of the inode
+    pub generation: u64,
+    /// Attributes of the inode
+    pub attr: FileAttr,
+    /// Time the kernel may cache the name lookup
+    pub entry_ttl: Duration,
+    /// Time the kernel may cache the attributes
+    pub attr_ttl: Duration,
+}
+
+/// Reply to a getattr request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attr {
+    /// Attributes of the inode
+    pub attr: FileAttr,
+    /// Time the kernel may cache the attributes
+    pub ttl: Duration,
+}
+
+/// Reply to an open or opendir request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Open {
+    /// File handle
+    pub fh: u64,
+    /// Open flags (FOPEN_* flags from `consts`)
+    pub flags: u32,
+}
+
+/// Entry of a readdir reply
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Inode number
+    pub ino: u64,
+    /// Offset of the next entry
+    pub offset: i64,
+    /// Kind of file
+    pub kind: FileType,
+    /// File name
+    pub name: OsString,
+}
+
+/// A fake kernel driver that sends requests to a filesystem and decodes its replies, without
+/// mounting it. Requests are dispatched one after another, like in `Session::run`, but
+/// replies may be sent from other threads. Every request method waits for the reply and
+/// panics if the filesystem doesn't reply in time (see `set_timeout`).
+#[derive(Debug)]
+pub struct FakeKernel<FS: Filesystem> {
+    filesystem: FS,
+    sender: ChannelSender,
+    recorder: Recorder,
+    interrupts: Arc<InterruptTracker>,
+    stats: SessionStats,
+    notifier: Notifier,
+    initialized: bool,
+    destroyed: AtomicBool,
+    next_unique: u64,
+    replies: HashMap<u64, Vec<u8>>,
+    timeout: Duration,
+    uid: u32,
+    gid: u32,
+}
+
+impl<FS: Filesystem> FakeKernel<FS> {
+    /// Create a fake kernel driver for the given filesystem. Like a real kernel driver, it
+    /// must be initialized with `init` before sending other requests. Requests are sent with
+    /// the user and group of the current process.
+    pub fn new(filesystem: FS) -> FakeKernel<FS> {
+        // Replies are collected by a recorder instead of being sent to the kernel
+        let recorder = Recorder::memory();
+        let sender = ChannelSender::recording(recorder.clone());
+        FakeKernel {
+            filesystem,
+            notifier: Notifier::new(sender.clone()),
+            sender,
+            recorder,
+            interrupts: Arc::new(InterruptTracker::default()),
+            stats: SessionStats::default(),
+            initialized: false,
+            destroyed: AtomicBool::new(false),
+            next_unique: 1,
+            replies: HashMap::new(),
+            timeout: Duration::from_secs(10),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    /// Returns the filesystem, e.g. to inspect its state
+    pub fn filesystem(&self) -> &FS {
+        &self.filesystem
+    }
+
+    /// Returns a handle to the statistics of the requests sent to the filesystem
+    pub fn stats(&self) -> SessionStats {
+        self.stats.clone()
+    }
+
+    /// Set the time to wait for a reply (10 seconds by default)
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Set the user and group the following requests are sent with
+    pub fn set_credentials(&mut self, uid: u32, gid: u32) {
+        self.uid = uid;
+        self.gid = gid;
+    }
+
+    /// Initialize the filesystem, offering the given capabilities (FUSE_* flags from
+    /// `consts`). Returns the capabilities requested by the filesystem.
+    pub fn init(&mut self, capabilities: u32) -> Result<u32, Errno> {
+        let mut arg: fuse_init_in = unsafe { mem::zeroed() };
+        arg.major = FUSE_KERNEL_VERSION;
+        arg.minor = FUSE_KERNEL_MINOR_VERSION;
+        arg.max_readahead = 128 * 1024;
+        arg.flags = capabilities;
+        let reply = self.request(fuse_opcode::FUSE_INIT as u32, 0, &struct_bytes(&arg))?;
+        let init: fuse_init_out = decode(&reply);
+        Ok(init.flags)
+    }
+
+    /// Destroy the filesystem, as done by the kernel driver when unmounting
+    pub fn destroy(&mut self) {
+        // Destroy is always replied to successfully
+        let _ = self.request(fuse_opcode::FUSE_DESTROY as u32, 0, &[]);
+    }
+
+    /// Look up a directory entry by name
+    pub fn lookup<T: AsRef<OsStr>>(&mut self, parent: u64, name: T) -> Result<Entry, Errno> {
+        let reply = self.request(
+            fuse_opcode::FUSE_LOOKUP as u32,
+            parent,
+            &name_bytes(name.as_ref()),
+        )?;
+        Ok(entry(&reply))
+    }
+
+    /// Forget about an inode (no reply)
+    pub fn forget(&mut self, ino: u64, nlookup: u64) {
+        let arg = fuse_forget_in { nlookup };
+        self.send(fuse_opcode::FUSE_FORGET as u32, ino, &struct_bytes(&arg));
+    }
+
+    /// Get the attributes of an inode
+    pub fn getattr(&mut self, ino: u64) -> Result<Attr, Errno> {
+        let arg = struct_bytes(&fuse_getattr_in {
+            getattr_flags: 0,
+            dummy: 0,
+            fh: 0,
+        });
+        let reply = self.request(fuse_opcode::FUSE_GETATTR as u32, ino, &arg)?;
+        let out: fuse_attr_out = decode(&reply);
+        Ok(Attr {
+            attr: file_attr(&out.attr),
+            ttl: Duration::new(out.attr_valid, out.attr_valid_nsec),
+        })
+    }
+
+    /// Create a directory
+    pub fn mkdir<T: AsRef<OsStr>>(
+        &mut self,
+        parent: u64,
+        name: T,
+        mode: u32,
+    ) -> Result<Entry, Errno> {
+        let mut arg: fuse_mkdir_in = unsafe { mem::zeroed() };
+        arg.mode = mode;
+        let mut data = struct_bytes(&arg);
+        data.extend(name_bytes(name.as_ref()));
+        let reply = self.request(fuse_opcode::FUSE_MKDIR as u32, parent, &data)?;
+        Ok(entry(&reply))
+    }
+
+    /// Remove a file
+    pub fn unlink<T: AsRef<OsStr>>(&mut self, parent: u64, name: T) -> Result<(), Errno> {
+        self.request(
+            fuse_opcode::FUSE_UNLINK as u32,
+            parent,
+            &name_bytes(name.as_ref()),
+        )?;
+        Ok(())
+    }
+
+    /// Open a file with the given open flags (O_* flags from libc)
+    pub fn open(&mut self, ino: u64, flags: u32) -> Result<Open, Errno> {
+        let arg = fuse_open_in { flags, unused: 0 };
+        let reply = self.request(fuse_opcode::FUSE_OPEN as u32, ino, &struct_bytes(&arg))?;
+        Ok(open(&reply))
+    }
+
+    /// Read up to the given number of bytes from an open file
+    pub fn read(&mut self, ino: u64, fh: u64, offset: i64, size: u32) -> Result<Vec<u8>, Errno> {
+        let arg = read_in(fh, offset, size);
+        self.request(fuse_opcode::FUSE_READ as u32, ino, &struct_bytes(&arg))
+    }
+
+    /// Write data to an open file. Returns the number of bytes written.
+    pub fn write(&mut self, ino: u64, fh: u64, offset: i64, data: &[u8]) -> Result<u32, Errno> {
+        let mut arg: fuse_write_in = unsafe { mem::zeroed() };
+        arg.fh = fh;
+        arg.offset = offset as u64;
+        arg.size = data.len() as u32;
+        let mut request = struct_bytes(&arg);
+        request.extend_from_slice(data);
+        let reply = self.request(fuse_opcode::FUSE_WRITE as u32, ino, &request)?;
+        let out: fuse_write_out = decode(&reply);
+        Ok(out.size)
+    }
+
+    /// Release an open file
+    pub fn release(&mut self, ino: u64, fh: u64) -> Result<(), Errno> {
+        let arg = release_in(fh);
+        self.request(fuse_opcode::FUSE_RELEASE as u32, ino, &struct_bytes(&arg))?;
+        Ok(())
+    }
+
+    /// Open a directory
+    pub fn opendir(&mut self, ino: u64) -> Result<Open, Errno> {
+        let arg = fuse_open_in {
+            flags: libc::O_RDONLY as u32,
+            unused: 0,
+        };
+        let reply = self.request(fuse_opcode::FUSE_OPENDIR as u32, ino, &struct_bytes(&arg))?;
+        Ok(open(&reply))
+    }
+
+    /// Read the entries of an open directory, starting at the given offset and returning up
+    /// to the given size in bytes (as the kernel driver would request it)
+    pub fn readdir(
+        &mut self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+    ) -> Result<Vec<DirEntry>, Errno> {
+        let arg = read_in(fh, offset, size);
+        let reply = self.request(fuse_opcode::FUSE_READDIR as u32, ino, &struct_bytes(&arg))?;
+        Ok(dir_entries(&reply))
+    }
+
+    /// Release an open directory
+    pub fn releasedir(&mut self, ino: u64, fh: u64) -> Result<(), Errno> {
+        let arg = release_in(fh);
+        self.request(
+            fuse_opcode::FUSE_RELEASEDIR as u32,
+            ino,
+            &struct_bytes(&arg),
+        )?;
+        Ok(())
+    }
+
+    /// Send a request with the given opcode (as defined by the FUSE protocol), inode and raw
+    /// arguments and wait for its reply. Returns the data of the reply (without header).
+    pub fn request(&mut self, opcode: u32, ino: u64, arg: &[u8]) -> Result<Vec<u8>, Errno> {
+        let unique = self.send(opcode, ino, arg);
+        self.wait(unique)
+    }
+
+    /// Send a request to the filesystem and return its unique id
+    fn send(&mut self, opcode: u32, ino: u64, arg: &[u8]) -> u64 {
+        let unique = self.next_unique;
+        self.next_unique += 1;
+        let header = fuse_in_header {
+            len: (mem::size_of::<fuse_in_header>() + arg.len()) as u32,
+            opcode,
+            unique,
+            nodeid: ino,
+            uid: self.uid,
+            gid: self.gid,
+            pid: std::process::id(),
+            padding: 0,
+        };
+        let mut data = struct_bytes(&header);
+        data.extend_from_slice(arg);
+        let req = Request::new(self.sender.clone(), &data, &self.interrupts, &self.stats)
+            .expect("Corrupt FUSE request");
+        // Invalid requests are replied to with an error already
+        if let Some(req) = req {
+            req.dispatch_standalone(
+                &self.filesystem,
+                &mut self.initialized,
+                &self.destroyed,
+                &self.notifier,
+            );
+        }
+        unique
+    }
+
+    /// Wait for the reply to the request with the given unique id
+    fn wait(&mut self, unique: u64) -> Result<Vec<u8>, Errno> {
+        let deadline = Instant::now() + self.timeout;
+        let reply = loop {
+            for record in self.recorder.take_records() {
+                let header: fuse_out_header = decode(&record.data);
+                self.replies.insert(header.unique, record.data);
+            }
+            if let Some(reply) = self.replies.remove(&unique) {
+                break reply;
+            }
+            if Instant::now() > deadline {
+                panic!(
+                    "No reply to FUSE request {} within {:?}",
+                    unique, self.timeout
+                );
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let header: fuse_out_header = decode(&reply);
+        match Errno::new(-header.error) {
+            Some(err) => Err(err),
+            None => Ok(reply[mem::size_of::<fuse_out_header>()..].to_vec()),
+        }
+    }
+}
+
+/// Returns the bytes of the given fuse_* struct
+fn struct_bytes<T>(data: &T) -> Vec<u8> {
+    as_bytes(data, |bytes| bytes.concat())
+}
+
+/// Returns the bytes of the given name with zero-termination
+fn name_bytes(name: &OsStr) -> Vec<u8> {
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Decode a fuse_* struct from the start of the given data. Panics if the data is too short.
+fn decode<T>(data: &[u8]) -> T {
+    assert!(
+        data.len() >= mem::size_of::<T>(),
+        "FUSE reply too short: {} bytes",
+        data.len()
+    );
+    unsafe { ptr::read_unaligned(data.as_ptr() as *const T) }
+}
+
+fn read_in(fh: u64, offset: i64, size: u32) -> fuse_read_in {
+    let mut arg: fuse_read_in = unsafe { mem::zeroed() };
+    arg.fh = fh;
+    arg.offset = offset as u64;
+    arg.size = size;
+    arg
+}
+
+fn release_in(fh: u64) -> fuse_release_in {
+    fuse_release_in {
+        fh,
+        flags: 0,
+        release_flags: 0,
+        lock_owner: 0,
+    }
+}
+
+fn entry(data: &[u8]) -> Entry {
+    let out: fuse_entry_out = decode(data);
+    Entry {
+        ino: out.nodeid,
+        generation: out.generation,
+        attr: file_attr(&out.attr),
+        entry_ttl: Duration::new(out.entry_valid, out.entry_valid_nsec),
+        attr_ttl: Duration::new(out.attr_valid, out.attr_valid_nsec),
+    }
+}
+
+fn open(data: &[u8]) -> Open {
+    let out: fuse_open_out = decode(data);
+    Open {
+        fh: out.fh,
+        flags: out.open_flags,
+    }
+}
+
+fn dir_entries(mut data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    while !data.is_empty() {
+        let dirent: fuse_dirent = decode(data);
+        let start = mem::size_of::<fuse_dirent>();
+        let end = start + dirent.namelen as usize;
+        let name = OsString::from_vec(data[start..end].to_vec());
+        entries.push(DirEntry {
+            ino: dirent.ino,
+            offset: dirent.off as i64,
+            kind: kind_from_mode(dirent.typ << 12),
+            name,
+        });
+        // Entries are 64bit aligned
+        let entsize = (end + mem::size_of::<u64>() - 1) & !(mem::size_of::<u64>() - 1);
+        data = &data[entsize.min(data.len())..];
+    }
+    entries
+}
+
+fn system_time(secs: u64, nsecs: u32) -> SystemTime {
+    UNIX_EPOCH + Duration::new(secs, nsecs)
+}
+
+/// Returns the FileAttr of a fuse_attr
+fn file_attr(attr: &fuse_attr) -> FileAttr {
+    FileAttr {
+        ino: attr.ino,
+        size: attr.size,
+        blocks: attr.blocks,
+        atime: system_time(attr.atime, attr.atimensec),
+        mtime: system_time(attr.mtime, attr.mtimensec),
+        ctime: system_time(attr.ctime, attr.ctimensec),
+        #[cfg(target_os = "macos")]
+        crtime: system_time(attr.crtime, attr.crtimensec),
+        #[cfg(not(target_os = "macos"))]
+        crtime: UNIX_EPOCH,
+        kind: kind_from_mode(attr.mode),
+        perm: (attr.mode & 0o7777) as u16,
+        nlink: attr.nlink,
+        uid: attr.uid,
+        gid: attr.gid,
+        rdev: attr.rdev,
+        blksize: attr.blksize,
+        padding: attr.padding,
+        #[cfg(target_os = "macos")]
+        flags: attr.flags,
+        #[cfg(not(target_os = "macos"))]
+        flags: 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{struct_bytes, DirEntry, FakeKernel};
+    use crate::fuse_abi::*;
+    use crate::reply::{ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen, ReplyWrite};
+    use crate::request::{Request, WriteFlags};
+    use crate::{Errno, FileAttr, FileType, Filesystem, KernelConfig, FUSE_ROOT_ID};
+    use std::ffi::OsStr;
+    use std::sync::Mutex;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Filesystem with a single file in its root directory
+    struct TestFs {
+        data: Mutex<Vec<u8>>,
+    }
+
+    impl TestFs {
+        fn attr(&self, ino: u64) -> FileAttr {
+            let (kind, size) = match ino {
+                FUSE_ROOT_ID => (FileType::Directory, 0),
+                _ => (
+                    FileType::RegularFile,
+                    self.data.lock().unwrap().len() as u64,
+                ),
+            };
+            FileAttr {
+                ino,
+                size,
+                blocks: 0,
+                atime: UNIX_EPOCH,
+                mtime: UNIX_EPOCH,
+                ctime: UNIX_EPOCH,
+                crtime: UNIX_EPOCH,
+                kind,
+                perm: 0o644,
+                nlink: 1,
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                blksize: 0,
+                padding: 0,
+                flags: 0,
+            }
+        }
+    }
+
+    impl Filesystem for TestFs {
+        fn init(&self, _req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), Errno> {
+            Ok(())
+        }
+
+        fn lookup(&self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+            if parent == FUSE_ROOT_ID && name == "hello" {
+                reply.entry(&Duration::from_secs(1), &self.attr(2), 0);
+            } else {
+                reply.error(Errno::ENOENT);
+            }
+        }
+
+        fn getattr(&self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+            reply.attr(&Duration::from_secs(1), &self.attr(ino));
+        }
+
+        fn open(&self, _req: &Request<'_>, _ino: u64, _flags: u32, reply: ReplyOpen) {
+            // Reply from another thread
+            std::thread::spawn(move || reply.opened(7, 0));
+        }
+
+        fn read(
+            &self,
+            _req: &Request<'_>,
+            _ino: u64,
+            _fh: u64,
+            offset: i64,
+            size: u32,
+            reply: ReplyData,
+        ) {
+            let data = self.data.lock().unwrap();
+            let start = (offset as usize).min(data.len());
+            let end = (start + size as usize).min(data.len());
+            reply.data(&data[start..end]);
+        }
+
+        fn write(
+            &self,
+            _req: &Request<'_>,
+            _ino: u64,
+            _fh: u64,
+            offset: i64,
+            data: &[u8],
+            _write_flags: WriteFlags,
+            _flags: u32,
+            _lock_owner: Option<u64>,
+            reply: ReplyWrite,
+        ) {
+            let mut file = self.data.lock().unwrap();
+            let end = offset as usize + data.len();
+            if file.len() < end {
+                file.resize(end, 0);
+            }
+            file[offset as usize..end].copy_from_slice(data);
+            reply.written(data.len() as u32);
+        }
+
+        fn readdir(
+            &self,
+            _req: &Request<'_>,
+            _ino: u64,
+            _fh: u64,
+            offset: i64,
+            mut reply: ReplyDirectory,
+        ) {
+            let entries = [
+                (FUSE_ROOT_ID, FileType::Directory, "."),
+                (FUSE_ROOT_ID, FileType::Directory, ".."),
+                (2, FileType::RegularFile, "hello"),
+            ];
+            for (i, (ino, kind, name)) in entries.iter().enumerate().skip(offset as usize) {
+                if reply.add(*ino, (i + 1) as i64, *kind, name) {
+                    break;
+                }
+            }
+            reply.ok();
+        }
+    }
+
+    fn kernel() -> FakeKernel<TestFs> {
+        let mut kernel = FakeKernel::new(TestFs {
+            data: Mutex::new(b"Hello World!".to_vec()),
+        });
+        kernel.init(0).unwrap();
+        kernel
+    }
+
+    #[test]
+    fn before_init() {
+        let mut kernel = FakeKernel::new(TestFs {
+            data: Mutex::new(Vec::new()),
+        });
+        assert_eq!(kernel.getattr(FUSE_ROOT_ID).unwrap_err(), Errno::EIO);
+    }
+
+    #[test]
+    fn lookup() {
+        let mut kernel = kernel();
+        let entry = kernel.lookup(FUSE_ROOT_ID, "hello").unwrap();
+        assert_eq!(entry.ino, 2);
+        assert_eq!(entry.attr.kind, FileType::RegularFile);
+        assert_eq!(entry.attr.perm, 0o644);
+        assert_eq!(entry.attr.size, 12);
+        assert_eq!(entry.entry_ttl, Duration::from_secs(1));
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "world"), Err(Errno::ENOENT));
+        let attr = kernel.getattr(FUSE_ROOT_ID).unwrap();
+        assert_eq!(attr.attr.kind, FileType::Directory);
+    }
+
+    #[test]
+    fn read_write() {
+        let mut kernel = kernel();
+        let open = kernel.open(2, libc::O_RDWR as u32).unwrap();
+        assert_eq!(open.fh, 7);
+        assert_eq!(kernel.write(2, open.fh, 6, b"FUSE!!").unwrap(), 6);
+        assert_eq!(kernel.read(2, open.fh, 0, 4096).unwrap(), b"Hello FUSE!!");
+        assert_eq!(kernel.read(2, open.fh, 6, 4).unwrap(), b"FUSE");
+        kernel.release(2, open.fh).unwrap();
+        let snapshot = kernel.stats().snapshot();
+        assert_eq!(snapshot["WRITE"].bytes_written, 6);
+        assert_eq!(snapshot["READ"].bytes_read, 16);
+        // Replies of other operations contain no data read
+        assert_eq!(snapshot["OPEN"].bytes_read, 0);
+    }
+
+    #[test]
+    fn readdir() {
+        let mut kernel = kernel();
+        let dir = kernel.opendir(FUSE_ROOT_ID).unwrap();
+        let entries = kernel.readdir(FUSE_ROOT_ID, dir.fh, 0, 4096).unwrap();
+        let names: Vec<_> = entries.iter().map(|e| e.name.to_str().unwrap()).collect();
+        assert_eq!(names, [".", "..", "hello"]);
+        assert_eq!(
+            entries[2],
+            DirEntry {
+                ino: 2,
+                offset: 3,
+                kind: FileType::RegularFile,
+                name: "hello".into(),
+            }
+        );
+        // Continue after the first entry with a buffer that only fits one entry
+        let entries = kernel.readdir(FUSE_ROOT_ID, dir.fh, 1, 32).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "..");
+        kernel.releasedir(FUSE_ROOT_ID, dir.fh).unwrap();
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let mut kernel = kernel();
+        // Unknown operation, e.g. of a newer kernel
+        assert_eq!(kernel.request(999, FUSE_ROOT_ID, &[]), Err(Errno::ENOSYS));
+        // Read request without arguments
+        assert_eq!(kernel.request(15, 2, &[]), Err(Errno::EIO));
+        // The session still works
+        assert_eq!(kernel.lookup(FUSE_ROOT_ID, "hello").unwrap().ino, 2);
+        let stats = kernel.stats();
+        assert_eq!(stats.invalid_requests(), 2);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot["UNKNOWN(999)"].errors[&Errno::ENOSYS], 1);
+        assert_eq!(snapshot["READ"].errors[&Errno::EIO], 1);
+    }
+
+    #[test]
+    fn filesystem_panic() {
+        struct PanicFs;
+
+        impl Filesystem for PanicFs {
+            fn getattr(&self, _req: &Request<'_>, _ino: u64, _reply: ReplyAttr) {
+                panic!("getattr failed");
+            }
+        }
+
+        let mut kernel = FakeKernel::new(PanicFs);
+        kernel.init(0).unwrap();
+        // The request is replied to with EIO and the session keeps serving
+        assert_eq!(kernel.getattr(FUSE_ROOT_ID).unwrap_err(), Errno::EIO);
+        assert_eq!(kernel.getattr(FUSE_ROOT_ID).unwrap_err(), Errno::EIO);
+        assert_eq!(
+            kernel.lookup(FUSE_ROOT_ID, "hello").unwrap_err(),
+            Errno::ENOSYS
+        );
+    }
+
+    #[test]
+    fn compat_protocol() {
+        let mut kernel = FakeKernel::new(TestFs {
+            data: Mutex::new(b"Hello World!".to_vec()),
+        });
+        // Kernels before ABI 7.36 send shorter init arguments and kernels before ABI 7.23
+        // expect a shorter init reply
+        let mut arg: fuse_init_in = unsafe { std::mem::zeroed() };
+        arg.major = 7;
+        arg.minor = 8;
+        arg.max_readahead = 4096;
+        let init = &struct_bytes(&arg)[..FUSE_COMPAT_INIT_IN_SIZE];
+        let reply = kernel.request(fuse_opcode::FUSE_INIT as u32, 0, init);
+        assert_eq!(reply.unwrap().len(), FUSE_COMPAT_22_INIT_OUT_SIZE);
+        // Before ABI 7.9, getattr has no arguments and attributes have no block size
+        let reply = kernel.request(fuse_opcode::FUSE_GETATTR as u32, FUSE_ROOT_ID, &[]);
+        assert_eq!(reply.unwrap().len(), FUSE_COMPAT_ATTR_OUT_SIZE);
+        let reply = kernel.request(fuse_opcode::FUSE_LOOKUP as u32, FUSE_ROOT_ID, b"hello\0");
+        assert_eq!(reply.unwrap().len(), FUSE_COMPAT_ENTRY_OUT_SIZE);
+        // Reads have no lock owner and flags before ABI 7.9
+        let mut arg: fuse_read_in = unsafe { std::mem::zeroed() };
+        arg.size = 5;
+        let read = &struct_bytes(&arg)[..FUSE_COMPAT_READ_IN_SIZE];
+        let reply = kernel.request(fuse_opcode::FUSE_READ as u32, 2, read);
+        assert_eq!(reply.unwrap(), b"Hello");
+    }
+}